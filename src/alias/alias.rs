@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use log::*;
+
+use super::{Alias, AliasReport, Evidence};
+use crate::TracerouteError;
+use crate::prelude::TTL;
+use crate::trace::{TraceOptions, TraceResponse, next_rounds};
+use crate::traceroute::Traceroute;
+
+// Probes are sent straight at an interface with the furthest distance traces may go
+const DIRECT_TTL: TTL = 32;
+
+/// Configuration of [`AliasResolver`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AliasOptions {
+    /// Interfaces are only tested against each other when seen at most this many hops apart
+    pub max_distance: TTL,
+    /// Probes sent to each interface of a pair when comparing their IP ids
+    pub samples: u8,
+    /// Largest step between consecutive IP ids still taken as the same counter
+    pub max_id_gap: u16,
+    /// Aliases less sure than this are reported but don't merge interfaces into routers
+    pub min_confidence: f64,
+}
+
+impl Default for AliasOptions {
+    fn default() -> Self {
+        Self {
+            max_distance: 1,
+            samples: 3,
+            max_id_gap: 1000,
+            min_confidence: 0.5,
+        }
+    }
+}
+
+// A reply to a probe sent straight at an interface
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    // Address the reply came from, not always the one probed
+    source: IpAddr,
+    ip_id: u16,
+    ttl: TTL,
+}
+
+/// Alias resolution merging the interfaces found by traces into routers
+///
+/// Each interface is probed directly with a probe to an unused port. A reply coming from another
+/// address gives that address away as an alias, as Mercator does. Interfaces seen near each other
+/// are then probed in turn: replies with IP ids climbing as though drawn from a single counter
+/// point to a router owning both, as Ally does. Replies arriving with the same TTL back the other
+/// techniques up while differing TTLs rule a pair out.
+#[derive(Debug, Default)]
+pub struct AliasResolver {
+    options: AliasOptions,
+    probes_sent: usize,
+}
+
+impl AliasResolver {
+    pub fn new(options: AliasOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Probes sent over every resolution
+    pub fn probes_sent(&self) -> usize {
+        self.probes_sent
+    }
+
+    /// Resolve aliases among `interfaces`, each paired with the closest distance it was seen at
+    ///
+    /// Probes use the protocol and timeout of `options`, each leaving from the source the routing
+    /// table picks for the interface probed.
+    pub async fn resolve(
+        &mut self,
+        traceroute: &Traceroute,
+        interfaces: &[(TTL, IpAddr)],
+        options: TraceOptions,
+    ) -> Result<AliasReport, TracerouteError> {
+        let mut aliases: Vec<Alias> = Vec::new();
+        let mut samples: HashMap<IpAddr, Sample> = HashMap::new();
+
+        for (_ttl, interface) in interfaces {
+            let sample = match self.probe(traceroute, *interface, options).await? {
+                Some(sample) => sample,
+                None => continue,
+            };
+            debug!(
+                "{} replied from {} with id {} and ttl {}",
+                interface, sample.source, sample.ip_id, sample.ttl
+            );
+            if sample.source != *interface {
+                debug!("{} answered for {}", sample.source, interface);
+                add_evidence(
+                    &mut aliases,
+                    *interface,
+                    sample.source,
+                    Evidence::CommonSource,
+                );
+            }
+            let _ = samples.insert(*interface, sample);
+        }
+
+        for (index, (ttl_a, a)) in interfaces.iter().enumerate() {
+            for (ttl_b, b) in &interfaces[index + 1..] {
+                if a == b || ttl_a.abs_diff(*ttl_b) > self.options.max_distance {
+                    continue;
+                }
+                let (sample_a, sample_b) = match (samples.get(a), samples.get(b)) {
+                    (Some(sample_a), Some(sample_b)) => (*sample_a, *sample_b),
+                    _ => continue,
+                };
+                // Replies take a different way back, so the interfaces are on different routers
+                if sample_a.ttl != sample_b.ttl {
+                    continue;
+                }
+                add_evidence(&mut aliases, *a, *b, Evidence::ReplyTtl);
+
+                if self.shared_counter(traceroute, (*a, *b), options).await? {
+                    add_evidence(&mut aliases, *a, *b, Evidence::IpIdVelocity);
+                }
+            }
+        }
+
+        let report = AliasReport::new(aliases, self.options.min_confidence);
+        info!(
+            "Alias resolution merged {} interfaces into {} routers",
+            report
+                .routers
+                .iter()
+                .map(|router| router.interfaces.len())
+                .sum::<usize>(),
+            report.routers.len()
+        );
+        Ok(report)
+    }
+
+    // Probe both interfaces in turn and check their IP ids climb as a single sequence
+    async fn shared_counter(
+        &mut self,
+        traceroute: &Traceroute,
+        (a, b): (IpAddr, IpAddr),
+        options: TraceOptions,
+    ) -> Result<bool, TracerouteError> {
+        let mut ids = Vec::new();
+        for _ in 0..self.options.samples {
+            for interface in [a, b] {
+                match self.probe(traceroute, interface, options).await? {
+                    Some(sample) => ids.push(sample.ip_id),
+                    None => return Ok(false),
+                }
+            }
+        }
+
+        // Routers which zero the id or pick it at random never climb steadily
+        let max_gap = self.options.max_id_gap;
+        Ok(ids.windows(2).all(|pair| climbs(pair[0], pair[1], max_gap)))
+    }
+
+    // Send a single probe straight at an interface
+    async fn probe(
+        &mut self,
+        traceroute: &Traceroute,
+        interface: IpAddr,
+        options: TraceOptions,
+    ) -> Result<Option<Sample>, TracerouteError> {
+        let options = TraceOptions {
+            min_ttl: DIRECT_TTL,
+            max_ttl: DIRECT_TTL,
+            ..options
+        };
+        let source = traceroute.source_for(interface);
+        let mut traces = [traceroute.trace(source, interface, options)?];
+        let round = next_rounds(&mut traces).await.pop().flatten();
+        self.probes_sent += traces[0].probes_sent();
+
+        let responses = match round {
+            Some(responses) => responses?,
+            None => return Ok(None),
+        };
+        let sample = responses.into_iter().find_map(|response| match response {
+            TraceResponse::Received(resp) => Some(Sample {
+                source: resp.destination,
+                ip_id: resp.reply_id,
+                ttl: resp.reply_ttl,
+            }),
+            _ => None,
+        });
+        Ok(sample)
+    }
+}
+
+// The id went up by at most `max_gap`, wrapping around
+fn climbs(previous: u16, next: u16, max_gap: u16) -> bool {
+    let gap = next.wrapping_sub(previous);
+    gap > 0 && gap <= max_gap
+}
+
+fn add_evidence(aliases: &mut Vec<Alias>, a: IpAddr, b: IpAddr, evidence: Evidence) {
+    let interfaces = match a < b {
+        true => (a, b),
+        false => (b, a),
+    };
+    match aliases
+        .iter_mut()
+        .find(|alias| alias.interfaces == interfaces)
+    {
+        Some(alias) => {
+            if !alias.evidence.contains(&evidence) {
+                alias.evidence.push(evidence);
+            }
+        }
+        None => aliases.push(Alias {
+            interfaces,
+            evidence: vec![evidence],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_climb_within_the_gap() {
+        assert!(climbs(100, 101, 1000));
+        assert!(climbs(100, 1100, 1000));
+        assert!(!climbs(100, 1101, 1000));
+        // Repeated or falling ids aren't a counter
+        assert!(!climbs(100, 100, 1000));
+        assert!(!climbs(100, 99, 1000));
+    }
+
+    #[test]
+    fn ids_climb_through_the_wrap_around() {
+        assert!(climbs(u16::MAX, 0, 1000));
+        assert!(climbs(65_000, 400, 1000));
+        assert!(!climbs(65_000, 600, 1000));
+    }
+
+    #[test]
+    fn evidence_is_kept_per_pair_in_order() {
+        let (a, b) = ("192.0.2.2".parse().unwrap(), "192.0.2.1".parse().unwrap());
+        let mut aliases = Vec::new();
+        add_evidence(&mut aliases, a, b, Evidence::ReplyTtl);
+        add_evidence(&mut aliases, b, a, Evidence::IpIdVelocity);
+        add_evidence(&mut aliases, a, b, Evidence::ReplyTtl);
+        assert_eq!(
+            aliases,
+            vec![Alias {
+                interfaces: (b, a),
+                evidence: vec![Evidence::ReplyTtl, Evidence::IpIdVelocity],
+            }]
+        );
+    }
+}
@@ -0,0 +1,5 @@
+mod alias;
+mod report;
+
+pub use alias::{AliasOptions, AliasResolver};
+pub use report::{Alias, AliasReport, Evidence, Router};
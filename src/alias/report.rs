@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Technique which found two interfaces to belong to the same router
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Evidence {
+    /// A probe sent to one interface was answered from the other, as Mercator does
+    CommonSource,
+    /// Replies from both interfaces drew their IP ids from a single counter, as Ally and MIDAR do
+    IpIdVelocity,
+    /// Replies from both interfaces arrived with the same TTL left
+    ReplyTtl,
+}
+
+impl Evidence {
+    /// How likely the interfaces are aliases going by this technique alone
+    pub fn confidence(&self) -> f64 {
+        match self {
+            Self::CommonSource => 0.9,
+            Self::IpIdVelocity => 0.8,
+            // Many routers sit the same distance away, it only backs up the other techniques
+            Self::ReplyTtl => 0.2,
+        }
+    }
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CommonSource => write!(f, "common source"),
+            Self::IpIdVelocity => write!(f, "ip id velocity"),
+            Self::ReplyTtl => write!(f, "reply ttl"),
+        }
+    }
+}
+
+/// Two interfaces thought to belong to the same router
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Alias {
+    pub interfaces: (IpAddr, IpAddr),
+    pub evidence: Vec<Evidence>,
+}
+
+impl Alias {
+    /// Confidence of every technique combined, between 0 and 1
+    pub fn confidence(&self) -> f64 {
+        let doubt = self
+            .evidence
+            .iter()
+            .map(|evidence| 1.0 - evidence.confidence())
+            .product::<f64>();
+        1.0 - doubt
+    }
+}
+
+impl fmt::Display for Alias {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let evidence = self
+            .evidence
+            .iter()
+            .map(Evidence::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(
+            f,
+            "{} = {} ({:.2}: {})",
+            self.interfaces.0,
+            self.interfaces.1,
+            self.confidence(),
+            evidence
+        )
+    }
+}
+
+/// Interfaces merged into a single router
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Router {
+    /// Interfaces in ascending order, the first one names the router
+    pub interfaces: Vec<IpAddr>,
+    /// Confidence of the weakest alias holding the router together
+    pub confidence: f64,
+}
+
+impl Router {
+    /// Address the router is known by
+    pub fn address(&self) -> IpAddr {
+        self.interfaces[0]
+    }
+}
+
+impl fmt::Display for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let interfaces = self
+            .interfaces
+            .iter()
+            .map(IpAddr::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "Router {} ({:.2})", interfaces, self.confidence)
+    }
+}
+
+/// Aliases found between the interfaces of a trace and the routers they make up
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AliasReport {
+    /// Every pair of interfaces with some evidence of being aliases, confident or not
+    pub aliases: Vec<Alias>,
+    /// Routers of more than one interface
+    pub routers: Vec<Router>,
+}
+
+impl AliasReport {
+    /// Group the interfaces of aliases at least `min_confidence` sure into routers
+    pub fn new(aliases: Vec<Alias>, min_confidence: f64) -> Self {
+        // Union find over the interfaces, each root keeping the weakest link it took. Going from the
+        // strongest alias down only takes the links a router needs
+        let mut parents: HashMap<IpAddr, IpAddr> = HashMap::new();
+        let mut weakest: HashMap<IpAddr, f64> = HashMap::new();
+
+        let mut strongest = aliases
+            .iter()
+            .map(|alias| (alias.confidence(), alias.interfaces))
+            .filter(|(confidence, _interfaces)| *confidence >= min_confidence)
+            .collect::<Vec<_>>();
+        strongest.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        for (confidence, (a, b)) in strongest {
+            let root_a = find(&mut parents, a);
+            let root_b = find(&mut parents, b);
+            if root_a == root_b {
+                continue;
+            }
+            let link = weakest
+                .get(&root_a)
+                .copied()
+                .unwrap_or(1.0)
+                .min(weakest.get(&root_b).copied().unwrap_or(1.0))
+                .min(confidence);
+            let (root, child) = match root_a < root_b {
+                true => (root_a, root_b),
+                false => (root_b, root_a),
+            };
+            let _ = parents.insert(child, root);
+            let _ = weakest.remove(&child);
+            let _ = weakest.insert(root, link);
+        }
+
+        let mut members: HashMap<IpAddr, Vec<IpAddr>> = HashMap::new();
+        let interfaces = parents.keys().copied().collect::<Vec<_>>();
+        for interface in interfaces {
+            let root = find(&mut parents, interface);
+            members.entry(root).or_default().push(interface);
+        }
+
+        let mut routers = members
+            .into_iter()
+            .filter(|(_root, interfaces)| interfaces.len() > 1)
+            .map(|(root, mut interfaces)| {
+                interfaces.sort();
+                Router {
+                    interfaces,
+                    confidence: weakest.get(&root).copied().unwrap_or(1.0),
+                }
+            })
+            .collect::<Vec<_>>();
+        routers.sort_by_key(Router::address);
+
+        Self { aliases, routers }
+    }
+
+    /// Router owning an interface, None when it wasn't merged with any other
+    pub fn router(&self, interface: &IpAddr) -> Option<&Router> {
+        self.routers
+            .iter()
+            .find(|router| router.interfaces.contains(interface))
+    }
+}
+
+impl fmt::Display for AliasReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for router in &self.routers {
+            writeln!(f, "{}", router)?;
+        }
+        Ok(())
+    }
+}
+
+// Root of an interface, compressing the path on the way
+fn find(parents: &mut HashMap<IpAddr, IpAddr>, interface: IpAddr) -> IpAddr {
+    let parent = *parents.entry(interface).or_insert(interface);
+    if parent == interface {
+        return interface;
+    }
+    let root = find(parents, parent);
+    let _ = parents.insert(interface, root);
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    fn alias(a: u8, b: u8, evidence: &[Evidence]) -> Alias {
+        Alias {
+            interfaces: (ip(a), ip(b)),
+            evidence: evidence.to_vec(),
+        }
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn evidence_adds_up() {
+        assert_close(alias(1, 2, &[]).confidence(), 0.0);
+        assert_close(alias(1, 2, &[Evidence::ReplyTtl]).confidence(), 0.2);
+        // Each technique takes away some of the doubt left
+        let both = alias(1, 2, &[Evidence::CommonSource, Evidence::IpIdVelocity]);
+        assert_close(both.confidence(), 0.98);
+        assert_eq!(
+            both.to_string(),
+            "192.0.2.1 = 192.0.2.2 (0.98: common source, ip id velocity)"
+        );
+    }
+
+    #[test]
+    fn doubtful_aliases_are_kept_but_not_merged() {
+        let aliases = vec![alias(1, 2, &[Evidence::ReplyTtl])];
+        let report = AliasReport::new(aliases.clone(), 0.5);
+        assert_eq!(report.aliases, aliases);
+        assert!(report.routers.is_empty());
+        assert_eq!(report.router(&ip(1)), None);
+        assert_eq!(report.to_string(), "");
+    }
+
+    #[test]
+    fn aliases_merge_transitively() {
+        let aliases = vec![
+            alias(3, 2, &[Evidence::IpIdVelocity]),
+            alias(1, 2, &[Evidence::CommonSource]),
+        ];
+        let report = AliasReport::new(aliases, 0.5);
+        assert_eq!(report.routers.len(), 1);
+
+        let router = &report.routers[0];
+        assert_eq!(router.interfaces, vec![ip(1), ip(2), ip(3)]);
+        assert_eq!(router.address(), ip(1));
+        // Only as sure as the weakest alias holding it together
+        assert_close(router.confidence, 0.8);
+        assert_eq!(report.router(&ip(3)), Some(router));
+    }
+
+    #[test]
+    fn redundant_aliases_dont_weaken_a_router() {
+        let aliases = vec![
+            alias(1, 2, &[Evidence::CommonSource]),
+            alias(2, 3, &[Evidence::CommonSource]),
+            // 0.84, but the stronger aliases already hold 1 and 3 together
+            alias(1, 3, &[Evidence::IpIdVelocity, Evidence::ReplyTtl]),
+        ];
+        let report = AliasReport::new(aliases, 0.5);
+        assert_eq!(report.routers.len(), 1);
+        assert_close(report.routers[0].confidence, 0.9);
+    }
+
+    #[test]
+    fn separate_routers_stay_apart() {
+        let aliases = vec![
+            alias(20, 21, &[Evidence::CommonSource]),
+            alias(10, 11, &[Evidence::IpIdVelocity]),
+            alias(11, 20, &[Evidence::ReplyTtl]),
+        ];
+        let report = AliasReport::new(aliases, 0.5);
+        let routers = report
+            .routers
+            .iter()
+            .map(|router| router.interfaces.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(routers, vec![vec![ip(10), ip(11)], vec![ip(20), ip(21)]]);
+        assert_eq!(
+            report.to_string(),
+            "Router 192.0.2.10, 192.0.2.11 (0.80)\nRouter 192.0.2.20, 192.0.2.21 (0.90)\n"
+        );
+    }
+}
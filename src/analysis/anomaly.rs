@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::prelude::{Flowhash, TTL};
+use crate::probe::{ProbeResponse, ReplyKind};
+use crate::trace::{TraceFlow, TraceResponse};
+
+/// Something off about a path which the hops alone don't show
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum Anomaly {
+    /// Probes came back around to a hop they already went through
+    Loop {
+        destination: IpAddr,
+        /// Distance of the first hop of the cycle
+        ttl: TTL,
+        /// Hops of the cycle, starting and ending at the same one
+        cycle: Vec<IpAddr>,
+    },
+    /// A hop answering some probes and not others while hops further along replied, most likely
+    /// limiting the rate of its ICMP messages
+    RateLimited {
+        destination: IpAddr,
+        ttl: TTL,
+        address: IpAddr,
+        /// Probes the hop left unanswered
+        lost: usize,
+        /// Probes sent to the hop
+        sent: usize,
+    },
+    /// A router forwarding probes which should have expired at it, hiding itself behind the next
+    /// hop answering for both distances
+    ZeroTtlForwarder {
+        destination: IpAddr,
+        /// Distance of the hidden router
+        ttl: TTL,
+        /// Hop answering in its place
+        next: IpAddr,
+    },
+}
+
+impl Anomaly {
+    /// Find the anomalies of every round of every flow
+    pub fn detect(flows: &[TraceFlow]) -> Vec<Self> {
+        let mut anomalies: Vec<Self> = Vec::new();
+        let mut push = |anomaly: Self| {
+            if !anomalies.contains(&anomaly) {
+                anomalies.push(anomaly);
+            }
+        };
+
+        for flow in flows {
+            for anomaly in loops(flow).into_iter().chain(zero_ttl_forwarders(flow)) {
+                push(anomaly);
+            }
+        }
+
+        // Loss only stands out over several rounds of the same flow
+        let mut rounds: BTreeMap<Flowhash, Vec<&TraceFlow>> = BTreeMap::new();
+        for flow in flows {
+            rounds.entry(flow.flowhash).or_default().push(flow);
+        }
+        for rounds in rounds.values() {
+            for anomaly in rate_limited(rounds) {
+                push(anomaly);
+            }
+        }
+
+        anomalies
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Loop {
+                destination,
+                ttl,
+                cycle,
+            } => {
+                let cycle = cycle
+                    .iter()
+                    .map(IpAddr::to_string)
+                    .collect::<Vec<String>>()
+                    .join(" -> ");
+                write!(
+                    f,
+                    "routing loop towards {} from hop {}: {}",
+                    destination, ttl, cycle
+                )
+            }
+            Self::RateLimited {
+                destination,
+                ttl,
+                address,
+                lost,
+                sent,
+            } => write!(
+                f,
+                "{} (hop {} towards {}) left {} of {} probes unanswered while later hops replied, \
+                 likely rate limiting ICMP",
+                address, ttl, destination, lost, sent
+            ),
+            Self::ZeroTtlForwarder {
+                destination,
+                ttl,
+                next,
+            } => write!(
+                f,
+                "hop {} towards {} forwards expired probes, {} answers in its place",
+                ttl, destination, next
+            ),
+        }
+    }
+}
+
+// Time exceeded replies of a round ordered by distance
+fn expired(flow: &TraceFlow) -> Vec<&ProbeResponse> {
+    let mut received = flow
+        .responses
+        .iter()
+        .filter_map(|response| match response {
+            TraceResponse::Received(resp) if resp.kind == ReplyKind::TimeExceeded => Some(resp),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    received.sort_by_key(|resp| resp.ttl);
+    received
+}
+
+// A hop answering again further along with other hops in between
+fn loops(flow: &TraceFlow) -> Vec<Anomaly> {
+    let received = expired(flow);
+
+    for (index, first) in received.iter().enumerate() {
+        let again = received[index + 1..]
+            .iter()
+            .position(|resp| resp.destination == first.destination);
+        let again = match again {
+            Some(again) => index + 1 + again,
+            None => continue,
+        };
+        let cycle = received[index..=again]
+            .iter()
+            .map(|resp| resp.destination)
+            .collect::<Vec<_>>();
+        // The same hop twice in a row is a zero TTL forwarder rather than a loop
+        if cycle.iter().all(|address| *address == first.destination) {
+            continue;
+        }
+
+        // Packets stuck in a loop keep going around it, the first cycle tells it all
+        return vec![Anomaly::Loop {
+            destination: flow.destination,
+            ttl: first.ttl,
+            cycle,
+        }];
+    }
+    Vec::new()
+}
+
+// The same hop answered two distances in a row, the router in front forwarded the probe
+// which should have expired at it
+//
+// A quoted TTL of 0 doesn't tell, most routers quote the probe after decrementing it.
+fn zero_ttl_forwarders(flow: &TraceFlow) -> Vec<Anomaly> {
+    let received = expired(flow);
+    let mut anomalies = Vec::new();
+
+    for pair in received.windows(2) {
+        let (previous, next) = (pair[0], pair[1]);
+        if previous.ttl + 1 != next.ttl || previous.destination != next.destination {
+            continue;
+        }
+        let anomaly = Anomaly::ZeroTtlForwarder {
+            destination: flow.destination,
+            ttl: previous.ttl,
+            next: next.destination,
+        };
+        if !anomalies.contains(&anomaly) {
+            anomalies.push(anomaly);
+        }
+    }
+    anomalies
+}
+
+// Distances answering in some rounds and not in others while the rounds missing them got
+// replies from further along
+fn rate_limited(rounds: &[&TraceFlow]) -> Vec<Anomaly> {
+    // Per distance: address, probes sent, probes lost with later hops replying
+    let mut distances: BTreeMap<TTL, (Option<IpAddr>, usize, usize)> = BTreeMap::new();
+    let mut destination = None;
+
+    for flow in rounds {
+        destination = Some(flow.destination);
+        let furthest_reply = flow
+            .responses
+            .iter()
+            .filter_map(|response| match response {
+                TraceResponse::Received(resp) => Some(resp.ttl),
+                _ => None,
+            })
+            .max();
+
+        for response in &flow.responses {
+            let ttl = response.get_distance();
+            match response {
+                TraceResponse::Received(resp) => {
+                    let distance = distances.entry(ttl).or_default();
+                    distance.0 = Some(resp.destination);
+                    distance.1 += 1;
+                }
+                TraceResponse::TimedOut(_sent) => {
+                    let distance = distances.entry(ttl).or_default();
+                    distance.1 += 1;
+                    if furthest_reply.is_some_and(|furthest| furthest > ttl) {
+                        distance.2 += 1;
+                    }
+                }
+                TraceResponse::Masked(_ttl) => (),
+            }
+        }
+    }
+
+    let destination = match destination {
+        Some(destination) => destination,
+        None => return Vec::new(),
+    };
+    distances
+        .into_iter()
+        .filter_map(|(ttl, (address, sent, lost))| {
+            // Hops never answering are anonymous rather than rate limited
+            let address = address?;
+            (lost > 0).then_some(Anomaly::RateLimited {
+                destination,
+                ttl,
+                address,
+                lost,
+                sent,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    // Hops as (ttl, last octet of the replying address). Timeouts have no address
+    fn flow(flowhash: Flowhash, hops: &[(TTL, Option<u8>)]) -> TraceFlow {
+        let responses = hops
+            .iter()
+            .map(|(ttl, address)| match address {
+                Some(last) => TraceResponse::Received(fake::received(
+                    *ttl,
+                    ip(*last),
+                    Duration::from_millis(1),
+                )),
+                None => TraceResponse::TimedOut(fake::sent(*ttl)),
+            })
+            .collect();
+        TraceFlow {
+            flowhash,
+            source: fake::SOURCE,
+            destination: ip(200),
+            responses,
+            joined: false,
+        }
+    }
+
+    #[test]
+    fn clean_paths_have_no_anomalies() {
+        let path = [(1, Some(1)), (2, Some(2)), (3, None), (4, Some(4))];
+        assert_eq!(Anomaly::detect(&[flow(1, &path)]), Vec::new());
+        assert_eq!(Anomaly::detect(&[]), Vec::new());
+    }
+
+    #[test]
+    fn loops_report_their_first_cycle() {
+        let path = [
+            (1, Some(1)),
+            (2, Some(2)),
+            (3, Some(3)),
+            (4, Some(2)),
+            (5, Some(3)),
+        ];
+        let anomalies = Anomaly::detect(&[flow(1, &path), flow(1, &path)]);
+        assert_eq!(
+            anomalies,
+            vec![Anomaly::Loop {
+                destination: ip(200),
+                ttl: 2,
+                cycle: vec![ip(2), ip(3), ip(2)],
+            }]
+        );
+        assert_eq!(
+            anomalies[0].to_string(),
+            "routing loop towards 192.0.2.200 from hop 2: 192.0.2.2 -> 192.0.2.3 -> 192.0.2.2"
+        );
+    }
+
+    #[test]
+    fn a_hop_answering_twice_in_a_row_hides_a_forwarder() {
+        let path = [(1, Some(1)), (2, Some(2)), (3, Some(2)), (4, Some(4))];
+        assert_eq!(
+            Anomaly::detect(&[flow(1, &path)]),
+            vec![Anomaly::ZeroTtlForwarder {
+                destination: ip(200),
+                ttl: 2,
+                next: ip(2),
+            }]
+        );
+    }
+
+    #[test]
+    fn a_hop_answering_around_a_timeout_is_no_anomaly() {
+        let path = [(1, Some(1)), (2, Some(2)), (3, None), (4, Some(2))];
+        assert_eq!(Anomaly::detect(&[flow(1, &path)]), Vec::new());
+    }
+
+    #[test]
+    fn only_expired_probes_are_compared() {
+        let mut destination = flow(1, &[(1, Some(1)), (2, Some(200)), (3, Some(200))]);
+        for response in &mut destination.responses {
+            if let TraceResponse::Received(resp) = response {
+                if resp.destination == ip(200) {
+                    resp.kind = ReplyKind::Unreachable { code: 3 };
+                }
+            }
+        }
+        assert_eq!(Anomaly::detect(&[destination]), Vec::new());
+    }
+
+    #[test]
+    fn loss_before_later_replies_is_rate_limiting() {
+        let rounds = [
+            flow(1, &[(1, Some(1)), (2, Some(2)), (3, Some(3))]),
+            flow(1, &[(1, Some(1)), (2, None), (3, Some(3))]),
+            flow(1, &[(1, Some(1)), (2, Some(2)), (3, Some(3))]),
+        ];
+        let anomalies = Anomaly::detect(&rounds);
+        assert_eq!(
+            anomalies,
+            vec![Anomaly::RateLimited {
+                destination: ip(200),
+                ttl: 2,
+                address: ip(2),
+                lost: 1,
+                sent: 3,
+            }]
+        );
+        assert_eq!(
+            anomalies[0].to_string(),
+            "192.0.2.2 (hop 2 towards 192.0.2.200) left 1 of 3 probes unanswered while later \
+             hops replied, likely rate limiting ICMP"
+        );
+    }
+
+    #[test]
+    fn loss_at_the_end_or_of_silent_hops_is_not_rate_limiting() {
+        let rounds = [
+            flow(1, &[(1, Some(1)), (2, None), (3, Some(3))]),
+            flow(1, &[(1, Some(1)), (2, None), (3, None)]),
+        ];
+        assert_eq!(Anomaly::detect(&rounds), Vec::new());
+    }
+
+    #[test]
+    fn loss_is_only_compared_within_a_flow() {
+        let rounds = [
+            flow(1, &[(1, Some(1)), (2, Some(2)), (3, Some(3))]),
+            flow(2, &[(1, Some(1)), (2, None), (3, Some(3))]),
+        ];
+        assert_eq!(Anomaly::detect(&rounds), Vec::new());
+    }
+}
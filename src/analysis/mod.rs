@@ -0,0 +1,3 @@
+mod anomaly;
+
+pub use anomaly::Anomaly;
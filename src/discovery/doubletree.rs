@@ -0,0 +1,213 @@
+use std::net::IpAddr;
+
+use log::*;
+
+use super::StopSet;
+use crate::TracerouteError;
+use crate::prelude::TTL;
+use crate::trace::{Trace, TraceData, TraceOptions, TraceResponse, next_rounds};
+use crate::traceroute::Traceroute;
+
+/// Configuration of [`Doubletree`] discovery
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DoubletreeOptions {
+    /// Distance probing starts at. Probes go forward to the destination and backward to the
+    /// source from here
+    pub start_ttl: TTL,
+    /// Destinations probed at the same time. Smaller batches share more of the stop set at the
+    /// cost of speed
+    pub batch_size: usize,
+}
+
+impl Default for DoubletreeOptions {
+    fn default() -> Self {
+        Self {
+            start_ttl: 10,
+            batch_size: 32,
+        }
+    }
+}
+
+/// Topology discovery which avoids probing near hops over and over
+///
+/// Each destination is probed forward from a mid-path distance and then backwards one hop at a
+/// time. Backward probing stops at the first interface already in the [`StopSet`] as the rest of
+/// the path was discovered by an earlier destination.
+#[derive(Debug, Default)]
+pub struct Doubletree {
+    options: DoubletreeOptions,
+    stop_set: StopSet,
+    probes_sent: usize,
+}
+
+impl Doubletree {
+    pub fn new(options: DoubletreeOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Interfaces discovered so far
+    pub fn stop_set(&self) -> &StopSet {
+        &self.stop_set
+    }
+
+    /// Probes sent over every discovery
+    pub fn probes_sent(&self) -> usize {
+        self.probes_sent
+    }
+
+    /// Discover the paths to every destination and merge them into `data`
+    ///
+    /// Traces are run with the options of `data`.
+    pub async fn discover(
+        &mut self,
+        traceroute: &Traceroute,
+        destinations: &[IpAddr],
+        data: &mut TraceData,
+    ) -> Result<(), TracerouteError> {
+        let batch_size = self.options.batch_size.max(1);
+        for batch in destinations.chunks(batch_size) {
+            self.discover_batch(traceroute, batch, data).await?;
+        }
+
+        info!(
+            "Doubletree sent {} probes to {} destinations and knows {} interfaces",
+            self.probes_sent,
+            destinations.len(),
+            self.stop_set.len()
+        );
+        Ok(())
+    }
+
+    async fn discover_batch(
+        &mut self,
+        traceroute: &Traceroute,
+        destinations: &[IpAddr],
+        data: &mut TraceData,
+    ) -> Result<(), TracerouteError> {
+        // Each destination is traced from the source the routing table picks for it
+        let flows = destinations
+            .iter()
+            .map(|destination| (traceroute.source_for(*destination), *destination))
+            .collect::<Vec<(IpAddr, IpAddr)>>();
+        let options = *data.options();
+        let start_ttl = self
+            .options
+            .start_ttl
+            .clamp(options.min_ttl, options.max_ttl);
+
+        // Forward from the start to the destination
+        let forward = TraceOptions {
+            min_ttl: start_ttl,
+            ..options
+        };
+        let mut paths = self.round(traceroute, &flows, forward).await?;
+        for path in &paths {
+            self.learn(path);
+        }
+
+        // Backward towards the source until known territory
+        let mut active: Vec<usize> = (0..destinations.len()).collect();
+        let mut joined = vec![false; destinations.len()];
+
+        for ttl in (options.min_ttl..start_ttl).rev() {
+            if active.is_empty() {
+                break;
+            }
+            let backward = TraceOptions {
+                min_ttl: ttl,
+                max_ttl: ttl,
+                ..options
+            };
+            let targets = active
+                .iter()
+                .map(|index| flows[*index])
+                .collect::<Vec<(IpAddr, IpAddr)>>();
+            let rounds = self.round(traceroute, &targets, backward).await?;
+
+            let mut still_active = Vec::new();
+            for (index, responses) in active.into_iter().zip(rounds) {
+                let known = responses.iter().any(|response| match response {
+                    TraceResponse::Received(resp) => self.stop_set.contains(resp.destination, ttl),
+                    _ => false,
+                });
+                if known {
+                    debug!(
+                        "Reached known territory at TTL {} towards {}",
+                        ttl, destinations[index]
+                    );
+                    joined[index] = true;
+                } else {
+                    self.learn(&responses);
+                    still_active.push(index);
+                }
+
+                // Backward probes go in front to keep the path in order
+                let _ = paths[index].splice(0..0, responses);
+            }
+            active = still_active;
+        }
+
+        for (index, path) in paths.iter().enumerate() {
+            let (source, destination) = flows[index];
+            if joined[index] {
+                data.join_flow(source, destination, path);
+            } else {
+                data.add_flow(source, destination, path);
+            }
+        }
+        Ok(())
+    }
+
+    // A single round along every (source, destination) flow
+    async fn round(
+        &mut self,
+        traceroute: &Traceroute,
+        flows: &[(IpAddr, IpAddr)],
+        options: TraceOptions,
+    ) -> Result<Vec<Vec<TraceResponse>>, TracerouteError> {
+        let mut traces = flows
+            .iter()
+            .map(|(source, destination)| traceroute.trace(*source, *destination, options))
+            .collect::<Result<Vec<Trace>, TracerouteError>>()?;
+
+        let rounds = next_rounds(&mut traces).await;
+        self.probes_sent += traces.iter().map(Trace::probes_sent).sum::<usize>();
+
+        rounds
+            .into_iter()
+            .map(|round| round.unwrap_or_else(|| Ok(Vec::new())))
+            .collect()
+    }
+
+    fn learn(&mut self, responses: &[TraceResponse]) {
+        for response in responses {
+            if let TraceResponse::Received(resp) = response {
+                let _ = self.stop_set.insert(resp.destination, resp.ttl);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn only_replies_are_learned() {
+        let hop = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut doubletree = Doubletree::default();
+        doubletree.learn(&[
+            TraceResponse::TimedOut(fake::sent(1)),
+            TraceResponse::Received(fake::received(2, hop, Duration::from_millis(5))),
+            TraceResponse::Masked(3),
+        ]);
+        assert_eq!(doubletree.stop_set().len(), 1);
+        assert!(doubletree.stop_set().contains(hop, 2));
+    }
+}
@@ -0,0 +1,5 @@
+mod doubletree;
+mod stop_set;
+
+pub use doubletree::{Doubletree, DoubletreeOptions};
+pub use stop_set::StopSet;
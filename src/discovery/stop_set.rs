@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use crate::prelude::TTL;
+
+/// Interfaces already discovered along with the distance they were seen at
+///
+/// Doubletree's "local stop set". Once backwards probing reaches a pair in the set the rest of
+/// the path towards the source is already known.
+#[derive(Clone, Debug, Default)]
+pub struct StopSet {
+    seen: HashSet<(IpAddr, TTL)>,
+}
+
+impl StopSet {
+    /// Remember an interface. Returns false if it was already known
+    pub fn insert(&mut self, interface: IpAddr, ttl: TTL) -> bool {
+        self.seen.insert((interface, ttl))
+    }
+
+    pub fn contains(&self, interface: IpAddr, ttl: TTL) -> bool {
+        self.seen.contains(&(interface, ttl))
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    #[test]
+    fn interfaces_are_remembered_once() {
+        let mut stop_set = StopSet::default();
+        assert!(stop_set.is_empty());
+        assert!(stop_set.insert(ip(1), 3));
+        assert!(!stop_set.insert(ip(1), 3));
+        assert_eq!(stop_set.len(), 1);
+        assert!(!stop_set.is_empty());
+    }
+
+    #[test]
+    fn distance_is_part_of_the_pair() {
+        let mut stop_set = StopSet::default();
+        let _ = stop_set.insert(ip(1), 3);
+        assert!(stop_set.contains(ip(1), 3));
+        // Seen at another distance the path towards the source may differ
+        assert!(!stop_set.contains(ip(1), 2));
+        assert!(!stop_set.contains(ip(2), 3));
+
+        assert!(stop_set.insert(ip(1), 2));
+        assert_eq!(stop_set.len(), 2);
+    }
+}
@@ -4,6 +4,8 @@ use std::time::Duration;
 
 /// Edge in the graph
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Edge {
     TTL(u8),
     RTT(Duration),
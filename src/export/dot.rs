@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+use super::ExportOptions;
+use super::view::{GraphView, NodeKind, escape_dot};
+use crate::trace::TraceData;
+
+// Colorblind friendly palette cycled through for each flow
+const FLOW_COLORS: [&str; 8] = [
+    "#0072b2", "#e69f00", "#009e73", "#cc79a7", "#56b4e9", "#d55e00", "#f0e442", "#000000",
+];
+
+impl TraceData {
+    /// Render the graph in the graphviz dot language
+    ///
+    /// Edges are drawn once per flow in the flow's colour and labeled with the latency of the
+    /// hop they lead to. Nodes which never replied are dashed and the first hops behind a NAT are
+    /// outlined in orange.
+    pub fn to_dot(&self, options: &ExportOptions) -> String {
+        let view = GraphView::new(self, options);
+        let mut out = String::from("digraph traceroute {\n");
+        out.push_str("    rankdir=TB;\n");
+        out.push_str("    node [shape=box, style=rounded, fontname=\"monospace\"];\n");
+        out.push_str("    edge [fontname=\"monospace\", fontsize=10];\n");
+
+        // Warnings are listed under the graph, each line left aligned
+        let warnings = self
+            .anomalies()
+            .iter()
+            .map(|anomaly| format!("Warning: {}\\l", escape_dot(&anomaly.to_string())))
+            .collect::<String>();
+        if !warnings.is_empty() {
+            let _ = writeln!(
+                out,
+                "    label=\"{}\";\n    labelloc=b;\n    labeljust=l;\n    fontname=\"monospace\";",
+                warnings
+            );
+        }
+
+        for (id, node) in view.nodes.iter().enumerate() {
+            let mut lines = vec![escape_dot(&node.label)];
+            if let Some(hostname) = &node.hostname {
+                lines.push(escape_dot(hostname));
+            }
+            if let Some(stats) = node.stats() {
+                lines.push(escape_dot(&stats));
+            }
+            if node.nat {
+                lines.push("behind NAT".to_string());
+            }
+            let style = match node.kind {
+                NodeKind::Source => "\"rounded,bold\"",
+                NodeKind::Hop => "rounded",
+                NodeKind::Hidden => "\"rounded,dashed\"",
+                NodeKind::Masked => "\"rounded,dashed,filled\", fillcolor=\"#eeeeee\"",
+            };
+            let color = match node.nat {
+                true => ", color=\"#d55e00\"",
+                false => "",
+            };
+            let _ = writeln!(
+                out,
+                "    n{} [label=\"{}\", style={}{}];",
+                id,
+                lines.join("\\n"),
+                style,
+                color
+            );
+        }
+
+        for edge in &view.edges {
+            let dashed = match view.nodes[edge.to].kind {
+                NodeKind::Hidden | NodeKind::Masked => ", style=dashed",
+                NodeKind::Source | NodeKind::Hop => "",
+            };
+
+            for flowhash in &edge.flows {
+                let index = view
+                    .flows
+                    .iter()
+                    .position(|flow| flow == flowhash)
+                    .unwrap_or_default();
+                let color = FLOW_COLORS[index % FLOW_COLORS.len()];
+
+                let label = edge.flow_label(*flowhash);
+
+                let _ = writeln!(
+                    out,
+                    "    n{} -> n{} [label=\"{}\", color=\"{}\", fontcolor=\"{}\"{}];",
+                    edge.from,
+                    edge.to,
+                    escape_dot(&label),
+                    color,
+                    color,
+                    dashed
+                );
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
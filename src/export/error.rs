@@ -0,0 +1,17 @@
+use std::{error::Error, fmt};
+
+/// Represents an error which occurred whilst parsing a Format.
+#[derive(Copy, Debug, PartialEq, Eq, Clone)]
+pub enum ParseFormatErr {
+    UnknownFormat,
+}
+
+impl Error for ParseFormatErr {}
+
+impl fmt::Display for ParseFormatErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseFormatErr::UnknownFormat => write!(f, "Unknown Format"),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::{fmt, str::FromStr};
+
+use super::error::ParseFormatErr;
+
+/// Output format of the collected [`TraceData`](crate::TraceData)
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Format {
+    /// Typical traceroute listing of the first flow
+    #[default]
+    Text,
+    /// Graph in the graphviz dot language
+    Dot,
+    /// Versioned machine readable document
+    Json,
+    /// Mermaid flowchart
+    Mermaid,
+    /// GraphML document for Gephi, yEd and friends
+    GraphMl,
+    /// Self contained page with an interactive graph
+    Html,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Dot => write!(f, "dot"),
+            Self::Json => write!(f, "json"),
+            Self::Mermaid => write!(f, "mermaid"),
+            Self::GraphMl => write!(f, "graphml"),
+            Self::Html => write!(f, "html"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = ParseFormatErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let format = match s {
+            "text" | "TEXT" => Self::Text,
+            "dot" | "DOT" | "graph" => Self::Dot,
+            "json" | "JSON" => Self::Json,
+            "mermaid" | "MERMAID" => Self::Mermaid,
+            "graphml" | "GRAPHML" => Self::GraphMl,
+            "html" | "HTML" => Self::Html,
+            _ => Err(ParseFormatErr::UnknownFormat)?,
+        };
+
+        Ok(format)
+    }
+}
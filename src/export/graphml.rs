@@ -0,0 +1,96 @@
+use std::fmt::Write;
+
+use super::ExportOptions;
+use super::view::{GraphView, escape_xml};
+use crate::trace::TraceData;
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns"
+    xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+    xsi:schemaLocation="http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="kind" for="node" attr.name="kind" attr.type="string"/>
+  <key id="hostname" for="node" attr.name="hostname" attr.type="string"/>
+  <key id="nat" for="node" attr.name="nat" attr.type="boolean">
+    <default>false</default>
+  </key>
+  <key id="edge_label" for="edge" attr.name="label" attr.type="string"/>
+  <key id="ttl" for="edge" attr.name="ttl" attr.type="int"/>
+  <key id="rtt" for="edge" attr.name="rtt_ms" attr.type="double"/>
+  <key id="flows" for="edge" attr.name="flows" attr.type="string"/>
+  <key id="warnings" for="graph" attr.name="warnings" attr.type="string"/>
+  <graph id="traceroute" edgedefault="directed">
+"#;
+
+const FOOTER: &str = "  </graph>\n</graphml>\n";
+
+impl TraceData {
+    /// Render the graph as GraphML for tools such as Gephi or yEd
+    pub fn to_graphml(&self, options: &ExportOptions) -> String {
+        let view = GraphView::new(self, options);
+        let mut out = String::from(HEADER);
+
+        let warnings = self
+            .anomalies()
+            .iter()
+            .map(|anomaly| anomaly.to_string())
+            .collect::<Vec<String>>();
+        if !warnings.is_empty() {
+            let _ = writeln!(
+                out,
+                "    <data key=\"warnings\">{}</data>",
+                escape_xml(&warnings.join("\n"))
+            );
+        }
+
+        for (id, node) in view.nodes.iter().enumerate() {
+            let _ = writeln!(out, "    <node id=\"n{}\">", id);
+            let _ = writeln!(out, "      <data key=\"label\">{}</data>", escape_xml(&node.label));
+            let _ = writeln!(out, "      <data key=\"kind\">{}</data>", node.kind.name());
+            if let Some(hostname) = &node.hostname {
+                let _ = writeln!(
+                    out,
+                    "      <data key=\"hostname\">{}</data>",
+                    escape_xml(hostname)
+                );
+            }
+            if node.nat {
+                out.push_str("      <data key=\"nat\">true</data>\n");
+            }
+            out.push_str("    </node>\n");
+        }
+
+        for (id, edge) in view.edges.iter().enumerate() {
+            let flows = edge
+                .flows
+                .iter()
+                .map(|flowhash| format!("{:x?}", flowhash))
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            let _ = writeln!(
+                out,
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+                id, edge.from, edge.to
+            );
+            let _ = writeln!(
+                out,
+                "      <data key=\"edge_label\">{}</data>",
+                escape_xml(&edge.label())
+            );
+            let _ = writeln!(out, "      <data key=\"ttl\">{}</data>", edge.ttl);
+            if let Some(rtt) = edge.rtt() {
+                let _ = writeln!(
+                    out,
+                    "      <data key=\"rtt\">{:.3}</data>",
+                    rtt.as_secs_f64() * 1000.0
+                );
+            }
+            let _ = writeln!(out, "      <data key=\"flows\">{}</data>", flows);
+            out.push_str("    </edge>\n");
+        }
+
+        out.push_str(FOOTER);
+        out
+    }
+}
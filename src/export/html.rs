@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use super::ExportOptions;
+use super::view::{GraphView, escape_xml, quote_json};
+use crate::trace::TraceData;
+
+// Page containing the force directed layout. Graph data is substituted into it.
+const TEMPLATE: &str = include_str!("graph.html");
+
+impl TraceData {
+    /// Render the graph as a self contained html page with an interactive force directed view
+    pub fn to_html(&self, options: &ExportOptions) -> String {
+        let view = GraphView::new(self, options);
+
+        let title = self
+            .flows()
+            .iter()
+            .map(|flow| flow.destination.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let title = format!("Traceroute {}", title);
+
+        // Nodes are placed vertically by the closest distance they were seen at
+        let mut ttls = vec![0u8; view.nodes.len()];
+        for edge in &view.edges {
+            if ttls[edge.to] == 0 || edge.ttl < ttls[edge.to] {
+                ttls[edge.to] = edge.ttl;
+            }
+        }
+
+        let mut graph = String::from("{\"nodes\":[");
+        for (id, node) in view.nodes.iter().enumerate() {
+            if id > 0 {
+                graph.push(',');
+            }
+            let _ = write!(
+                graph,
+                "{{\"label\":{},\"kind\":\"{}\",\"ttl\":{},\"nat\":{}}}",
+                quote_json(&node.label),
+                node.kind.name(),
+                ttls[id],
+                node.nat
+            );
+        }
+        graph.push_str("],\"edges\":[");
+        for (id, edge) in view.edges.iter().enumerate() {
+            if id > 0 {
+                graph.push(',');
+            }
+            let _ = write!(
+                graph,
+                "{{\"from\":{},\"to\":{},\"label\":{}}}",
+                edge.from,
+                edge.to,
+                quote_json(&edge.label())
+            );
+        }
+        graph.push_str("]}");
+
+        let warnings = self
+            .anomalies()
+            .iter()
+            .map(|anomaly| {
+                format!(
+                    "<div class=\"warning\">Warning: {}</div>",
+                    escape_xml(&anomaly.to_string())
+                )
+            })
+            .collect::<String>();
+
+        TEMPLATE
+            .replace("{{TITLE}}", &escape_xml(&title))
+            .replace("{{WARNINGS}}", &warnings)
+            .replace("{{GRAPH}}", &graph)
+    }
+}
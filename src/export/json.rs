@@ -0,0 +1,188 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::alias::AliasReport;
+use crate::analysis::Anomaly;
+use crate::middlebox::Middlebox;
+use crate::prelude::{Checksum, Flowhash, TTL, TcpId};
+use crate::probe::HeaderChange;
+use crate::trace::{TraceData, TraceFlow, TraceOptions, TraceResponse};
+use crate::{Edge, Node, TracerouteError};
+
+/// Version of the json document layout
+///
+/// Bump whenever a field is renamed, removed or changes meaning. Adding fields is not a breaking
+/// change.
+const JSON_SCHEMA_VERSION: u32 = 2;
+
+/// Root of the json document
+#[derive(Serialize)]
+struct Document<'a> {
+    schema: &'static str,
+    version: u32,
+    options: &'a TraceOptions,
+    flows: Vec<Flow<'a>>,
+    graph: Graph,
+    /// Interfaces merged into routers when aliases were resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aliases: Option<&'a AliasReport>,
+    /// Routing loops, rate limited hops and other oddities of the paths
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Anomaly>,
+}
+
+/// A single flow and each hop along it
+#[derive(Serialize)]
+struct Flow<'a> {
+    flowhash: Flowhash,
+    source: IpAddr,
+    destination: IpAddr,
+    hops: Vec<Hop<'a>>,
+    /// Hops start part way along the path where other flows left off
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    joined: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    middleboxes: Vec<Middlebox>,
+    responses: &'a [TraceResponse],
+}
+
+/// Outcome of a single probe flattened for easy consumption
+#[derive(Serialize)]
+struct Hop<'a> {
+    ttl: TTL,
+    status: HopStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<IpAddr>,
+    /// Round trip time in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<TcpId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<Checksum>,
+    /// Difference between the quoted and sent checksum, set when a NAT rewrote it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nat_id: Option<Checksum>,
+    /// TTL left on the reply when it arrived
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_ttl: Option<TTL>,
+    /// Hops the reply went through on its way back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_hops: Option<TTL>,
+    /// Return hops less forward hops, set when they differ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asymmetry: Option<i16>,
+    /// Headers which arrived at the hop changed
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    modifications: &'a [HeaderChange],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum HopStatus {
+    Received,
+    TimedOut,
+    Masked,
+}
+
+#[derive(Serialize)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize)]
+struct GraphEdge {
+    from: Node,
+    to: Node,
+    edge: Edge,
+}
+
+impl<'a> From<&'a TraceResponse> for Hop<'a> {
+    fn from(response: &'a TraceResponse) -> Self {
+        match response {
+            TraceResponse::Received(resp) => Self {
+                ttl: resp.ttl,
+                status: HopStatus::Received,
+                address: Some(resp.destination),
+                rtt: Some(resp.ping.as_secs_f64() * 1000.0),
+                id: Some(resp.sent.id),
+                checksum: Some(resp.sent.checksum),
+                nat_id: resp.nat_id().filter(|nat_id| *nat_id != 0),
+                reply_ttl: Some(resp.reply_ttl).filter(|reply_ttl| *reply_ttl != 0),
+                return_hops: resp.return_hops(),
+                asymmetry: resp.asymmetry(),
+                modifications: &resp.modifications,
+            },
+            TraceResponse::TimedOut(sent) => Self {
+                ttl: sent.ttl,
+                status: HopStatus::TimedOut,
+                address: None,
+                rtt: None,
+                id: Some(sent.id),
+                checksum: Some(sent.checksum),
+                nat_id: None,
+                reply_ttl: None,
+                return_hops: None,
+                asymmetry: None,
+                modifications: &[],
+            },
+            TraceResponse::Masked(ttl) => Self {
+                ttl: *ttl,
+                status: HopStatus::Masked,
+                address: None,
+                rtt: None,
+                id: None,
+                checksum: None,
+                nat_id: None,
+                reply_ttl: None,
+                return_hops: None,
+                asymmetry: None,
+                modifications: &[],
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a TraceFlow> for Flow<'a> {
+    fn from(flow: &'a TraceFlow) -> Self {
+        Self {
+            flowhash: flow.flowhash,
+            source: flow.source,
+            destination: flow.destination,
+            hops: flow.responses.iter().map(Hop::from).collect(),
+            joined: flow.joined,
+            middleboxes: flow.middleboxes(),
+            responses: &flow.responses,
+        }
+    }
+}
+
+impl TraceData {
+    /// Render all flows, hops and the graph as a versioned json document
+    pub fn to_json(&self) -> Result<String, TracerouteError> {
+        let graph = self.graph();
+        let document = Document {
+            schema: "hollister-traceroute",
+            version: JSON_SCHEMA_VERSION,
+            options: self.options(),
+            flows: self.flows().iter().map(Flow::from).collect(),
+            graph: Graph {
+                nodes: graph.nodes().collect(),
+                edges: graph
+                    .all_edges()
+                    .map(|(from, to, edge)| GraphEdge {
+                        from,
+                        to,
+                        edge: edge.clone(),
+                    })
+                    .collect(),
+            },
+            aliases: self.aliases(),
+            warnings: self.anomalies(),
+        };
+
+        serde_json::to_string_pretty(&document).map_err(TracerouteError::Json)
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt::Write;
+
+use super::ExportOptions;
+use super::view::{GraphView, NodeKind};
+use crate::trace::TraceData;
+
+impl TraceData {
+    /// Render the graph as a Mermaid flowchart
+    pub fn to_mermaid(&self, options: &ExportOptions) -> String {
+        let view = GraphView::new(self, options);
+        let mut out = String::from("graph TB\n");
+
+        for (id, node) in view.nodes.iter().enumerate() {
+            // Mermaid doesn't allow quotes inside of labels
+            let label = node.label.replace('"', "#quot;");
+            let _ = match node.kind {
+                NodeKind::Source => writeln!(out, "    n{}[[\"{}\"]]:::source", id, label),
+                NodeKind::Hop => writeln!(out, "    n{}([\"{}\"]):::hop", id, label),
+                NodeKind::Hidden => writeln!(out, "    n{}{{{{\"{}\"}}}}:::hidden", id, label),
+                NodeKind::Masked => writeln!(out, "    n{}[/\"{}\"/]:::masked", id, label),
+            };
+            if node.nat {
+                let _ = writeln!(out, "    class n{} nat", id);
+            }
+        }
+
+        // Warnings stand apart from the path as notes
+        for (id, anomaly) in self.anomalies().iter().enumerate() {
+            let warning = anomaly.to_string().replace('"', "#quot;");
+            let _ = writeln!(out, "    w{}[\"Warning: {}\"]:::warning", id, warning);
+        }
+
+        for edge in &view.edges {
+            // Draw links into nodes we know nothing about as dotted
+            let arrow = match view.nodes[edge.to].kind {
+                NodeKind::Hidden | NodeKind::Masked => "-.->",
+                NodeKind::Source | NodeKind::Hop => "-->",
+            };
+            let _ = writeln!(
+                out,
+                "    n{} {}|\"{}\"| n{}",
+                edge.from,
+                arrow,
+                edge.label(),
+                edge.to
+            );
+        }
+
+        out.push_str("    classDef source stroke-width:3px\n");
+        out.push_str("    classDef hop fill:#fff\n");
+        out.push_str("    classDef hidden stroke-dasharray: 5 5,fill:#eee\n");
+        out.push_str("    classDef masked stroke-dasharray: 2 2,fill:#ddd,color:#666\n");
+        out.push_str("    classDef nat stroke:#d55e00,stroke-width:2px\n");
+        out.push_str("    classDef warning fill:#fff3cd,stroke:#d55e00\n");
+        out
+    }
+}
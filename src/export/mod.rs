@@ -0,0 +1,49 @@
+mod dot;
+mod error;
+mod format;
+mod graphml;
+mod html;
+#[cfg(feature = "serde")]
+mod json;
+mod mermaid;
+mod view;
+
+pub use format::Format;
+
+use crate::TracerouteError;
+use crate::trace::TraceData;
+
+/// Tweaks to how the graph formats are rendered
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    /// Draw consecutive hops without a reply as a single node
+    pub collapse_hidden: bool,
+    /// Draw the interfaces of a router as a single node when aliases were resolved
+    pub routers: bool,
+}
+
+impl TraceData {
+    /// Render the collected data in the requested format
+    pub fn render(&self, format: Format) -> Result<String, TracerouteError> {
+        self.render_with(format, &ExportOptions::default())
+    }
+
+    /// Render the collected data in the requested format with extra options
+    pub fn render_with(
+        &self,
+        format: Format,
+        options: &ExportOptions,
+    ) -> Result<String, TracerouteError> {
+        match format {
+            Format::Text => Ok(format!("{}", self)),
+            Format::Dot => Ok(self.to_dot(options)),
+            #[cfg(feature = "serde")]
+            Format::Json => self.to_json(),
+            #[cfg(not(feature = "serde"))]
+            Format::Json => Err(TracerouteError::UnsupportedFormat(format)),
+            Format::Mermaid => Ok(self.to_mermaid(options)),
+            Format::GraphMl => Ok(self.to_graphml(options)),
+            Format::Html => Ok(self.to_html(options)),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::ExportOptions;
+use crate::Node;
+use crate::middlebox::MiddleboxKind;
+use crate::prelude::{Flowhash, TTL};
+use crate::trace::{TraceData, TraceResponse};
+
+/// How a node should be drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Machine the probes were sent from
+    Source,
+    /// Machine which replied to a probe
+    Hop,
+    /// No reply was received
+    Hidden,
+    /// Intentionally not probed
+    Masked,
+}
+
+impl NodeKind {
+    /// Name used for css classes and graph attributes
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Hop => "hop",
+            Self::Hidden => "hidden",
+            Self::Masked => "masked",
+        }
+    }
+}
+
+/// Node as seen by the exporters
+#[derive(Clone, Debug)]
+pub struct ViewNode {
+    pub kind: NodeKind,
+    pub label: String,
+    /// Reverse DNS name of the address
+    pub hostname: Option<String>,
+    /// Every latency measured for the node
+    pub rtts: Vec<Duration>,
+    /// First hop behind a NAT in any of the flows
+    pub nat: bool,
+}
+
+impl ViewNode {
+    /// Replies and min/avg/max latency summary
+    pub fn stats(&self) -> Option<String> {
+        let min = self.rtts.iter().min()?;
+        let max = self.rtts.iter().max()?;
+        let avg = self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32;
+        let replies = match self.rtts.len() {
+            1 => "1 reply".to_string(),
+            n => format!("{} replies", n),
+        };
+        Some(format!("{}, {:.2?}/{:.2?}/{:.2?}", replies, min, avg, max))
+    }
+}
+
+/// Lineage between two nodes merged across every flow which took it
+#[derive(Clone, Debug)]
+pub struct ViewEdge {
+    pub from: usize,
+    pub to: usize,
+    /// Distance of the `to` node
+    pub ttl: TTL,
+    /// Latency of the `to` node for each flow
+    pub rtts: Vec<(Flowhash, Duration)>,
+    /// Flows which traversed this edge
+    pub flows: Vec<Flowhash>,
+}
+
+impl ViewEdge {
+    /// Average latency of the `to` node if it ever replied
+    pub fn rtt(&self) -> Option<Duration> {
+        if self.rtts.is_empty() {
+            return None;
+        }
+        let total = self.rtts.iter().map(|(_flowhash, rtt)| *rtt).sum::<Duration>();
+        Some(total / self.rtts.len() as u32)
+    }
+
+    /// Latency of the `to` node as seen by a single flow
+    pub fn flow_rtt(&self, flowhash: Flowhash) -> Option<Duration> {
+        self.rtts
+            .iter()
+            .find(|(flow, _rtt)| *flow == flowhash)
+            .map(|(_flowhash, rtt)| *rtt)
+    }
+
+    /// Label shared by every exporter so the formats read the same
+    pub fn label(&self) -> String {
+        self.format_label(self.rtt())
+    }
+
+    /// Label of the edge as seen by a single flow
+    pub fn flow_label(&self, flowhash: Flowhash) -> String {
+        self.format_label(self.flow_rtt(flowhash))
+    }
+
+    fn format_label(&self, rtt: Option<Duration>) -> String {
+        match rtt {
+            Some(rtt) => format!("TTL {} · {:.2?}", self.ttl, rtt),
+            None => format!("TTL {}", self.ttl),
+        }
+    }
+}
+
+/// Renderer independent representation of the collected graph
+///
+/// Nodes and edges are kept in the order they were first seen so the output of every exporter
+/// is stable between runs with the same results.
+#[derive(Clone, Debug, Default)]
+pub struct GraphView {
+    pub nodes: Vec<ViewNode>,
+    pub edges: Vec<ViewEdge>,
+    /// Every flow in the order they were processed
+    pub flows: Vec<Flowhash>,
+}
+
+impl GraphView {
+    pub fn new(data: &TraceData, options: &ExportOptions) -> Self {
+        let mut view = Self::default();
+        let mut node_index: HashMap<Node, usize> = HashMap::new();
+        let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+        // Collapsed runs of hidden hops by their first hop and length
+        let mut run_index: HashMap<(Node, usize), usize> = HashMap::new();
+
+        for flow in data.flows() {
+            view.flows.push(flow.flowhash);
+
+            let nats = flow
+                .middleboxes()
+                .into_iter()
+                .filter(|middlebox| middlebox.kind == MiddleboxKind::Nat)
+                .map(|middlebox| middlebox.before.1)
+                .collect::<Vec<_>>();
+
+            let source = Node::Hop(flow.source);
+            let source = view.node(&mut node_index, data, source, NodeKind::Source);
+            // Joined flows start part way along, the hops up to them are drawn by other flows
+            let mut prev = (!flow.joined).then_some(source);
+
+            // Hidden hops already folded into the run before them
+            let mut folded = 0;
+            for (position, response) in flow.responses.iter().enumerate() {
+                if folded > 0 {
+                    folded -= 1;
+                    continue;
+                }
+                let (node, kind, rtt) = match response {
+                    TraceResponse::Received(resp) => {
+                        (Node::Hop(resp.destination), NodeKind::Hop, Some(resp.ping))
+                    }
+                    TraceResponse::TimedOut(sent) => (
+                        Node::hidden(flow.source, flow.flowhash, &flow.responses, sent.ttl),
+                        NodeKind::Hidden,
+                        None,
+                    ),
+                    TraceResponse::Masked(ttl) => (Node::Masked(*ttl), NodeKind::Masked, None),
+                };
+
+                // Length of the run of hidden hops this one starts, counted along this flow
+                let run = match options.collapse_hidden && kind == NodeKind::Hidden {
+                    true => flow.responses[position..]
+                        .iter()
+                        .take_while(|response| matches!(response, TraceResponse::TimedOut(_)))
+                        .count(),
+                    false => 1,
+                };
+
+                let next = match node {
+                    // Flows share the run only when they agree on where it starts and its length
+                    _ if run > 1 => {
+                        folded = run - 1;
+                        *run_index.entry((node, run)).or_insert_with(|| {
+                            view.nodes.push(ViewNode {
+                                kind,
+                                label: format!("{} hops without reply", run),
+                                hostname: None,
+                                rtts: Vec::new(),
+                                nat: false,
+                            });
+                            view.nodes.len() - 1
+                        })
+                    }
+                    Node::Hop(address) => {
+                        let next = view.hop(&mut node_index, data, options, address);
+                        view.nodes[next].nat |= nats.contains(&address);
+                        next
+                    }
+                    _ => view.node(&mut node_index, data, node, kind),
+                };
+
+                if let Some(rtt) = rtt {
+                    view.nodes[next].rtts.push(rtt);
+                }
+
+                // The same machine answering several ttls in a row isn't a new hop
+                let from = match prev {
+                    Some(from) if from != next => from,
+                    Some(_from) => continue,
+                    None => {
+                        prev = Some(next);
+                        continue;
+                    }
+                };
+
+                let index = *edge_index.entry((from, next)).or_insert_with(|| {
+                    view.edges.push(ViewEdge {
+                        from,
+                        to: next,
+                        ttl: response.get_distance(),
+                        rtts: Vec::new(),
+                        flows: Vec::new(),
+                    });
+                    view.edges.len() - 1
+                });
+                let edge = &mut view.edges[index];
+                if let Some(rtt) = rtt {
+                    edge.rtts.push((flow.flowhash, rtt));
+                }
+                if !edge.flows.contains(&flow.flowhash) {
+                    edge.flows.push(flow.flowhash);
+                }
+
+                prev = Some(next);
+            }
+        }
+
+        view
+    }
+
+    // Get the index of the node of an interface, shared by every interface of its router when
+    // drawing routers
+    fn hop(
+        &mut self,
+        index: &mut HashMap<Node, usize>,
+        data: &TraceData,
+        options: &ExportOptions,
+        address: IpAddr,
+    ) -> usize {
+        let router = data
+            .aliases()
+            .filter(|_aliases| options.routers)
+            .and_then(|aliases| aliases.router(&address));
+        let router = match router {
+            Some(router) => router,
+            None => return self.node(index, data, Node::Hop(address), NodeKind::Hop),
+        };
+
+        let node = Node::Hop(router.address());
+        let seen = index.contains_key(&node);
+        let next = self.node(index, data, node, NodeKind::Hop);
+        if !seen {
+            self.nodes[next].label = router
+                .interfaces
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<String>>()
+                .join(", ");
+        }
+        next
+    }
+
+    // Get the index of a node adding it when unseen
+    fn node(
+        &mut self,
+        index: &mut HashMap<Node, usize>,
+        data: &TraceData,
+        node: Node,
+        kind: NodeKind,
+    ) -> usize {
+        *index.entry(node).or_insert_with(|| {
+            let hostname = match node {
+                Node::Hop(ip) => data.hostname(&ip).cloned(),
+                _ => None,
+            };
+            self.nodes.push(ViewNode {
+                kind,
+                label: format!("{}", node),
+                hostname,
+                rtts: Vec::new(),
+                nat: false,
+            });
+            self.nodes.len() - 1
+        })
+    }
+}
+
+/// Escape text for use within xml and html documents
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape text for use within a quoted dot string
+pub fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quote text as a json string
+pub fn quote_json(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            // Keep the output safe to embed in a <script> tag
+            '<' => quoted.push_str("\\u003c"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
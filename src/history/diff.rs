@@ -0,0 +1,271 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::{RunId, StoredRun};
+use crate::prelude::TTL;
+
+/// Smallest latency increase reported as a regression by default
+pub const DEFAULT_RTT_REGRESSION: Duration = Duration::from_millis(20);
+
+/// A single difference between two runs of a path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HopChange {
+    /// Address replied in the newer run only
+    Added { ttl: TTL, address: IpAddr },
+    /// Address replied in the older run only
+    Removed { ttl: TTL, address: IpAddr },
+    /// Address replied at a different distance
+    TtlShift { address: IpAddr, from: TTL, to: TTL },
+    /// Address got slower by more than the threshold
+    RttRegression {
+        ttl: TTL,
+        address: IpAddr,
+        before: Duration,
+        after: Duration,
+    },
+}
+
+impl fmt::Display for HopChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Added { ttl, address } => write!(f, "+ {:>2}. {}", ttl, address),
+            Self::Removed { ttl, address } => write!(f, "- {:>2}. {}", ttl, address),
+            Self::TtlShift { address, from, to } => {
+                write!(f, "~ {} moved from TTL {} to TTL {}", address, from, to)
+            }
+            Self::RttRegression {
+                ttl,
+                address,
+                before,
+                after,
+            } => write!(
+                f,
+                "! {:>2}. {} latency {:.2?} -> {:.2?}",
+                ttl, address, before, after
+            ),
+        }
+    }
+}
+
+/// Differences between two stored runs of the same path
+#[derive(Clone, Debug)]
+pub struct PathDiff {
+    pub before: RunId,
+    pub after: RunId,
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub changes: Vec<HopChange>,
+}
+
+impl PathDiff {
+    /// Compare two runs reporting latency increases larger than `rtt_regression`
+    pub fn between(before: &StoredRun, after: &StoredRun, rtt_regression: Duration) -> Self {
+        let mut changes = Vec::new();
+        let old = before.addresses();
+        let new = after.addresses();
+
+        for address in &old {
+            if !new.contains(address) {
+                let ttl = before.ttl_of(*address).unwrap_or_default();
+                changes.push(HopChange::Removed {
+                    ttl,
+                    address: *address,
+                });
+            }
+        }
+
+        for address in &new {
+            let to = after.ttl_of(*address).unwrap_or_default();
+            let from = match before.ttl_of(*address) {
+                Some(ttl) => ttl,
+                None => {
+                    changes.push(HopChange::Added {
+                        ttl: to,
+                        address: *address,
+                    });
+                    continue;
+                }
+            };
+
+            if from != to {
+                changes.push(HopChange::TtlShift {
+                    address: *address,
+                    from,
+                    to,
+                });
+            }
+
+            if let (Some(rtt_before), Some(rtt_after)) =
+                (before.rtt_of(*address), after.rtt_of(*address))
+                && rtt_after > rtt_before + rtt_regression
+            {
+                changes.push(HopChange::RttRegression {
+                    ttl: to,
+                    address: *address,
+                    before: rtt_before,
+                    after: rtt_after,
+                });
+            }
+        }
+
+        Self {
+            before: before.id,
+            after: after.id,
+            source: after.source,
+            destination: after.destination,
+            changes,
+        }
+    }
+
+    /// Whether the hops taken changed. Latency regressions alone don't count
+    pub fn path_changed(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|change| !matches!(change, HopChange::RttRegression { .. }))
+    }
+}
+
+impl fmt::Display for PathDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} -> {} run {} vs run {}",
+            self.source, self.destination, self.before, self.after
+        )?;
+        if self.changes.is_empty() {
+            return writeln!(f, "  no changes");
+        }
+        for change in &self.changes {
+            writeln!(f, "  {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{HopStatus, StoredHop};
+    use std::net::Ipv4Addr;
+    use std::time::SystemTime;
+
+    const fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    // Hops as (ttl, last octet of the replying address, rtt in ms). Timeouts have no address
+    fn run(id: RunId, hops: &[(TTL, Option<u8>, u64)]) -> StoredRun {
+        let hops = hops
+            .iter()
+            .map(|(ttl, address, rtt)| StoredHop {
+                ttl: *ttl,
+                status: match address {
+                    Some(_) => HopStatus::Received,
+                    None => HopStatus::TimedOut,
+                },
+                address: address.map(ip),
+                rtt: address.map(|_| ms(*rtt)),
+            })
+            .collect();
+        StoredRun {
+            id,
+            started_at: SystemTime::UNIX_EPOCH,
+            source: ip(100),
+            destination: ip(200),
+            protocol: "UDP".to_string(),
+            flowhash: 0,
+            hops,
+        }
+    }
+
+    #[test]
+    fn identical_runs_have_no_changes() {
+        let hops = [(1, Some(1), 5), (2, None, 0), (3, Some(3), 9)];
+        let diff = PathDiff::between(&run(1, &hops), &run(2, &hops), DEFAULT_RTT_REGRESSION);
+        assert!(diff.changes.is_empty());
+        assert!(!diff.path_changed());
+        assert_eq!(
+            diff.to_string(),
+            "192.0.2.100 -> 192.0.2.200 run 1 vs run 2\n  no changes\n"
+        );
+    }
+
+    #[test]
+    fn swapped_hops_are_removed_and_added() {
+        let before = run(1, &[(1, Some(1), 5), (2, Some(2), 5)]);
+        let after = run(2, &[(1, Some(1), 5), (2, Some(9), 5)]);
+        let diff = PathDiff::between(&before, &after, DEFAULT_RTT_REGRESSION);
+        assert_eq!(
+            diff.changes,
+            vec![
+                HopChange::Removed {
+                    ttl: 2,
+                    address: ip(2)
+                },
+                HopChange::Added {
+                    ttl: 2,
+                    address: ip(9)
+                },
+            ]
+        );
+        assert!(diff.path_changed());
+    }
+
+    #[test]
+    fn moved_hops_are_shifted() {
+        let before = run(1, &[(1, Some(1), 5), (2, Some(2), 5)]);
+        // A hop went missing in front, the first sighting of a repeated address counts
+        let after = run(
+            2,
+            &[
+                (1, None, 0),
+                (2, Some(1), 5),
+                (3, Some(2), 5),
+                (4, Some(2), 5),
+            ],
+        );
+        let diff = PathDiff::between(&before, &after, DEFAULT_RTT_REGRESSION);
+        assert_eq!(
+            diff.changes,
+            vec![
+                HopChange::TtlShift {
+                    address: ip(1),
+                    from: 1,
+                    to: 2
+                },
+                HopChange::TtlShift {
+                    address: ip(2),
+                    from: 2,
+                    to: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn only_latency_beyond_the_threshold_regresses() {
+        let before = run(1, &[(1, Some(1), 10), (2, Some(2), 10), (3, Some(3), 50)]);
+        let after = run(2, &[(1, Some(1), 30), (2, Some(2), 31), (3, Some(3), 5)]);
+        let diff = PathDiff::between(&before, &after, ms(20));
+        assert_eq!(
+            diff.changes,
+            vec![HopChange::RttRegression {
+                ttl: 2,
+                address: ip(2),
+                before: ms(10),
+                after: ms(31),
+            }]
+        );
+        // Getting slower isn't taking another path
+        assert!(!diff.path_changed());
+        assert_eq!(
+            diff.changes[0].to_string(),
+            "!  2. 192.0.2.2 latency 10.00ms -> 31.00ms"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod diff;
+mod run;
+mod store;
+
+pub use diff::{DEFAULT_RTT_REGRESSION, HopChange, PathDiff};
+pub use run::{HopStatus, StoredHop, StoredRun};
+pub use store::{HistoryStore, RunId};
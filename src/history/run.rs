@@ -0,0 +1,91 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use super::RunId;
+use crate::TracerouteError;
+use crate::prelude::{Flowhash, TTL};
+
+/// Outcome of a stored probe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HopStatus {
+    Received,
+    TimedOut,
+    Masked,
+}
+
+impl HopStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Received => "received",
+            Self::TimedOut => "timed_out",
+            Self::Masked => "masked",
+        }
+    }
+}
+
+impl FromStr for HopStatus {
+    type Err = TracerouteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let status = match s {
+            "received" => Self::Received,
+            "timed_out" => Self::TimedOut,
+            "masked" => Self::Masked,
+            _ => Err(TracerouteError::CorruptHistory("unknown hop status"))?,
+        };
+        Ok(status)
+    }
+}
+
+/// A single probe of a stored run
+#[derive(Clone, Debug)]
+pub struct StoredHop {
+    pub ttl: TTL,
+    pub status: HopStatus,
+    /// IP of the machine which responded
+    pub address: Option<IpAddr>,
+    pub rtt: Option<Duration>,
+}
+
+/// A round of a flow loaded from the [`HistoryStore`](super::HistoryStore)
+#[derive(Clone, Debug)]
+pub struct StoredRun {
+    pub id: RunId,
+    pub started_at: SystemTime,
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: String,
+    pub flowhash: Flowhash,
+    /// Hops ordered by TTL
+    pub hops: Vec<StoredHop>,
+}
+
+impl StoredRun {
+    /// First TTL the address replied at
+    pub fn ttl_of(&self, address: IpAddr) -> Option<TTL> {
+        self.hops
+            .iter()
+            .find(|hop| hop.address == Some(address))
+            .map(|hop| hop.ttl)
+    }
+
+    /// Latency of the address at its first TTL
+    pub fn rtt_of(&self, address: IpAddr) -> Option<Duration> {
+        self.hops
+            .iter()
+            .find(|hop| hop.address == Some(address))
+            .and_then(|hop| hop.rtt)
+    }
+
+    /// Every address which replied in order of distance without repeats
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        for address in self.hops.iter().filter_map(|hop| hop.address) {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses
+    }
+}
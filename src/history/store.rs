@@ -0,0 +1,254 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::{HopStatus, StoredHop, StoredRun};
+use crate::TracerouteError;
+use crate::prelude::{Flowhash, Protocol};
+use crate::trace::{TraceData, TraceFlow, TraceResponse};
+
+/// Identifier of a run within the [`HistoryStore`]
+pub type RunId = i64;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    flowhash INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_by_flow ON runs (source, destination, protocol, flowhash);
+CREATE TABLE IF NOT EXISTS hops (
+    run_id INTEGER NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    ttl INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    address TEXT,
+    rtt_us INTEGER,
+    PRIMARY KEY (run_id, ttl)
+);
+";
+
+/// Trace results persisted to an embedded SQLite file
+///
+/// Every round of a flow is stored as a run keyed by source, destination, protocol and
+/// flowhash so runs of the same path can be compared with [`PathDiff`](super::PathDiff).
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    /// Open or create the store at the given path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TracerouteError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Store living only in memory. Mostly useful for testing
+    pub fn in_memory() -> Result<Self, TracerouteError> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Record the latest round of every flow in the data
+    pub fn record_data(&mut self, data: &TraceData) -> Result<Vec<RunId>, TracerouteError> {
+        let protocol = data.options().protocol;
+        data.flows()
+            .iter()
+            .map(|flow| self.record(flow, protocol))
+            .collect()
+    }
+
+    /// Record a single round of a flow
+    pub fn record(&mut self, flow: &TraceFlow, protocol: Protocol) -> Result<RunId, TracerouteError> {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (started_at, source, destination, protocol, flowhash)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                started_at,
+                flow.source.to_string(),
+                flow.destination.to_string(),
+                protocol.to_string(),
+                flow.flowhash,
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO hops (run_id, ttl, status, address, rtt_us)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for response in &flow.responses {
+                let hop = StoredHop::from(response);
+                insert.execute(params![
+                    run_id,
+                    hop.ttl,
+                    hop.status.as_str(),
+                    hop.address.map(|ip| ip.to_string()),
+                    hop.rtt.map(|rtt| rtt.as_micros() as i64),
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        debug!("Recorded run {} for {} -> {}", run_id, flow.source, flow.destination);
+        Ok(run_id)
+    }
+
+    /// Load a run and all of its hops
+    pub fn load(&self, run_id: RunId) -> Result<Option<StoredRun>, TracerouteError> {
+        let run = self
+            .conn
+            .query_row(
+                "SELECT id, started_at, source, destination, protocol, flowhash
+                 FROM runs WHERE id = ?1",
+                params![run_id],
+                |row| {
+                    Ok((
+                        row.get::<_, RunId>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, Flowhash>(5)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let (id, started_at, source, destination, protocol, flowhash) = match run {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+
+        let mut select = self.conn.prepare(
+            "SELECT ttl, status, address, rtt_us FROM hops WHERE run_id = ?1 ORDER BY ttl",
+        )?;
+        let hops = select
+            .query_map(params![id], |row| {
+                Ok((
+                    row.get::<_, u8>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            })?
+            .map(|row| {
+                let (ttl, status, address, rtt) = row?;
+                Ok(StoredHop {
+                    ttl,
+                    status: status.parse()?,
+                    address: address.map(|ip| parse_ip(&ip)).transpose()?,
+                    rtt: rtt.map(|us| Duration::from_micros(us as u64)),
+                })
+            })
+            .collect::<Result<Vec<StoredHop>, TracerouteError>>()?;
+
+        Ok(Some(StoredRun {
+            id,
+            started_at: UNIX_EPOCH + Duration::from_millis(started_at as u64),
+            source: parse_ip(&source)?,
+            destination: parse_ip(&destination)?,
+            protocol,
+            flowhash,
+            hops,
+        }))
+    }
+
+    /// Ids of the most recent runs of a path, newest first
+    pub fn recent_runs(
+        &self,
+        source: IpAddr,
+        destination: IpAddr,
+        protocol: Protocol,
+        flowhash: Flowhash,
+        limit: usize,
+    ) -> Result<Vec<RunId>, TracerouteError> {
+        let mut select = self.conn.prepare(
+            "SELECT id FROM runs
+             WHERE source = ?1 AND destination = ?2 AND protocol = ?3 AND flowhash = ?4
+             ORDER BY id DESC LIMIT ?5",
+        )?;
+        let ids = select
+            .query_map(
+                params![
+                    source.to_string(),
+                    destination.to_string(),
+                    protocol.to_string(),
+                    flowhash,
+                    limit as i64,
+                ],
+                |row| row.get::<_, RunId>(0),
+            )?
+            .collect::<Result<Vec<RunId>, rusqlite::Error>>()?;
+        Ok(ids)
+    }
+
+    /// The run recorded for the same path right before the given run
+    pub fn previous_run(&self, run: &StoredRun) -> Result<Option<StoredRun>, TracerouteError> {
+        let previous = self
+            .conn
+            .query_row(
+                "SELECT id FROM runs
+                 WHERE source = ?1 AND destination = ?2 AND protocol = ?3 AND flowhash = ?4
+                   AND id < ?5
+                 ORDER BY id DESC LIMIT 1",
+                params![
+                    run.source.to_string(),
+                    run.destination.to_string(),
+                    run.protocol,
+                    run.flowhash,
+                    run.id,
+                ],
+                |row| row.get::<_, RunId>(0),
+            )
+            .optional()?;
+
+        match previous {
+            Some(id) => self.load(id),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_ip(ip: &str) -> Result<IpAddr, TracerouteError> {
+    ip.parse().map_err(|_| TracerouteError::CorruptHistory("invalid ip address"))
+}
+
+impl From<&TraceResponse> for StoredHop {
+    fn from(response: &TraceResponse) -> Self {
+        match response {
+            TraceResponse::Received(resp) => Self {
+                ttl: resp.ttl,
+                status: HopStatus::Received,
+                address: Some(resp.destination),
+                rtt: Some(resp.ping),
+            },
+            TraceResponse::TimedOut(sent) => Self {
+                ttl: sent.ttl,
+                status: HopStatus::TimedOut,
+                address: None,
+                rtt: None,
+            },
+            TraceResponse::Masked(ttl) => Self {
+                ttl: *ttl,
+                status: HopStatus::Masked,
+                address: None,
+                rtt: None,
+            },
+        }
+    }
+}
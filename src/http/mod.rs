@@ -0,0 +1,6 @@
+//! Just enough HTTP/1.1 to serve a few local endpoints without pulling in a web framework
+mod request;
+mod response;
+
+pub use request::Request;
+pub use response::Response;
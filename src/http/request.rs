@@ -0,0 +1,188 @@
+use std::io::{self, BufRead, Read};
+
+// Refuse anything larger than this to keep a misbehaving client from eating memory
+const MAX_BODY: usize = 64 * 1024;
+const MAX_HEADERS: usize = 64;
+// Longest request or header line, line ending included
+const MAX_LINE: usize = 8 * 1024;
+
+/// Request line, headers and body of an incoming request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Read a single request off the connection
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut line = String::new();
+        read_line(reader, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+            _ => return Err(invalid("malformed request line")),
+        };
+        // The query string isn't used by any endpoint
+        let path = target.split('?').next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            if read_line(reader, &mut line)? == 0 {
+                return Err(invalid("connection closed within headers"));
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(invalid("too many headers"));
+            }
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed header"))?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+
+        let length = match request.header("content-length") {
+            Some(length) => length
+                .parse::<usize>()
+                .map_err(|_| invalid("malformed content-length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(invalid("body too large"));
+        }
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body)?;
+
+        Ok(request)
+    }
+
+    /// Value of a header. Names are matched case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _value)| header.eq_ignore_ascii_case(name))
+            .map(|(_header, value)| value.as_str())
+    }
+}
+
+// Read a single line into `line`, refusing to buffer more than MAX_LINE bytes of it
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    line.clear();
+    let read = reader.by_ref().take(MAX_LINE as u64).read_line(line)?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(invalid("line too long"));
+    }
+    Ok(read)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(raw: &[u8]) -> io::Result<Request> {
+        Request::read(&mut &raw[..])
+    }
+
+    fn rejected(raw: &[u8]) -> String {
+        let err = read(raw).expect_err("request should be refused");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn request_is_read_in_full() {
+        let request = read(
+            b"POST /trace?verbose=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbodyleft over",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/trace");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("CONTENT-LENGTH"), Some("4"));
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn bare_newlines_end_lines_too() {
+        let request = read(b"GET /metrics HTTP/1.1\nAccept: */*\n\n").unwrap();
+        assert_eq!(request.path, "/metrics");
+        assert_eq!(request.header("accept"), Some("*/*"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn lines_up_to_the_limit_are_read() {
+        let padding = "a".repeat(MAX_LINE - "GET / HTTP/1.1\n".len());
+        let raw = format!("GET /{} HTTP/1.1\n\n", padding);
+        assert_eq!(read(raw.as_bytes()).unwrap().path.len(), padding.len() + 1);
+    }
+
+    #[test]
+    fn long_lines_are_refused() {
+        let padding = "a".repeat(MAX_LINE);
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", padding);
+        assert_eq!(rejected(raw.as_bytes()), "line too long");
+
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", padding);
+        assert_eq!(rejected(raw.as_bytes()), "line too long");
+
+        // Without a line ending the rest is never buffered
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}", padding.repeat(100));
+        assert_eq!(rejected(raw.as_bytes()), "line too long");
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        assert_eq!(rejected(b""), "malformed request line");
+        assert_eq!(rejected(b"GET\r\n\r\n"), "malformed request line");
+        assert_eq!(
+            rejected(b"GET / HTTP/1.1\r\nHost: localhost\r\n"),
+            "connection closed within headers"
+        );
+        assert_eq!(
+            rejected(b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n"),
+            "malformed header"
+        );
+        assert_eq!(
+            rejected(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            "malformed content-length"
+        );
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(rejected(raw.as_bytes()), "too many headers");
+
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(rejected(raw.as_bytes()), "body too large");
+    }
+
+    #[test]
+    fn short_bodies_fail() {
+        let err = read(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -20,8 +20,8 @@
 //! use async_std::task::block_on;
 //!
 //! let mut traceroute = Traceroute::new(5)?;
-//! let source = traceroute.addresses().first().unwrap().clone();
 //! let destination = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+//! let source = traceroute.source_for(destination);
 //! let options = TraceOptions::default();
 //! let mut trace = traceroute.trace(source, destination, options)?;
 //!
@@ -42,17 +42,33 @@ extern crate log;
 extern crate petgraph;
 extern crate pnet;
 
+mod alias;
+mod analysis;
+mod discovery;
 mod edge;
+mod export;
+#[cfg(any(feature = "monitor", feature = "server"))]
+mod http;
+mod middlebox;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "monitor")]
+pub mod monitor;
 mod node;
 mod packet;
+mod pmtu;
 pub mod prelude;
 mod probe;
 mod protocol;
+#[cfg(feature = "server")]
+pub mod server;
 mod sockets;
+mod targets;
 mod trace;
 mod traceroute;
 mod utils;
+mod yarrp;
 
 pub use edge::Edge;
 pub use prelude::*;
-pub use node::Node;
+pub use node::{Anonymous, Node};
@@ -1,31 +1,56 @@
 #![doc = include_str!("../README.md")]
 
+mod alias;
+mod analysis;
+mod discovery;
 mod edge;
+mod export;
+#[cfg(any(feature = "monitor", feature = "server"))]
+mod http;
+mod middlebox;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "monitor")]
+pub mod monitor;
 mod node;
 mod options;
 mod trace;
 pub mod prelude;
 mod protocol;
+#[cfg(feature = "server")]
+pub mod server;
 mod probe;
 mod packet;
+mod pmtu;
 mod traceroute;
 mod sockets;
+mod targets;
 mod utils;
+mod yarrp;
 
 use std::fs::File;
 use std::io::prelude::*;
 pub use prelude::*;
 use crate::edge::Edge;
 use crate::node::Node;
+use crate::protocol::UdpParams;
 use async_std::task;
 use log::*;
 pub use options::Options;
+use pnet::packet::ip::IpNextHeaderProtocols;
 use std::io;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use structopt::StructOpt;
 
 fn main() -> Result<(), io::Error> {
     let options = Options::from_args();
+    if let Some(reason) = options.invalid() {
+        structopt::clap::Error::with_description(reason, structopt::clap::ErrorKind::ArgumentConflict)
+            .exit();
+    }
 
     stderrlog::new()
         .verbosity(options.verbose)
@@ -46,21 +71,47 @@ fn main() -> Result<(), io::Error> {
 }
 
 async fn app(options: Options) -> Result<(), TracerouteError> {
+    #[cfg(feature = "history")]
+    if let (Some(path), Some(runs)) = (&options.history, &options.diff_runs) {
+        let output = diff_stored_runs(path, runs[0], runs[1])?;
+        return write_output(&options.output_file, output);
+    }
+
+    #[cfg(feature = "server")]
+    if let Some(address) = options.serve {
+        return serve(&options, address);
+    }
+
     let targets = options.target_ips()?;
+    let format = options.format();
+
+    if options.yarrp {
+        return yarrp(&options, &targets, format);
+    }
 
     let Options {
         min_ttl,
         max_ttl,
         delay,
-        mask,
-        protocol,
-        dot,
-        output_file,
+        ref mask,
+        ref output_file,
+        no_dns,
+        collapse_hidden,
         ..
     } = options;
 
+    let dot = format != Format::Text;
+    let protocol = options.protocol();
+
     // Lock to ensure traceroute isn't running at the same time as another
-    let agent = Traceroute::new(delay)?;
+    let mut builder = Traceroute::builder()
+        .rate_limit(options.rate_limit())
+        .binding(options.binding());
+    // The destination answers TCP probes over TCP
+    if protocol == Protocol::TCP {
+        builder = builder.listen(IpNextHeaderProtocols::Tcp);
+    }
+    let agent = builder.build()?;
 
 
     let mut config = TraceOptions {
@@ -68,46 +119,379 @@ async fn app(options: Options) -> Result<(), TracerouteError> {
         max_ttl,
         delay,
         mask: Default::default(),
-        timeout: 300,
+        timeout: options.timeout,
+        adaptive_timeout: options.adaptive_timeout,
+        attempts: options.attempts,
+        deadline: options.deadline,
         protocol,
         dot,
+        size: options.size,
+        payload: options.pattern.unwrap_or_default(),
+        dscp: options.dscp,
+        ecn: options.ecn,
+        broken_nat: options.broken_nat,
+        ports: options.ports,
+        ..Default::default()
     };
 
-    // @TODO maybe a default source?
-    let source = agent.addresses().first().unwrap().clone();
-
     // Fill in mask from options
     if let Some(mask) = mask {
         for ttl in mask {
-            config.mask(ttl.clone());
+            config.mask(*ttl);
         }
     }
 
-    let mut traces = vec![];
-    for target in targets {
-        match target {
-            IpAddr::V4(_) => traces.push(agent.trace(source.clone(), target, config.clone())?),
-            IpAddr::V6(ip) => warn!("Skipped IPv6 target {}", ip),
+    // Ctrl-C stops tracing but still writes out what was found
+    let interrupted = Arc::new(AtomicBool::new(false));
+    handle_interrupts(agent.shutdown_handle(), interrupted.clone())?;
+
+    #[cfg(feature = "monitor")]
+    if options.monitor {
+        return monitor(&options, &agent, &targets, config).await;
+    }
+
+    if options.pmtu {
+        return pmtu(&options, &agent, &targets, config, format).await;
+    }
+
+    let mut data = TraceData::new(config.clone());
+
+    let result = if options.doubletree {
+        let targets = ipv4_targets(&targets);
+        let mut doubletree = Doubletree::new(DoubletreeOptions {
+            start_ttl: options.start_ttl,
+            batch_size: options.batch_size,
+        });
+        doubletree.discover(&agent, &targets, &mut data).await
+    } else {
+        trace_targets(&agent, &targets, config, &mut data).await
+    };
+    // Output whatever was collected before the failure
+    if let Err(err) = result {
+        warn!("Tracing stopped early: {}", err);
+    }
+    // Don't hold up the partial results any longer than needed
+    let interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Names are only shown in the graph formats
+    if dot && !no_dns && !interrupted {
+        data.resolve_hostnames();
+    }
+
+    if options.aliases && !interrupted {
+        let mut resolver = AliasResolver::new(AliasOptions::default());
+        if let Err(err) = data.resolve_aliases(&agent, &mut resolver).await {
+            warn!("Alias resolution stopped early: {}", err);
         }
+        info!("Alias resolution sent {} probes", resolver.probes_sent());
+    }
+
+    let export_options = ExportOptions {
+        collapse_hidden,
+        routers: options.router_level,
+    };
+    let output = data.render_with(format, &export_options)?;
+
+    #[cfg(feature = "history")]
+    let output = match options.history {
+        Some(ref path) => {
+            let diff = record_history(path, &data)?;
+            if options.diff { diff } else { output }
+        }
+        None => output,
+    };
+
+    write_output(output_file, output)
+}
+
+// Stream a record per reply as they arrive
+fn yarrp(options: &Options, targets: &[IpAddr], format: Format) -> Result<(), TracerouteError> {
+    let targets = ipv4_targets(targets)
+        .into_iter()
+        .filter_map(|target| match target {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_ip) => None,
+        })
+        .collect::<Vec<_>>();
+    let binding = options.binding();
+    let bound = binding.resolve()?;
+    // Every probe leaves from the source reaching the first target, all others were IPv6
+    let source = match targets.first() {
+        Some(target) => bound.source_for(IpAddr::V4(*target)),
+        None => return Err(TracerouteError::NoIpv6),
+    };
+    let source = match source {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_ip) => return Err(TracerouteError::NoIpv6),
+    };
+    let mut yarrp = Yarrp::new(
+        source,
+        YarrpOptions {
+            min_ttl: options.min_ttl,
+            max_ttl: options.max_ttl,
+            packets_per_second: options.rate_limit().packets_per_second,
+            ports: UdpParams {
+                source_port: options.src_port,
+                destination_port: options.dst_port,
+            },
+            ..Default::default()
+        },
+    );
+    yarrp.set_binding(binding);
+
+    // Ctrl-C stops probing but still writes out what was found
+    let interrupted = Arc::new(AtomicBool::new(false));
+    handle_interrupts(yarrp.shutdown_handle(), interrupted)?;
+
+    let mut output: Box<dyn Write> = match options.output_file {
+        Some(ref file) => Box::new(io::BufWriter::new(File::create(file)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut result = Ok(());
+    let summary = yarrp.run(&targets, |record| {
+        if result.is_err() {
+            return;
+        }
+        result = match format {
+            #[cfg(feature = "serde")]
+            Format::Json => serde_json::to_string(&record)
+                .map_err(TracerouteError::Json)
+                .and_then(|line| writeln!(output, "{}", line).map_err(TracerouteError::Io)),
+            _ => writeln!(output, "{}", record).map_err(TracerouteError::Io),
+        };
+    });
+    // Keep what was written even when probing failed part way
+    output.flush()?;
+    summary?;
+    result?;
+    Ok(())
+}
+
+// Report the path MTU of every target one after the other
+async fn pmtu(
+    options: &Options,
+    agent: &Traceroute,
+    targets: &[IpAddr],
+    config: TraceOptions,
+    format: Format,
+) -> Result<(), TracerouteError> {
+    let mut discovery = PathMtu::new(PmtuOptions {
+        max_size: options.max_mtu,
+        ..Default::default()
+    });
 
-        // break after the first trace if we are not building a graph
-        if !dot {
-            break;
+    let mut output = String::new();
+    for target in ipv4_targets(targets) {
+        let source = agent.source_for(target);
+        let report = match discovery.discover(agent, source, target, config).await {
+            Ok(report) => report,
+            // Interrupted, keep the reports of the targets already done
+            Err(TracerouteError::Cancelled) => break,
+            Err(err) => return Err(err),
+        };
+        match format {
+            #[cfg(feature = "serde")]
+            Format::Json => {
+                output.push_str(&serde_json::to_string(&report).map_err(TracerouteError::Json)?);
+                output.push('\n');
+            }
+            _ => output.push_str(&report.to_string()),
         }
     }
+    info!("Path MTU discovery sent {} probes", discovery.probes_sent());
 
+    write_output(&options.output_file, output)
+}
 
-    let mut data = TraceData::new(config.clone());
-    let _ = data.process(traces).await;
+// A single round towards every target at the same time
+async fn trace_targets(
+    agent: &Traceroute,
+    targets: &[IpAddr],
+    config: TraceOptions,
+    data: &mut TraceData,
+) -> Result<(), TracerouteError> {
+    let traces = ipv4_targets(targets)
+        .into_iter()
+        .map(|target| agent.trace(agent.source_for(target), target, config))
+        .collect::<Result<Vec<Trace>, TracerouteError>>()?;
+    info!("Tracing {} targets", traces.len());
+
+    data.process(traces).await
+}
+
+// Shut down on the first Ctrl-C, quit right away on the second
+#[cfg(unix)]
+fn handle_interrupts(
+    shutdown: ShutdownHandle,
+    interrupted: Arc<AtomicBool>,
+) -> Result<(), TracerouteError> {
+    use signal_hook::consts::SIGINT;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGINT])?;
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for _signal in signals.forever() {
+                if interrupted.swap(true, Ordering::SeqCst) {
+                    std::process::exit(130);
+                }
+                warn!("Interrupted, writing out what was found so far. Ctrl-C again to quit");
+                shutdown.shutdown();
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_interrupts(
+    _shutdown: ShutdownHandle,
+    _interrupted: Arc<AtomicBool>,
+) -> Result<(), TracerouteError> {
+    Ok(())
+}
+
+// Only ipv4 can be traced for now
+fn ipv4_targets(targets: &[IpAddr]) -> Vec<IpAddr> {
+    targets
+        .iter()
+        .filter(|target| match target {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(ip) => {
+                warn!("Skipped IPv6 target {}", ip);
+                false
+            }
+        })
+        .copied()
+        .collect()
+}
+
+// Trace the targets forever raising alerts along the way
+#[cfg(feature = "monitor")]
+async fn monitor(
+    options: &Options,
+    agent: &Traceroute,
+    targets: &[IpAddr],
+    config: TraceOptions,
+) -> Result<(), TracerouteError> {
+    use crate::monitor::{
+        AsnTable, FileSink, Metrics, Monitor, MonitorOptions, StdoutSink, WebhookSink,
+    };
+    use std::time::Duration;
+
+    let targets = targets
+        .iter()
+        .filter(|target| target.is_ipv4())
+        .copied()
+        .collect::<Vec<IpAddr>>();
+
+    let monitor_options = MonitorOptions {
+        interval: Duration::from_secs(options.interval),
+        learning_rounds: options.learning_rounds,
+        loss_threshold: options.loss_threshold,
+        latency_jump: Duration::from_millis(options.latency_jump),
+    };
+    let mut monitor = Monitor::new(agent, &targets, config, monitor_options)?;
+
+    if let Some(url) = &options.alert_webhook {
+        monitor.add_sink(Box::new(WebhookSink::new(url)?));
+    }
+    if let Some(path) = &options.alert_file {
+        monitor.add_sink(Box::new(FileSink::open(path)?));
+    }
+    if options.alert_webhook.is_none() && options.alert_file.is_none() {
+        monitor.add_sink(Box::new(StdoutSink));
+    }
+    if let Some(path) = &options.asn_db {
+        monitor.set_asn_table(AsnTable::load(path)?);
+    }
+    if let Some(address) = options.metrics {
+        let metrics = Arc::new(Metrics::new(agent.stats()));
+        let _ = metrics.clone().serve(address)?;
+        monitor.set_metrics(metrics);
+    }
+
+    info!("Monitoring {} targets every {}s", targets.len(), options.interval);
+    match monitor.run().await {
+        // Alerts went out as they were raised, there is nothing left to write
+        Err(TracerouteError::Cancelled) => Ok(()),
+        result => result,
+    }
+}
+
+// Keep the sockets open and trace whatever clients ask for
+#[cfg(feature = "server")]
+fn serve(options: &Options, address: std::net::SocketAddr) -> Result<(), TracerouteError> {
+    use crate::server::{Server, ServerOptions};
+
+    // Clients may ask for TCP probes which the destination answers over TCP
+    let agent = Traceroute::builder()
+        .rate_limit(options.rate_limit())
+        .binding(options.binding())
+        .listen(IpNextHeaderProtocols::Tcp)
+        .build()?;
+    let server = Server::new(ServerOptions {
+        address,
+        max_concurrent: options.max_concurrent,
+        rate_limit: options.rate_limit,
+    });
+    server.run(&agent)
+}
+
+// Record all flows and describe how they changed since the previous run
+#[cfg(feature = "history")]
+fn record_history(path: &PathBuf, data: &TraceData) -> Result<String, TracerouteError> {
+    use crate::history::{DEFAULT_RTT_REGRESSION, HistoryStore, PathDiff};
+
+    let mut store = HistoryStore::open(path)?;
+    let mut output = String::new();
+
+    for run_id in store.record_data(data)? {
+        let run = match store.load(run_id)? {
+            Some(run) => run,
+            None => continue,
+        };
+        match store.previous_run(&run)? {
+            Some(previous) => {
+                let diff = PathDiff::between(&previous, &run, DEFAULT_RTT_REGRESSION);
+                output.push_str(&format!("{}", diff));
+            }
+            None => output.push_str(&format!(
+                "{} -> {} run {} is the first recorded run\n",
+                run.source, run.destination, run.id
+            )),
+        }
+    }
+
+    Ok(output)
+}
+
+// Describe how a path changed between two recorded runs
+#[cfg(feature = "history")]
+fn diff_stored_runs(path: &PathBuf, before: i64, after: i64) -> Result<String, TracerouteError> {
+    use crate::history::{DEFAULT_RTT_REGRESSION, HistoryStore, PathDiff};
+
+    let store = HistoryStore::open(path)?;
+    let load = |run_id| {
+        store.load(run_id)?.ok_or_else(|| {
+            let err = io::Error::new(io::ErrorKind::NotFound, format!("No run {}", run_id));
+            TracerouteError::Io(err)
+        })
+    };
+    let diff = PathDiff::between(&load(before)?, &load(after)?, DEFAULT_RTT_REGRESSION);
+
+    Ok(format!("{}", diff))
+}
 
+fn write_output(output_file: &Option<PathBuf>, output: String) -> Result<(), TracerouteError> {
     match output_file {
         None => io::stdout()
             .lock()
-            .write_all(format!("{}",data).as_bytes())
+            .write_all(output.as_bytes())
             .map_err(TracerouteError::Io),
         Some(file) => {
             let mut handle =File::create(file)?;
-            let result = handle.write_all(format!("{}", data).as_bytes())?;
+            let result = handle.write_all(output.as_bytes())?;
             Ok(result)
         },
     }?;
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::prelude::TTL;
+use crate::probe::HeaderChange;
+use crate::trace::TraceResponse;
+
+/// What a middlebox was taken for from the headers it changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MiddleboxKind {
+    /// Rewrote the source address or ports
+    Nat,
+    /// Rewrote the TCP sequence number or window, as a proxy splitting the connection does
+    Proxy,
+    /// Rewrote any other field, as firewalls normalizing traffic or clamping the MSS do
+    Normalizer,
+}
+
+impl MiddleboxKind {
+    /// Kind of box which would make the change
+    pub fn of(change: &HeaderChange) -> Self {
+        match change {
+            HeaderChange::SourceAddress { .. }
+            | HeaderChange::SourcePort { .. }
+            | HeaderChange::DestinationPort { .. }
+            // Left behind by NATs restoring the quoted headers
+            | HeaderChange::IpId { .. }
+            | HeaderChange::Checksum { .. } => Self::Nat,
+            HeaderChange::Sequence { .. } | HeaderChange::Window { .. } => Self::Proxy,
+            _ => Self::Normalizer,
+        }
+    }
+}
+
+impl fmt::Display for MiddleboxKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Nat => write!(f, "NAT"),
+            Self::Proxy => write!(f, "Proxy"),
+            Self::Normalizer => write!(f, "Normalizer"),
+        }
+    }
+}
+
+/// A box between two hops which changed the probes passing through it
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Middlebox {
+    pub kind: MiddleboxKind,
+    /// Last hop which quoted the probe without the changes. None when the first hop already
+    /// saw them
+    pub after: Option<(TTL, IpAddr)>,
+    /// First hop which quoted the probe with the changes
+    pub before: (TTL, IpAddr),
+    /// Changes seen at `before`
+    pub changes: Vec<HeaderChange>,
+}
+
+impl Middlebox {
+    /// Locate the boxes changing the probes of a single flow
+    ///
+    /// A change is pinned between the last hop quoting the probe untouched and the first one
+    /// quoting it changed. Changes carrying on downstream are put down to the same box. A hop
+    /// quoting its own mangled copy of the probe looks the same as a box in front of it.
+    pub fn infer(responses: &[TraceResponse]) -> Vec<Self> {
+        let mut middleboxes: Vec<Self> = Vec::new();
+        let mut last_untouched: Vec<(MiddleboxKind, (TTL, IpAddr))> = Vec::new();
+
+        let mut received = responses
+            .iter()
+            .filter_map(|response| match response {
+                TraceResponse::Received(resp) => Some(resp),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        received.sort_by_key(|resp| resp.ttl);
+
+        for resp in received {
+            let hop = (resp.ttl, resp.destination);
+            for kind in [
+                MiddleboxKind::Nat,
+                MiddleboxKind::Proxy,
+                MiddleboxKind::Normalizer,
+            ] {
+                let changes = resp
+                    .modifications
+                    .iter()
+                    .filter(|change| MiddleboxKind::of(change) == kind)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let known = middleboxes.iter().any(|middlebox| middlebox.kind == kind);
+
+                if changes.is_empty() {
+                    if !known {
+                        last_untouched.retain(|(untouched, _hop)| *untouched != kind);
+                        last_untouched.push((kind, hop));
+                    }
+                    continue;
+                }
+                if known {
+                    continue;
+                }
+
+                let after = last_untouched
+                    .iter()
+                    .find(|(untouched, _hop)| *untouched == kind)
+                    .map(|(_kind, hop)| *hop);
+                middleboxes.push(Self {
+                    kind,
+                    after,
+                    before: hop,
+                    changes,
+                });
+            }
+        }
+
+        middleboxes
+    }
+}
+
+impl fmt::Display for Middlebox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (before_ttl, before) = self.before;
+        match self.after {
+            Some((after_ttl, after)) => write!(
+                f,
+                "{} between {} (hop {}) and {} (hop {})",
+                self.kind, after, after_ttl, before, before_ttl
+            )?,
+            None => write!(f, "{} before {} (hop {})", self.kind, before, before_ttl)?,
+        }
+        let changes = self
+            .changes
+            .iter()
+            .map(HeaderChange::to_string)
+            .collect::<Vec<_>>();
+        write!(f, ": {}", changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    fn nat() -> HeaderChange {
+        HeaderChange::SourceAddress {
+            sent: ip(100),
+            received: ip(200),
+        }
+    }
+
+    fn clamp() -> HeaderChange {
+        HeaderChange::Mss {
+            sent: Some(1460),
+            received: Some(1360),
+        }
+    }
+
+    // A reply from hop `ttl` quoting the probe with `modifications`
+    fn hop(ttl: TTL, modifications: Vec<HeaderChange>) -> TraceResponse {
+        let mut resp = fake::received(ttl, ip(ttl), Duration::from_millis(1));
+        resp.modifications = modifications;
+        TraceResponse::Received(resp)
+    }
+
+    #[test]
+    fn untouched_paths_have_no_middleboxes() {
+        let responses = [hop(1, vec![]), hop(2, vec![])];
+        assert!(Middlebox::infer(&responses).is_empty());
+    }
+
+    #[test]
+    fn changes_are_pinned_after_the_last_untouched_hop() {
+        let responses = [
+            hop(1, vec![]),
+            hop(2, vec![]),
+            hop(3, vec![nat()]),
+            // Carried on downstream from the same box
+            hop(4, vec![nat()]),
+        ];
+        assert_eq!(
+            Middlebox::infer(&responses),
+            vec![Middlebox {
+                kind: MiddleboxKind::Nat,
+                after: Some((2, ip(2))),
+                before: (3, ip(3)),
+                changes: vec![nat()],
+            }]
+        );
+    }
+
+    #[test]
+    fn changes_from_the_first_hop_have_nothing_in_front() {
+        let middleboxes = Middlebox::infer(&[hop(1, vec![clamp()])]);
+        assert_eq!(middleboxes[0].after, None);
+        assert_eq!(
+            middleboxes[0].to_string(),
+            "Normalizer before 192.0.2.1 (hop 1): mss 1460 -> 1360"
+        );
+    }
+
+    #[test]
+    fn kinds_are_pinned_on_their_own() {
+        let responses = [
+            hop(1, vec![]),
+            hop(2, vec![clamp()]),
+            hop(3, vec![clamp(), nat()]),
+        ];
+        let middleboxes = Middlebox::infer(&responses);
+        assert_eq!(middleboxes.len(), 2);
+        assert_eq!(middleboxes[0].kind, MiddleboxKind::Normalizer);
+        assert_eq!(middleboxes[0].after, Some((1, ip(1))));
+        assert_eq!(middleboxes[1].kind, MiddleboxKind::Nat);
+        // The clamped MSS doesn't hide that the second hop saw the original source
+        assert_eq!(middleboxes[1].after, Some((2, ip(2))));
+        assert_eq!(middleboxes[1].changes, vec![nat()]);
+        assert_eq!(
+            middleboxes[1].to_string(),
+            "NAT between 192.0.2.2 (hop 2) and 192.0.2.3 (hop 3): source 192.0.2.100 -> 192.0.2.200"
+        );
+    }
+
+    #[test]
+    fn replies_are_taken_in_distance_order() {
+        let responses = [
+            hop(3, vec![nat()]),
+            TraceResponse::TimedOut(fake::sent(2)),
+            hop(1, vec![]),
+            TraceResponse::Masked(4),
+        ];
+        let middleboxes = Middlebox::infer(&responses);
+        assert_eq!(middleboxes.len(), 1);
+        assert_eq!(middleboxes[0].after, Some((1, ip(1))));
+        assert_eq!(middleboxes[0].before, (3, ip(3)));
+    }
+
+    #[test]
+    fn changes_are_put_down_to_the_kind_of_box_making_them() {
+        let window = HeaderChange::Window {
+            sent: 1,
+            received: 2,
+        };
+        let checksum = HeaderChange::Checksum {
+            sent: 1,
+            received: 2,
+        };
+        assert_eq!(MiddleboxKind::of(&window), MiddleboxKind::Proxy);
+        assert_eq!(MiddleboxKind::of(&checksum), MiddleboxKind::Nat);
+        assert_eq!(MiddleboxKind::of(&clamp()), MiddleboxKind::Normalizer);
+    }
+}
@@ -0,0 +1,3 @@
+mod middlebox;
+
+pub use middlebox::{Middlebox, MiddleboxKind};
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use super::Asn;
+use crate::prelude::TTL;
+
+/// Something unexpected observed while comparing a round to its baseline
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AlertKind {
+    /// A hop of the baseline path didn't reply at all this round
+    HopDisappeared { ttl: TTL, address: IpAddr },
+    /// A hop belongs to an autonomous system never seen on the path
+    NewAs { ttl: TTL, address: IpAddr, asn: Asn },
+    /// Share of probes without a reply went over the threshold
+    LossExceeded { loss: f64, threshold: f64 },
+    /// Latency of a hop jumped well above its baseline
+    LatencyJump {
+        ttl: TTL,
+        address: IpAddr,
+        /// Baseline latency in milliseconds
+        baseline: f64,
+        /// Observed latency in milliseconds
+        observed: f64,
+    },
+}
+
+/// Alert emitted by the [`Monitor`](super::Monitor)
+#[derive(Clone, Debug, Serialize)]
+pub struct AlertEvent {
+    /// Unix time in milliseconds
+    pub timestamp: u64,
+    pub source: IpAddr,
+    pub target: IpAddr,
+    /// Round of the trace the alert came from
+    pub round: usize,
+    #[serde(flatten)]
+    pub kind: AlertKind,
+}
+
+impl AlertEvent {
+    pub fn new(source: IpAddr, target: IpAddr, round: usize, kind: AlertKind) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            timestamp,
+            source,
+            target,
+            round,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::HopDisappeared { ttl, address } => {
+                write!(f, "hop {} at TTL {} disappeared", address, ttl)
+            }
+            Self::NewAs { ttl, address, asn } => {
+                write!(f, "hop {} at TTL {} is in new AS{}", address, ttl, asn)
+            }
+            Self::LossExceeded { loss, threshold } => write!(
+                f,
+                "loss {:.0}% exceeds {:.0}%",
+                loss * 100.0,
+                threshold * 100.0
+            ),
+            Self::LatencyJump {
+                ttl,
+                address,
+                baseline,
+                observed,
+            } => write!(
+                f,
+                "hop {} at TTL {} latency {:.2?} up from {:.2?}",
+                address,
+                ttl,
+                Duration::from_secs_f64(observed / 1000.0),
+                Duration::from_secs_f64(baseline / 1000.0)
+            ),
+        }
+    }
+}
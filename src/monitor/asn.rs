@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use crate::TracerouteError;
+
+/// Autonomous system number
+pub type Asn = u32;
+
+/// IPv4 address ranges mapped to the autonomous system announcing them
+///
+/// Loaded from the tab separated `ip2asn-v4.tsv` format:
+/// `range_start range_end AS_number country_code AS_description`
+#[derive(Clone, Debug, Default)]
+pub struct AsnTable {
+    // (start, end, asn) sorted by start
+    ranges: Vec<(u32, u32, Asn)>,
+}
+
+impl AsnTable {
+    /// Load a table from an ip2asn tsv file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TracerouteError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a table in the ip2asn tsv format
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, TracerouteError> {
+        let mut ranges = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.split('\t');
+            let (start, end, asn) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(start), Some(end), Some(asn)) => (start, end, asn),
+                _ => continue,
+            };
+            let parsed = (
+                start.parse::<Ipv4Addr>(),
+                end.parse::<Ipv4Addr>(),
+                asn.parse::<Asn>(),
+            );
+            match parsed {
+                // AS 0 marks unrouted space
+                (Ok(_), Ok(_), Ok(0)) => continue,
+                (Ok(start), Ok(end), Ok(asn)) => ranges.push((start.into(), end.into(), asn)),
+                _ => {
+                    let err = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Malformed ip2asn line: {}", line),
+                    );
+                    return Err(TracerouteError::Io(err));
+                }
+            }
+        }
+
+        ranges.sort_unstable_by_key(|(start, _end, _asn)| *start);
+        Ok(Self { ranges })
+    }
+
+    /// Autonomous system announcing the address
+    pub fn lookup(&self, ip: IpAddr) -> Option<Asn> {
+        let ip: u32 = match ip {
+            IpAddr::V4(ip) => ip.into(),
+            IpAddr::V6(_) => return None,
+        };
+        // Last range starting at or before the address
+        let index = self.ranges.partition_point(|(start, _end, _asn)| *start <= ip);
+        let (_start, end, asn) = self.ranges.get(index.checked_sub(1)?)?;
+        if ip <= *end { Some(*asn) } else { None }
+    }
+}
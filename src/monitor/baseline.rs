@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use super::{AlertKind, Asn, AsnTable, MonitorOptions};
+use crate::prelude::TTL;
+use crate::trace::TraceResponse;
+
+// Weights of new samples, same as the TCP retransmission timer (RFC 6298)
+const MEAN_WEIGHT: f64 = 0.125;
+const DEVIATION_WEIGHT: f64 = 0.25;
+
+/// Learned latency profile of a single hop
+#[derive(Clone, Debug)]
+pub struct HopProfile {
+    /// Distance the hop was last seen at
+    pub ttl: TTL,
+    /// Rounds the hop replied in
+    pub seen: usize,
+    /// Smoothed latency in milliseconds
+    pub mean: f64,
+    /// Smoothed latency deviation in milliseconds
+    pub deviation: f64,
+}
+
+/// Expected path and latency of a target learned from previous rounds
+#[derive(Clone, Debug, Default)]
+pub struct Baseline {
+    /// Rounds learned from
+    pub rounds: usize,
+    pub hops: HashMap<IpAddr, HopProfile>,
+    /// Autonomous systems seen along the path
+    pub asns: HashSet<Asn>,
+}
+
+impl Baseline {
+    /// Fold a round into the baseline
+    pub fn learn(&mut self, responses: &[TraceResponse], asn_table: Option<&AsnTable>) {
+        self.rounds += 1;
+
+        for response in responses {
+            let resp = match response {
+                TraceResponse::Received(resp) => resp,
+                _ => continue,
+            };
+            let rtt = resp.ping.as_secs_f64() * 1000.0;
+
+            match self.hops.get_mut(&resp.destination) {
+                Some(profile) => {
+                    profile.ttl = resp.ttl;
+                    profile.seen += 1;
+                    profile.deviation = (1.0 - DEVIATION_WEIGHT) * profile.deviation
+                        + DEVIATION_WEIGHT * (profile.mean - rtt).abs();
+                    profile.mean = (1.0 - MEAN_WEIGHT) * profile.mean + MEAN_WEIGHT * rtt;
+                }
+                None => {
+                    let profile = HopProfile {
+                        ttl: resp.ttl,
+                        seen: 1,
+                        mean: rtt,
+                        deviation: rtt / 2.0,
+                    };
+                    let _ = self.hops.insert(resp.destination, profile);
+                }
+            }
+
+            if let Some(asn) = asn_table.and_then(|table| table.lookup(resp.destination)) {
+                let _ = self.asns.insert(asn);
+            }
+        }
+    }
+
+    /// Hops which replied in at least half of the rounds
+    pub fn stable_hops(&self) -> impl Iterator<Item = (&IpAddr, &HopProfile)> {
+        self.hops
+            .iter()
+            .filter(|(_address, profile)| profile.seen * 2 >= self.rounds)
+    }
+
+    /// Compare a round to the baseline
+    pub fn compare(
+        &self,
+        responses: &[TraceResponse],
+        options: &MonitorOptions,
+        asn_table: Option<&AsnTable>,
+    ) -> Vec<AlertKind> {
+        let mut alerts = Vec::new();
+
+        let replied: HashSet<IpAddr> = responses
+            .iter()
+            .filter_map(|response| response.get_destination())
+            .collect();
+
+        // Only distances which usually reply count towards loss
+        let mut expected_ttls: Vec<TTL> = Vec::new();
+        let mut stable = self.stable_hops().collect::<Vec<_>>();
+        stable.sort_by_key(|(_address, profile)| profile.ttl);
+
+        for (address, profile) in stable {
+            if !expected_ttls.contains(&profile.ttl) {
+                expected_ttls.push(profile.ttl);
+            }
+            if !replied.contains(address) {
+                alerts.push(AlertKind::HopDisappeared {
+                    ttl: profile.ttl,
+                    address: *address,
+                });
+            }
+        }
+
+        let lost = responses
+            .iter()
+            .filter(|response| matches!(response, TraceResponse::TimedOut(_)))
+            .filter(|response| expected_ttls.contains(&response.get_distance()))
+            .count();
+        if !expected_ttls.is_empty() {
+            let loss = lost as f64 / expected_ttls.len() as f64;
+            if loss > options.loss_threshold {
+                alerts.push(AlertKind::LossExceeded {
+                    loss,
+                    threshold: options.loss_threshold,
+                });
+            }
+        }
+
+        let jump = options.latency_jump.as_secs_f64() * 1000.0;
+        for response in responses {
+            let resp = match response {
+                TraceResponse::Received(resp) => resp,
+                _ => continue,
+            };
+
+            if let Some(asn) = asn_table.and_then(|table| table.lookup(resp.destination))
+                && !self.asns.contains(&asn)
+            {
+                alerts.push(AlertKind::NewAs {
+                    ttl: resp.ttl,
+                    address: resp.destination,
+                    asn,
+                });
+            }
+
+            let profile = match self.hops.get(&resp.destination) {
+                Some(profile) => profile,
+                None => continue,
+            };
+            let observed = resp.ping.as_secs_f64() * 1000.0;
+            if observed > profile.mean + jump.max(4.0 * profile.deviation) {
+                alerts.push(AlertKind::LatencyJump {
+                    ttl: resp.ttl,
+                    address: resp.destination,
+                    baseline: profile.mean,
+                    observed,
+                });
+            }
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    // Hops as (ttl, last octet of the replying address, rtt in ms). Timeouts have no address
+    fn round(hops: &[(TTL, Option<u8>, u64)]) -> Vec<TraceResponse> {
+        hops.iter()
+            .map(|(ttl, address, rtt)| match address {
+                Some(last) => TraceResponse::Received(fake::received(
+                    *ttl,
+                    ip(*last),
+                    Duration::from_millis(*rtt),
+                )),
+                None => TraceResponse::TimedOut(fake::sent(*ttl)),
+            })
+            .collect()
+    }
+
+    fn learned(rounds: &[&[(TTL, Option<u8>, u64)]]) -> Baseline {
+        let mut baseline = Baseline::default();
+        for hops in rounds {
+            baseline.learn(&round(hops), None);
+        }
+        baseline
+    }
+
+    fn compare(baseline: &Baseline, hops: &[(TTL, Option<u8>, u64)]) -> Vec<AlertKind> {
+        baseline.compare(&round(hops), &MonitorOptions::default(), None)
+    }
+
+    #[test]
+    fn latency_is_smoothed_like_the_retransmission_timer() {
+        let baseline = learned(&[&[(1, Some(1), 10)]]);
+        let profile = &baseline.hops[&ip(1)];
+        assert_eq!((profile.mean, profile.deviation), (10.0, 5.0));
+
+        let baseline = learned(&[&[(1, Some(1), 10)], &[(2, Some(1), 18)]]);
+        let profile = &baseline.hops[&ip(1)];
+        assert_eq!(profile.ttl, 2);
+        assert_eq!(profile.seen, 2);
+        // 7/8 * 10 + 1/8 * 18 and 3/4 * 5 + 1/4 * 8
+        assert_eq!((profile.mean, profile.deviation), (11.0, 5.75));
+    }
+
+    #[test]
+    fn steady_rounds_raise_nothing() {
+        let hops = [(1, Some(1), 10), (2, None, 0), (3, Some(3), 20)];
+        let baseline = learned(&[&hops, &hops]);
+        assert!(compare(&baseline, &hops).is_empty());
+    }
+
+    #[test]
+    fn only_usual_hops_are_missed() {
+        // The second hop replied once in four rounds
+        let usual = [(1, Some(1), 10), (2, None, 0)];
+        let baseline = learned(&[
+            &usual,
+            &usual,
+            &usual,
+            &[(1, Some(1), 10), (2, Some(2), 10)],
+        ]);
+        assert_eq!(baseline.stable_hops().count(), 1);
+        assert!(compare(&baseline, &usual).is_empty());
+
+        assert_eq!(
+            compare(&baseline, &[(1, None, 0), (2, None, 0)]),
+            vec![
+                AlertKind::HopDisappeared {
+                    ttl: 1,
+                    address: ip(1)
+                },
+                AlertKind::LossExceeded {
+                    loss: 1.0,
+                    threshold: 0.5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn loss_must_exceed_the_threshold() {
+        let hops = [(1, Some(1), 10), (2, Some(2), 10)];
+        let baseline = learned(&[&hops]);
+        // Half the usual distances went silent, timeouts further out don't count
+        let alerts = compare(&baseline, &[(1, Some(1), 10), (2, None, 0), (3, None, 0)]);
+        assert_eq!(
+            alerts,
+            vec![AlertKind::HopDisappeared {
+                ttl: 2,
+                address: ip(2)
+            }]
+        );
+    }
+
+    #[test]
+    fn latency_jumps_past_the_larger_of_the_threshold_and_the_deviation() {
+        // Mean 10ms with a deviation of 5ms, the 50ms threshold wins
+        let baseline = learned(&[&[(1, Some(1), 10)]]);
+        assert!(compare(&baseline, &[(1, Some(1), 59)]).is_empty());
+        assert!(matches!(
+            compare(&baseline, &[(1, Some(1), 61)])[..],
+            [AlertKind::LatencyJump { ttl: 1, .. }]
+        ));
+
+        // Mean 100ms with a deviation of 50ms, 4 deviations win
+        let baseline = learned(&[&[(1, Some(1), 100)]]);
+        assert!(compare(&baseline, &[(1, Some(1), 299)]).is_empty());
+        assert!(matches!(
+            compare(&baseline, &[(1, Some(1), 301)])[..],
+            [AlertKind::LatencyJump { ttl: 1, .. }]
+        ));
+    }
+
+    #[test]
+    fn hops_in_unseen_systems_are_new() {
+        let table = "192.0.2.0\t192.0.2.127\t64500\tZZ\tOne\n\
+                     192.0.2.128\t192.0.2.255\t64501\tZZ\tTwo\n";
+        let table = AsnTable::read(table.as_bytes()).unwrap();
+        let options = MonitorOptions::default();
+
+        let mut baseline = Baseline::default();
+        baseline.learn(&round(&[(1, Some(1), 10)]), Some(&table));
+        assert_eq!(baseline.asns, HashSet::from([64500]));
+
+        let same_system = round(&[(1, Some(1), 10), (2, Some(2), 10)]);
+        assert!(
+            baseline
+                .compare(&same_system, &options, Some(&table))
+                .is_empty()
+        );
+
+        let new_system = round(&[(1, Some(1), 10), (2, Some(200), 10)]);
+        assert_eq!(
+            baseline.compare(&new_system, &options, Some(&table)),
+            vec![AlertKind::NewAs {
+                ttl: 2,
+                address: ip(200),
+                asn: 64501
+            }]
+        );
+        // Without a table nothing is known about systems
+        assert!(baseline.compare(&new_system, &options, None).is_empty());
+    }
+}
@@ -0,0 +1,538 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::*;
+
+use crate::TracerouteError;
+use crate::http::{Request, Response};
+use crate::prelude::TTL;
+use crate::sockets::EngineStats;
+use crate::trace::TraceResponse;
+
+/// Upper bounds in seconds of the hop latency histogram buckets
+pub const RTT_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+// How long a scrape gets to send its request
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    // Not cumulative, summed up while rendering
+    buckets: [u64; RTT_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = RTT_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct HopMetrics {
+    rtt: Histogram,
+    last_rtt: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DistanceMetrics {
+    sent: u64,
+    lost: u64,
+    // Share of probes lost in the latest round
+    loss: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TargetMetrics {
+    rounds: u64,
+    hops: BTreeMap<(TTL, IpAddr), HopMetrics>,
+    distances: BTreeMap<TTL, DistanceMetrics>,
+    hop_count: TTL,
+    path: Vec<(TTL, IpAddr)>,
+    path_changes: u64,
+}
+
+/// Latency and loss of every traced path kept in the Prometheus data model
+pub struct Metrics {
+    engine: Arc<EngineStats>,
+    targets: Mutex<HashMap<(IpAddr, IpAddr), TargetMetrics>>,
+}
+
+impl Metrics {
+    /// Registry also reporting the counters of the network threads behind `engine`
+    pub fn new(engine: Arc<EngineStats>) -> Self {
+        Self {
+            engine,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fold the responses of one trace round into the metrics
+    pub fn observe(&self, source: IpAddr, target: IpAddr, responses: &[TraceResponse]) {
+        let mut targets = self.targets.lock().unwrap_or_else(|err| err.into_inner());
+        let metrics = targets.entry((source, target)).or_default();
+        metrics.rounds += 1;
+
+        let mut round: BTreeMap<TTL, (u64, u64)> = BTreeMap::new();
+        let mut path = Vec::new();
+
+        for response in responses {
+            let ttl = response.get_distance();
+            match response {
+                TraceResponse::Received(resp) => {
+                    let rtt = resp.ping.as_secs_f64();
+                    let hop = metrics.hops.entry((ttl, resp.destination)).or_default();
+                    hop.rtt.observe(rtt);
+                    hop.last_rtt = rtt;
+                    round.entry(ttl).or_default().0 += 1;
+                    if !path.contains(&(ttl, resp.destination)) {
+                        path.push((ttl, resp.destination));
+                    }
+                }
+                TraceResponse::TimedOut(_) => {
+                    let counts = round.entry(ttl).or_default();
+                    counts.0 += 1;
+                    counts.1 += 1;
+                }
+                TraceResponse::Masked(_) => (),
+            }
+        }
+
+        for (ttl, (sent, lost)) in round {
+            let distance = metrics.distances.entry(ttl).or_default();
+            distance.sent += sent;
+            distance.lost += lost;
+            distance.loss = lost as f64 / sent as f64;
+        }
+
+        path.sort();
+        metrics.hop_count = path.iter().map(|(ttl, _address)| *ttl).max().unwrap_or(0);
+        if !metrics.path.is_empty() && metrics.path != path {
+            metrics.path_changes += 1;
+        }
+        metrics.path = path;
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let targets = self.targets.lock().unwrap_or_else(|err| err.into_inner());
+        let mut targets = targets.iter().collect::<Vec<_>>();
+        targets.sort_by_key(|(key, _metrics)| **key);
+
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "traceroute_hop_rtt_seconds",
+            "histogram",
+            "Round trip time of replies from a hop",
+        );
+        for ((source, target), metrics) in &targets {
+            for ((ttl, hop), hop_metrics) in &metrics.hops {
+                let labels = format!(
+                    "source=\"{}\",target=\"{}\",ttl=\"{}\",hop=\"{}\"",
+                    source, target, ttl, hop
+                );
+                let histogram = &hop_metrics.rtt;
+                let mut cumulative = 0;
+                for (bound, count) in RTT_BUCKETS.iter().zip(histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        out,
+                        "traceroute_hop_rtt_seconds_bucket{{{},le=\"{}\"}} {}",
+                        labels, bound, cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "traceroute_hop_rtt_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                    labels, histogram.count
+                );
+                let _ = writeln!(
+                    out,
+                    "traceroute_hop_rtt_seconds_sum{{{}}} {}",
+                    labels, histogram.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "traceroute_hop_rtt_seconds_count{{{}}} {}",
+                    labels, histogram.count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "traceroute_hop_last_rtt_seconds",
+            "gauge",
+            "Latest round trip time of a hop",
+        );
+        for ((source, target), metrics) in &targets {
+            for ((ttl, hop), hop_metrics) in &metrics.hops {
+                let _ = writeln!(
+                    out,
+                    "traceroute_hop_last_rtt_seconds{{source=\"{}\",target=\"{}\",ttl=\"{}\",hop=\"{}\"}} {}",
+                    source, target, ttl, hop, hop_metrics.last_rtt
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "traceroute_probes_total",
+            "counter",
+            "Probes sent per distance",
+        );
+        distance_metric(&mut out, &targets, "traceroute_probes_total", |distance| {
+            distance.sent as f64
+        });
+        header(
+            &mut out,
+            "traceroute_probes_lost_total",
+            "counter",
+            "Probes per distance which got no reply",
+        );
+        distance_metric(
+            &mut out,
+            &targets,
+            "traceroute_probes_lost_total",
+            |distance| distance.lost as f64,
+        );
+        header(
+            &mut out,
+            "traceroute_loss_ratio",
+            "gauge",
+            "Share of probes lost per distance in the latest round",
+        );
+        distance_metric(&mut out, &targets, "traceroute_loss_ratio", |distance| {
+            distance.loss
+        });
+
+        header(
+            &mut out,
+            "traceroute_hop_count",
+            "gauge",
+            "Furthest distance which replied in the latest round",
+        );
+        target_metric(&mut out, &targets, "traceroute_hop_count", |metrics| {
+            metrics.hop_count as u64
+        });
+        header(
+            &mut out,
+            "traceroute_path_changes_total",
+            "counter",
+            "Rounds where the path differed from the round before",
+        );
+        target_metric(
+            &mut out,
+            &targets,
+            "traceroute_path_changes_total",
+            |metrics| metrics.path_changes,
+        );
+        header(
+            &mut out,
+            "traceroute_rounds_total",
+            "counter",
+            "Trace rounds completed",
+        );
+        target_metric(&mut out, &targets, "traceroute_rounds_total", |metrics| {
+            metrics.rounds
+        });
+
+        let counters = self.engine.counters();
+        let engine = [
+            (
+                "traceroute_engine_probes_sent_total",
+                "Probes handed to the network",
+                counters.probes_sent,
+            ),
+            (
+                "traceroute_engine_replies_matched_total",
+                "Replies correlated to a probe",
+                counters.replies_matched,
+            ),
+            (
+                "traceroute_engine_unmatched_packets_total",
+                "Packets received without a probe waiting for them",
+                counters.unmatched_packets,
+            ),
+            (
+                "traceroute_engine_probes_expired_total",
+                "Probes which timed out without a reply",
+                counters.probes_expired,
+            ),
+            (
+                "traceroute_engine_probes_cancelled_total",
+                "Probes dropped because their trace was cancelled",
+                counters.probes_cancelled,
+            ),
+            (
+                "traceroute_engine_parse_errors_total",
+                "Packets which couldn't be parsed",
+                counters.parse_errors,
+            ),
+        ];
+        for (name, help, value) in engine {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        out
+    }
+
+    /// Serve the metrics on `GET /metrics` from a background thread
+    pub fn serve(self: Arc<Self>, address: SocketAddr) -> Result<JoinHandle<()>, TracerouteError> {
+        let listener = TcpListener::bind(address)?;
+        info!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+
+        let handle = thread::Builder::new()
+            .name("metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|stream| self.scrape(stream));
+                    if let Err(err) = result {
+                        debug!("Failed metrics scrape: {}", err);
+                    }
+                }
+            })?;
+        Ok(handle)
+    }
+
+    fn scrape(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        let request = Request::read(&mut BufReader::new(&stream))?;
+
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Response::new(
+                200,
+                "text/plain; version=0.0.4; charset=utf-8",
+                self.render(),
+            ),
+            (_, "/metrics") => Response::error(405),
+            _ => Response::error(404),
+        };
+        response.write_to(&mut stream)
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+type TargetEntry<'a> = (&'a (IpAddr, IpAddr), &'a TargetMetrics);
+
+fn distance_metric(
+    out: &mut String,
+    targets: &[TargetEntry],
+    name: &str,
+    value: impl Fn(&DistanceMetrics) -> f64,
+) {
+    for ((source, target), metrics) in targets {
+        for (ttl, distance) in &metrics.distances {
+            let _ = writeln!(
+                out,
+                "{}{{source=\"{}\",target=\"{}\",ttl=\"{}\"}} {}",
+                name,
+                source,
+                target,
+                ttl,
+                value(distance)
+            );
+        }
+    }
+}
+
+fn target_metric(
+    out: &mut String,
+    targets: &[TargetEntry],
+    name: &str,
+    value: impl Fn(&TargetMetrics) -> u64,
+) {
+    for ((source, target), metrics) in targets {
+        let _ = writeln!(
+            out,
+            "{}{{source=\"{}\",target=\"{}\"}} {}",
+            name,
+            source,
+            target,
+            value(metrics)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+
+    const TARGET: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 9));
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    fn received(ttl: TTL, last: u8, millis: u64) -> TraceResponse {
+        TraceResponse::Received(fake::received(ttl, ip(last), Duration::from_millis(millis)))
+    }
+
+    fn lost(ttl: TTL) -> TraceResponse {
+        TraceResponse::TimedOut(fake::sent(ttl))
+    }
+
+    // Value of the sample named with its labels
+    fn sample<'a>(rendered: &'a str, series: &str) -> Option<&'a str> {
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+    }
+
+    fn series(name: &str, labels: &str) -> String {
+        format!(
+            "{}{{source=\"{}\",target=\"{}\"{}}}",
+            name,
+            fake::SOURCE,
+            TARGET,
+            labels
+        )
+    }
+
+    #[test]
+    fn rtt_buckets_are_cumulative() {
+        let metrics = Metrics::new(Arc::default());
+        for millis in [3, 30, 5_000] {
+            metrics.observe(fake::SOURCE, TARGET, &[received(1, 1, millis)]);
+        }
+        let rendered = metrics.render();
+
+        let bucket = |le: &str| {
+            let labels = format!(",ttl=\"1\",hop=\"192.0.2.1\",le=\"{}\"", le);
+            sample(
+                &rendered,
+                &series("traceroute_hop_rtt_seconds_bucket", &labels),
+            )
+        };
+        assert_eq!(bucket("0.001"), Some("0"));
+        assert_eq!(bucket("0.005"), Some("1"));
+        assert_eq!(bucket("0.025"), Some("1"));
+        assert_eq!(bucket("0.05"), Some("2"));
+        // Slower than every bound only shows up in +Inf
+        assert_eq!(bucket("2.5"), Some("2"));
+        assert_eq!(bucket("+Inf"), Some("3"));
+
+        let hop = ",ttl=\"1\",hop=\"192.0.2.1\"";
+        let count = series("traceroute_hop_rtt_seconds_count", hop);
+        assert_eq!(sample(&rendered, &count), Some("3"));
+        let last = series("traceroute_hop_last_rtt_seconds", hop);
+        assert_eq!(sample(&rendered, &last), Some("5"));
+    }
+
+    #[test]
+    fn loss_is_counted_per_distance() {
+        let metrics = Metrics::new(Arc::default());
+        metrics.observe(
+            fake::SOURCE,
+            TARGET,
+            &[
+                received(1, 1, 5),
+                lost(2),
+                lost(2),
+                TraceResponse::Masked(3),
+            ],
+        );
+        metrics.observe(
+            fake::SOURCE,
+            TARGET,
+            &[received(1, 1, 5), received(2, 2, 5), lost(2)],
+        );
+        let rendered = metrics.render();
+
+        let value = |name: &str, ttl: TTL| {
+            let labels = format!(",ttl=\"{}\"", ttl);
+            sample(&rendered, &series(name, &labels))
+        };
+        assert_eq!(value("traceroute_probes_total", 1), Some("2"));
+        assert_eq!(value("traceroute_probes_lost_total", 1), Some("0"));
+        assert_eq!(value("traceroute_probes_total", 2), Some("4"));
+        assert_eq!(value("traceroute_probes_lost_total", 2), Some("3"));
+        // Only the latest round makes up the ratio
+        assert_eq!(value("traceroute_loss_ratio", 2), Some("0.5"));
+        // Masked distances aren't probed
+        assert_eq!(value("traceroute_probes_total", 3), None);
+    }
+
+    #[test]
+    fn path_changes_are_counted_between_rounds() {
+        let metrics = Metrics::new(Arc::default());
+        let rounds = [
+            vec![received(1, 1, 5), received(2, 2, 5)],
+            // Replies arriving in another order are the same path
+            vec![received(2, 2, 5), received(1, 1, 5)],
+            vec![received(1, 1, 5), received(2, 3, 5), received(3, 4, 5)],
+        ];
+        for round in &rounds {
+            metrics.observe(fake::SOURCE, TARGET, round);
+        }
+        let rendered = metrics.render();
+
+        let value = |name: &str| sample(&rendered, &series(name, ""));
+        assert_eq!(value("traceroute_rounds_total"), Some("3"));
+        assert_eq!(value("traceroute_path_changes_total"), Some("1"));
+        assert_eq!(value("traceroute_hop_count"), Some("3"));
+    }
+
+    #[test]
+    fn every_metric_is_described_once() {
+        let engine = Arc::new(EngineStats::default());
+        engine.probe_sent();
+        engine.probe_sent();
+        engine.parse_error();
+        let metrics = Metrics::new(engine);
+        metrics.observe(fake::SOURCE, TARGET, &[received(1, 1, 5)]);
+        let rendered = metrics.render();
+
+        assert_eq!(
+            sample(&rendered, "traceroute_engine_probes_sent_total"),
+            Some("2")
+        );
+        assert_eq!(
+            sample(&rendered, "traceroute_engine_parse_errors_total"),
+            Some("1")
+        );
+
+        let types = rendered
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .map(|line| line.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(types.len(), 14);
+        for name in &types {
+            assert_eq!(types.iter().filter(|other| *other == name).count(), 1);
+            assert!(rendered.contains(&format!("# HELP {} ", name)));
+        }
+        // Every sample belongs to a described metric
+        for line in rendered.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = ["_bucket", "_sum", "_count"]
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .filter(|family| types.contains(family))
+                .unwrap_or(name);
+            assert!(types.contains(&family), "{} isn't described", name);
+        }
+    }
+}
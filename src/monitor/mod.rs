@@ -0,0 +1,13 @@
+mod alert;
+mod asn;
+mod baseline;
+mod metrics;
+mod monitor;
+mod sink;
+
+pub use alert::{AlertEvent, AlertKind};
+pub use asn::{Asn, AsnTable};
+pub use baseline::{Baseline, HopProfile};
+pub use metrics::{Metrics, RTT_BUCKETS};
+pub use monitor::{Monitor, MonitorOptions};
+pub use sink::{AlertSink, FileSink, StdoutSink, WebhookSink};
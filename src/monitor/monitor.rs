@@ -0,0 +1,170 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::stream::StreamExt;
+use async_std::task;
+use log::*;
+
+use super::{AlertEvent, AlertSink, AsnTable, Baseline, Metrics};
+use crate::TracerouteError;
+use crate::trace::{Trace, TraceOptions};
+use crate::traceroute::Traceroute;
+
+/// Configuration of the [`Monitor`]
+#[derive(Clone, Debug)]
+pub struct MonitorOptions {
+    /// Time between the start of two rounds
+    pub interval: Duration,
+    /// Rounds used to build the baseline before alerts are raised
+    pub learning_rounds: usize,
+    /// Share of usually replying hops allowed to go silent. 0.0 - 1.0
+    pub loss_threshold: f64,
+    /// Smallest latency increase over the baseline considered a jump
+    pub latency_jump: Duration,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            learning_rounds: 3,
+            loss_threshold: 0.5,
+            latency_jump: Duration::from_millis(50),
+        }
+    }
+}
+
+// A target with its trace and what we learned about it
+struct Monitored {
+    trace: Trace,
+    baseline: Baseline,
+    round: usize,
+}
+
+/// Repeatedly trace a list of targets and raise alerts when they stray from their baseline
+pub struct Monitor {
+    options: MonitorOptions,
+    targets: Vec<Monitored>,
+    sinks: Vec<Box<dyn AlertSink>>,
+    asn_table: Option<AsnTable>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl Monitor {
+    pub fn new(
+        traceroute: &Traceroute,
+        targets: &[IpAddr],
+        trace_options: TraceOptions,
+        options: MonitorOptions,
+    ) -> Result<Self, TracerouteError> {
+        let targets = targets
+            .iter()
+            .map(|target| {
+                let source = traceroute.source_for(*target);
+                Ok(Monitored {
+                    trace: traceroute.trace(source, *target, trace_options)?,
+                    baseline: Baseline::default(),
+                    round: 0,
+                })
+            })
+            .collect::<Result<Vec<Monitored>, TracerouteError>>()?;
+
+        Ok(Self {
+            options,
+            targets,
+            sinks: Vec::new(),
+            asn_table: None,
+            metrics: None,
+        })
+    }
+
+    /// Send alerts to an additional destination
+    pub fn add_sink(&mut self, sink: Box<dyn AlertSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Enable alerts for hops in autonomous systems new to the path
+    pub fn set_asn_table(&mut self, table: AsnTable) {
+        self.asn_table = Some(table);
+    }
+
+    /// Record the latency and loss of every round
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Trace every target once, emitting and returning the alerts raised
+    pub async fn run_round(&mut self) -> Result<Vec<AlertEvent>, TracerouteError> {
+        let mut events = Vec::new();
+
+        for monitored in &mut self.targets {
+            // Traces only end once cancelled. A round cut short would look like loss
+            let responses = match StreamExt::next(&mut monitored.trace).await {
+                Some(_result) if monitored.trace.is_cancelled() => {
+                    return Err(TracerouteError::Cancelled);
+                }
+                Some(result) => result?,
+                None => return Err(TracerouteError::Cancelled),
+            };
+            monitored.round += 1;
+
+            if let Some(metrics) = &self.metrics {
+                metrics.observe(
+                    monitored.trace.source(),
+                    monitored.trace.destination(),
+                    &responses,
+                );
+            }
+
+            let asn_table = self.asn_table.as_ref();
+            if monitored.round > self.options.learning_rounds {
+                let alerts = monitored
+                    .baseline
+                    .compare(&responses, &self.options, asn_table);
+                for kind in alerts {
+                    events.push(AlertEvent::new(
+                        monitored.trace.source(),
+                        monitored.trace.destination(),
+                        monitored.round,
+                        kind,
+                    ));
+                }
+            } else {
+                debug!(
+                    "Learning baseline of {} round {}/{}",
+                    monitored.trace.destination(),
+                    monitored.round,
+                    self.options.learning_rounds
+                );
+            }
+            monitored.baseline.learn(&responses, asn_table);
+        }
+
+        for event in &events {
+            info!("{} {}", event.target, event.kind);
+            for sink in &mut self.sinks {
+                // A broken sink shouldn't stop the monitoring
+                if let Err(err) = sink.emit(event) {
+                    warn!("Failed to emit alert: {}", err);
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Run rounds forever waiting `interval` between the start of each round
+    ///
+    /// Only returns with an error, [`TracerouteError::Cancelled`] once the engine is shut down.
+    pub async fn run(&mut self) -> Result<(), TracerouteError> {
+        loop {
+            let start = std::time::Instant::now();
+            let _ = self.run_round().await?;
+            let elapsed = start.elapsed();
+            if elapsed < self.options.interval {
+                task::sleep(self.options.interval - elapsed).await;
+            }
+        }
+    }
+}
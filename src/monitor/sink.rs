@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use url::Url;
+
+use super::AlertEvent;
+use crate::TracerouteError;
+
+// How long a webhook gets to accept an alert
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Destination for [`AlertEvent`]s
+pub trait AlertSink {
+    fn emit(&mut self, event: &AlertEvent) -> Result<(), TracerouteError>;
+}
+
+/// Write alerts to stdout as json lines
+pub struct StdoutSink;
+
+impl AlertSink for StdoutSink {
+    fn emit(&mut self, event: &AlertEvent) -> Result<(), TracerouteError> {
+        let line = serde_json::to_string(event).map_err(TracerouteError::Json)?;
+        writeln!(io::stdout().lock(), "{}", line)?;
+        Ok(())
+    }
+}
+
+/// Append alerts to a file as json lines
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TracerouteError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl AlertSink for FileSink {
+    fn emit(&mut self, event: &AlertEvent) -> Result<(), TracerouteError> {
+        let line = serde_json::to_string(event).map_err(TracerouteError::Json)?;
+        writeln!(self.file, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// POST each alert as json to a plain http endpoint
+///
+/// Meant for webhook receivers on the local machine or network so there is no TLS support.
+pub struct WebhookSink {
+    url: Url,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> Result<Self, TracerouteError> {
+        let url = Url::parse(url).map_err(|_| invalid_webhook("url can't be parsed"))?;
+        if url.scheme() != "http" {
+            return Err(invalid_webhook("only http:// urls are supported"));
+        }
+        if url.host_str().is_none() {
+            return Err(invalid_webhook("url has no host"));
+        }
+        Ok(Self { url })
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn emit(&mut self, event: &AlertEvent) -> Result<(), TracerouteError> {
+        let body = serde_json::to_string(event).map_err(TracerouteError::Json)?;
+        let host = self.url.host_str().unwrap_or_default();
+        let port = self.url.port_or_known_default().unwrap_or(80);
+
+        let mut path = self.url.path().to_string();
+        if let Some(query) = self.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        let mut stream = connect(host, port)?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host,
+            port,
+            body.len(),
+            body
+        )?;
+
+        // Only the status line is of interest. ex: HTTP/1.1 204 No Content
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            let err = io::Error::other(format!("Webhook responded with {}", status_line.trim()));
+            return Err(TracerouteError::Io(err));
+        }
+        Ok(())
+    }
+}
+
+// Try each address of the webhook host in turn, giving each the webhook timeout
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("Webhook host has no address")))
+}
+
+fn invalid_webhook(reason: &str) -> TracerouteError {
+    let err = io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid webhook: {}", reason));
+    TracerouteError::Io(err)
+}
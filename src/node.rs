@@ -4,14 +4,29 @@ use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
 
 use crate::prelude::{Flowhash, TTL};
+use crate::trace::TraceResponse;
+
+/// Where an anonymous router sits, telling apart the ones at the same distance
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Anonymous {
+    /// Between the closest hops which replied on either side. Flows agreeing on both are taken to
+    /// go through the same router
+    Between(IpAddr, IpAddr),
+    /// Nothing replied after it so only the flow which went through it knows it
+    Flow(Flowhash),
+}
 
 /// Node in the graph
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Node {
     Flow(Flowhash),
     Hop(IpAddr),
     // A node that didn't respond, aka timed out
-    Hidden(TTL),
+    Hidden(TTL, Anonymous),
     Masked(TTL),
 }
 
@@ -20,7 +35,10 @@ impl Hash for Node {
         match self {
             Self::Flow(flowhash) => flowhash.hash(state),
             Self::Hop(ip) => ip.hash(state),
-            Self::Hidden(ttl) => ttl.hash(state),
+            Self::Hidden(ttl, anonymous) => {
+                ttl.hash(state);
+                anonymous.hash(state);
+            }
             Self::Masked(ttl) => ttl.hash(state),
         }
     }
@@ -31,7 +49,7 @@ impl fmt::Display for Node {
         match self {
             Self::Flow(flowhash) => write!(f, "Flow #{:x?}", flowhash),
             Self::Hop(ip) => write!(f, "{}", ip),
-            Self::Hidden(_ttl) => write!(f, "No Reply"),
+            Self::Hidden(_ttl, _anonymous) => write!(f, "No Reply"),
             Self::Masked(_ttl) => write!(f, "Masked"),
         }
     }
@@ -42,19 +60,19 @@ impl PartialEq for Node {
         match self {
             Self::Flow(flow) => match other {
                 Self::Flow(flow2) => flow == flow2,
-                Self::Hop(_) | Self::Hidden(_) | Self::Masked(_) => false,
+                Self::Hop(_) | Self::Hidden(..) | Self::Masked(_) => false,
             },
             Self::Hop(ip) => match other {
                 Self::Hop(ip2) => ip == ip2,
-                Self::Flow(_) | Self::Hidden(_) | Self::Masked(_) => false,
+                Self::Flow(_) | Self::Hidden(..) | Self::Masked(_) => false,
             },
-            Self::Hidden(ttl) => match other {
-                Self::Hidden(ttl2) => ttl == ttl2,
+            Self::Hidden(ttl, anonymous) => match other {
+                Self::Hidden(ttl2, anonymous2) => ttl == ttl2 && anonymous == anonymous2,
                 Self::Flow(_) | Self::Hop(_) | Self::Masked(_) => false,
             },
             Self::Masked(ttl) => match other {
                 Self::Masked(ttl2) => ttl == ttl2,
-                Self::Flow(_) | Self::Hidden(_) | Self::Hop(_) => false,
+                Self::Flow(_) | Self::Hidden(..) | Self::Hop(_) => false,
             },
         }
     }
@@ -66,28 +84,147 @@ impl Ord for Node {
         match self {
             Self::Flow(flow) => match other {
                 Self::Flow(flow2) => flow.cmp(flow2),
-                Self::Hop(_) | Self::Hidden(_) | Self::Masked(_) => Ordering::Less,
+                Self::Hop(_) | Self::Hidden(..) | Self::Masked(_) => Ordering::Less,
             },
             Self::Hop(ip) => match other {
                 Self::Flow(_) => Ordering::Greater,
                 Self::Hop(ip2) => ip.cmp(ip2),
-                Self::Hidden(_) | Self::Masked(_) => Ordering::Less,
+                Self::Hidden(..) | Self::Masked(_) => Ordering::Less,
             },
-            Self::Hidden(ttl) => match other {
+            Self::Hidden(ttl, anonymous) => match other {
                 Self::Flow(_) | Self::Hop(_) => Ordering::Greater,
-                Self::Hidden(ttl2) => ttl.cmp(ttl2),
+                Self::Hidden(ttl2, anonymous2) => (ttl, anonymous).cmp(&(ttl2, anonymous2)),
                 Self::Masked(_) => Ordering::Less,
             },
             Self::Masked(ttl) => match other {
-                Self::Flow(_) | Self::Hop(_) | Self::Hidden(_) => Ordering::Greater,
+                Self::Flow(_) | Self::Hop(_) | Self::Hidden(..) => Ordering::Greater,
                 Self::Masked(ttl2) => ttl.cmp(ttl2),
             },
         }
     }
 }
 
+impl Node {
+    /// Node of the anonymous router a flow timed out at
+    ///
+    /// Routers at the same distance are only merged across flows when the hops replying on either
+    /// side of them agree. `source` stands in for the hop before when nothing replied earlier.
+    pub fn hidden(
+        source: IpAddr,
+        flowhash: Flowhash,
+        responses: &[TraceResponse],
+        ttl: TTL,
+    ) -> Self {
+        let replied = |response: &TraceResponse| match response {
+            TraceResponse::Received(resp) => Some((resp.ttl, resp.destination)),
+            _ => None,
+        };
+        let before = responses
+            .iter()
+            .filter_map(replied)
+            .filter(|(distance, _address)| *distance < ttl)
+            .max_by_key(|(distance, _address)| *distance)
+            .map_or(source, |(_distance, address)| address);
+        let after = responses
+            .iter()
+            .filter_map(replied)
+            .filter(|(distance, _address)| *distance > ttl)
+            .min_by_key(|(distance, _address)| *distance)
+            .map(|(_distance, address)| address);
+
+        let anonymous = match after {
+            Some(after) => Anonymous::Between(before, after),
+            None => Anonymous::Flow(flowhash),
+        };
+        Self::Hidden(ttl, anonymous)
+    }
+}
+
 impl PartialOrd for Node {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    // Hops as (ttl, last octet of the replying address). Timeouts have no address
+    fn round(hops: &[(TTL, Option<u8>)]) -> Vec<TraceResponse> {
+        hops.iter()
+            .map(|(ttl, address)| match address {
+                Some(last) => TraceResponse::Received(fake::received(
+                    *ttl,
+                    ip(*last),
+                    Duration::from_millis(1),
+                )),
+                None => TraceResponse::TimedOut(fake::sent(*ttl)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hidden_hops_sit_between_the_closest_replies() {
+        let responses = round(&[
+            (1, Some(1)),
+            (2, None),
+            (3, None),
+            (4, Some(4)),
+            (5, Some(5)),
+        ]);
+        assert_eq!(
+            Node::hidden(fake::SOURCE, 7, &responses, 2),
+            Node::Hidden(2, Anonymous::Between(ip(1), ip(4)))
+        );
+        assert_eq!(
+            Node::hidden(fake::SOURCE, 7, &responses, 3),
+            Node::Hidden(3, Anonymous::Between(ip(1), ip(4)))
+        );
+    }
+
+    #[test]
+    fn the_source_stands_in_for_missing_earlier_replies() {
+        let responses = round(&[(1, None), (2, Some(2))]);
+        assert_eq!(
+            Node::hidden(fake::SOURCE, 7, &responses, 1),
+            Node::Hidden(1, Anonymous::Between(fake::SOURCE, ip(2)))
+        );
+    }
+
+    #[test]
+    fn trailing_hidden_hops_belong_to_their_flow() {
+        let responses = round(&[(1, Some(1)), (2, None), (3, None)]);
+        assert_eq!(
+            Node::hidden(fake::SOURCE, 7, &responses, 3),
+            Node::Hidden(3, Anonymous::Flow(7))
+        );
+        assert_ne!(
+            Node::hidden(fake::SOURCE, 7, &responses, 3),
+            Node::hidden(fake::SOURCE, 8, &responses, 3)
+        );
+    }
+
+    #[test]
+    fn hidden_hops_merge_only_when_neighbours_agree() {
+        let first = round(&[(1, Some(1)), (2, None), (3, Some(3))]);
+        let agreeing = round(&[(1, Some(1)), (2, None), (3, Some(3)), (4, Some(4))]);
+        let diverging = round(&[(1, Some(1)), (2, None), (3, Some(13))]);
+
+        let hidden = Node::hidden(fake::SOURCE, 1, &first, 2);
+        assert_eq!(hidden, Node::hidden(fake::SOURCE, 2, &agreeing, 2));
+        assert_ne!(hidden, Node::hidden(fake::SOURCE, 3, &diverging, 2));
+        // Distance still tells hidden hops apart
+        assert_ne!(
+            Node::Hidden(2, Anonymous::Flow(1)),
+            Node::Hidden(3, Anonymous::Flow(1))
+        );
+    }
+}
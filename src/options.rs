@@ -1,36 +1,51 @@
-use std::io;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader};
 use std::net::IpAddr;
-use std::path::PathBuf;
-use std::time::Instant;
+#[cfg(any(feature = "monitor", feature = "server"))]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use log::*;
 use structopt::StructOpt;
-use crate::protocol::Protocol;
+use crate::export::Format;
+use crate::packet::Payload;
+use crate::protocol::{PortStrategy, Protocol, UdpParams};
+use crate::trace::{MIN_BROKEN_NAT_SIZE, MIN_PROBE_SIZE};
+use crate::targets::{Sampling, Target, read_targets};
 use url::Host;
 
 use resolve::resolve_host;
 
-use crate::TracerouteError;
+use crate::{Binding, RateLimit, TracerouteError};
 
 /// Command line configuration parameters
 #[derive(StructOpt, Clone, Debug, Default)]
 #[structopt(name = "hollister-traceroute", about)]
 pub struct Options {
     /// The network has a broken NAT configuration (e.g. no payload fixup). Try this if you see
-    /// fewer hops than expected
-    //#[structopt(short, long)]
-    //pub broken_nat: bool,
-    /// Generate paths using source port instead of destination port
-    //#[structopt(short = "i", long = "use-srcport")]
-    //pub use_srcport_for_path_generation: bool,
+    /// fewer hops than expected. Overwrites the first two bytes of the UDP payload
+    #[structopt(long)]
+    pub broken_nat: bool,
     /// Do not attempt to do reverse DNS lookup of the hops
-    //#[structopt(short = "N", long)]
-    //pub no_dns: bool,
+    #[structopt(short = "N", long)]
+    pub no_dns: bool,
+    /// Network interface to send probes through. ex: eth0
+    #[structopt(short, long)]
+    pub interface: Option<String>,
+    /// Address to send probes from. Picked from the routing table for each target by default
+    #[structopt(long)]
+    pub source: Option<IpAddr>,
     /// Source port to send packets from
     #[structopt(short, long, default_value = "12345")]
     pub src_port: u16,
     /// Base destination port to send packets to
     #[structopt(short, long, default_value = "33434")]
     pub dst_port: u16,
+    /// How UDP ports change: fixed like Paris traceroute, incrementing like classic traceroute,
+    /// random-source for each flow or a list of destination ports taken in turn. ex: 53,123,33434
+    #[structopt(long, default_value = "fixed")]
+    pub ports: PortStrategy,
     /// The minimum TTL to probe
     #[structopt(short, long, default_value = "1")]
     pub min_ttl: u8,
@@ -40,6 +55,25 @@ pub struct Options {
     /// The inter-packet delay in milliseconds
     #[structopt(short = "D", long, default_value = "5")]
     pub delay: u16,
+    /// Milliseconds to wait for a reply to a probe
+    #[structopt(long, default_value = "300")]
+    pub timeout: u16,
+    /// Wait on each hop as long as its earlier replies suggest, as TCP adapts its retransmission
+    /// timeout. Hops which haven't replied go by the closest hop which has, until then --timeout
+    #[structopt(long)]
+    pub adaptive_timeout: bool,
+    /// Probes sent to a hop before giving up on it, each waiting twice as long as the last
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_attempts))]
+    pub attempts: u8,
+    /// Give up on a trace after this many milliseconds, keeping the hops found so far
+    #[structopt(long)]
+    pub deadline: Option<u32>,
+    /// Packets per second shared by all traces. Overrides the inter-packet delay. 0 is unlimited
+    #[structopt(long)]
+    pub pps: Option<u32>,
+    /// Packets per second sent to any single destination
+    #[structopt(long)]
+    pub per_destination_pps: Option<u32>,
     /// Output file name [default: stdout]
     #[structopt(short, long, parse(from_os_str))]
     pub output_file: Option<PathBuf>,
@@ -52,49 +86,230 @@ pub struct Options {
     /// TTLs to assume Hidden
     #[structopt(long, use_delimiter = true)]
     pub mask: Option<Vec<u8>>,
-    /// Hostname or IP address of target
-    #[structopt(parse(try_from_str = Host::parse))]
-    pub target: Vec<Host>,
+    /// Hostname, IP address or IPv4 CIDR range of target. Text output only traces the first
+    /// address of a hostname, the graph formats trace all of them
+    pub target: Vec<Target>,
+    /// File with one hostname, IP address or CIDR range per line. Use - for stdin
+    #[structopt(long, parse(from_os_str))]
+    pub targets_file: Option<PathBuf>,
+    /// Addresses traced per /24 of CIDR targets: first (the .1 address), random or random:N
+    #[structopt(long, default_value = "first")]
+    pub sampling: Sampling,
     /// Protocol to probe with DCCP, ICMP, SCTP, TCP, UDP
     #[structopt(short, long, default_value = "udp")]
     pub protocol: Protocol,
-    /// Output graph in Dot format
+    /// Total length of each probe in bytes including the IP header
+    #[structopt(long, default_value = "52", parse(try_from_str = parse_size))]
+    pub size: u16,
+    /// Hex bytes repeated over the probe payload, like ping -p. ex: ff00
+    #[structopt(long)]
+    pub pattern: Option<Payload>,
+    /// Differentiated services codepoint to mark probes with. 0 - 63. ex: 46 for EF
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_dscp))]
+    pub dscp: u8,
+    /// Explicit congestion notification codepoint to mark probes with. 0 - 3. ex: 2 for ECT(0)
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_ecn))]
+    pub ecn: u8,
+    /// Output graph in Dot format. Shorthand for `--format dot`
     #[structopt(short = "g", long= "graph")]
     pub dot: bool,
+    /// Output format: text, dot, json, mermaid, graphml, html
+    #[structopt(short, long, default_value = "text")]
+    pub format: Format,
+    /// Probe every target and TTL once in random order without keeping state, printing each reply
+    #[structopt(long)]
+    pub yarrp: bool,
+    /// Discover topology probing backwards from --start-ttl, skipping hops already seen
+    #[structopt(long)]
+    pub doubletree: bool,
+    /// Distance Doubletree probing starts at
+    #[structopt(long, default_value = "10")]
+    pub start_ttl: u8,
+    /// Destinations Doubletree probes at the same time
+    #[structopt(long, default_value = "32")]
+    pub batch_size: usize,
+    /// Find the largest packet size reaching each hop with Don't Fragment probes
+    #[structopt(long)]
+    pub pmtu: bool,
+    /// Probe size in bytes path MTU discovery starts from
+    #[structopt(long, default_value = "1500")]
+    pub max_mtu: u16,
+    /// Draw runs of hops without a reply as a single node in graph formats
+    #[structopt(long)]
+    pub collapse_hidden: bool,
+    /// After tracing, probe the hops to find interfaces belonging to the same router
+    #[structopt(long)]
+    pub aliases: bool,
+    /// Draw a node per router instead of per interface in graph formats. Requires --aliases
+    #[structopt(long, requires = "aliases")]
+    pub router_level: bool,
+    /// SQLite file to record every trace into
+    #[cfg(feature = "history")]
+    #[structopt(long, parse(from_os_str))]
+    pub history: Option<PathBuf>,
+    /// Output changes since the previous recorded run instead of the trace. Requires --history
+    #[cfg(feature = "history")]
+    #[structopt(long, requires = "history")]
+    pub diff: bool,
+    /// Output changes between two recorded runs without tracing. ex: 3,7
+    #[cfg(feature = "history")]
+    #[structopt(long, use_delimiter = true, number_of_values = 2, requires = "history")]
+    pub diff_runs: Option<Vec<i64>>,
+    /// Keep tracing the targets and emit alerts when they stray from their learned baseline
+    #[cfg(feature = "monitor")]
+    #[structopt(long)]
+    pub monitor: bool,
+    /// Seconds between monitoring rounds
+    #[cfg(feature = "monitor")]
+    #[structopt(long, default_value = "60")]
+    pub interval: u64,
+    /// Rounds used to learn the baseline before alerting
+    #[cfg(feature = "monitor")]
+    #[structopt(long, default_value = "3")]
+    pub learning_rounds: usize,
+    /// Share of usually replying hops allowed to go silent before alerting. 0.0 - 1.0
+    #[cfg(feature = "monitor")]
+    #[structopt(long, default_value = "0.5")]
+    pub loss_threshold: f64,
+    /// Latency increase in milliseconds over the baseline considered a jump
+    #[cfg(feature = "monitor")]
+    #[structopt(long, default_value = "50")]
+    pub latency_jump: u64,
+    /// POST alerts as json to this http url
+    #[cfg(feature = "monitor")]
+    #[structopt(long)]
+    pub alert_webhook: Option<String>,
+    /// Append alerts as json lines to this file
+    #[cfg(feature = "monitor")]
+    #[structopt(long, parse(from_os_str))]
+    pub alert_file: Option<PathBuf>,
+    /// ip2asn tsv file used to alert on autonomous systems new to a path
+    #[cfg(feature = "monitor")]
+    #[structopt(long, parse(from_os_str))]
+    pub asn_db: Option<PathBuf>,
+    /// Serve Prometheus metrics on this address. ex: 127.0.0.1:9184
+    #[cfg(feature = "monitor")]
+    #[structopt(long, requires = "monitor")]
+    pub metrics: Option<SocketAddr>,
+    /// Serve traces on request over a local HTTP JSON API on this address. ex: 127.0.0.1:9185
+    #[cfg(feature = "server")]
+    #[structopt(long)]
+    pub serve: Option<SocketAddr>,
+    /// Connections the server handles at the same time, each running at most one trace
+    #[cfg(feature = "server")]
+    #[structopt(long, default_value = "4")]
+    pub max_concurrent: usize,
+    /// Trace requests each client may make to the server per minute
+    #[cfg(feature = "server")]
+    #[structopt(long, default_value = "30")]
+    pub rate_limit: u32,
 }
 
 impl Options {
+    /// Output format taking the `--graph` shorthand into account
+    pub fn format(&self) -> Format {
+        if self.dot {
+            return Format::Dot;
+        }
+        self.format
+    }
+
+    /// Protocol to probe with carrying `--src-port` and `--dst-port` for UDP
+    pub fn protocol(&self) -> Protocol {
+        match self.protocol {
+            Protocol::UDP(_params) => Protocol::UDP(UdpParams {
+                source_port: self.src_port,
+                destination_port: self.dst_port,
+            }),
+            protocol => protocol,
+        }
+    }
+
+    /// Interface and source probes are bound to
+    pub fn binding(&self) -> Binding {
+        Binding {
+            interface: self.interface.clone(),
+            source: self.source,
+        }
+    }
+
+    /// Sending budget from `--pps` or else the inter-packet delay
+    pub fn rate_limit(&self) -> RateLimit {
+        let mut rate_limit = match self.pps {
+            Some(packets_per_second) => RateLimit {
+                packets_per_second,
+                per_destination: None,
+            },
+            None => RateLimit::from_delay(Duration::from_millis(self.delay.into())),
+        };
+        rate_limit.per_destination = self.per_destination_pps;
+        rate_limit
+    }
+
+    /// Reason options which parsed on their own don't work together, if any
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.broken_nat && self.size < MIN_BROKEN_NAT_SIZE {
+            return Some("--broken-nat needs a --size of at least 30 to make each probe unique");
+        }
+        if self.yarrp && !matches!(self.protocol, Protocol::UDP(_)) {
+            return Some("--yarrp only probes with UDP");
+        }
+        None
+    }
+
     /// Gather all IP addresses dictated through options
     pub fn target_ips(&self) -> Result<Vec<IpAddr>, TracerouteError> {
         // @TODO return an iterator for the different targets?
         let mut hosts = vec![];
 
-        if self.target.is_empty() {
+        let mut targets = self.target.clone();
+        if let Some(path) = &self.targets_file {
+            targets.extend(Self::read_targets_file(path)?);
+        }
+
+        if targets.is_empty() {
             let err = io::Error::new(io::ErrorKind::Other, "No target given");
             return Err(TracerouteError::Io(err));
         }
 
-        for host in &self.target {
-            match host {
-                Host::Ipv4(ip) => hosts.push(IpAddr::V4(*ip)),
-                Host::Ipv6(ip) => hosts.push(IpAddr::V6(*ip)),
-                Host::Domain(domain) => Self::resolve_domain(domain, &mut hosts)?,
+        for target in &targets {
+            match target {
+                Target::Host(Host::Ipv4(ip)) => hosts.push(IpAddr::V4(*ip)),
+                Target::Host(Host::Ipv6(ip)) => hosts.push(IpAddr::V6(*ip)),
+                Target::Host(Host::Domain(domain)) => {
+                    let mut addresses = Self::resolve_domain(domain)?;
+                    // A single trace was always shown for a name in text
+                    if self.format() == Format::Text {
+                        let first = addresses.iter().find(|address| address.is_ipv4());
+                        let first = first.or(addresses.first()).copied();
+                        addresses = first.into_iter().collect();
+                    }
+                    hosts.extend(addresses);
+                }
+                Target::Network(_network) => hosts.extend(target.sample(self.sampling)),
             }
         }
 
+        // The same address may be listed or resolved more than once
+        let mut seen = HashSet::new();
+        hosts.retain(|host| seen.insert(*host));
+
         Ok(hosts)
     }
 
+    // Targets listed in a file or stdin
+    fn read_targets_file(path: &Path) -> Result<Vec<Target>, TracerouteError> {
+        if path == Path::new("-") {
+            return read_targets(io::stdin().lock());
+        }
+        read_targets(BufReader::new(File::open(path)?))
+    }
+
     // Get IP addresses associated with domain
-    fn resolve_domain(domain: &str, hosts: &mut Vec<IpAddr>) -> Result<(), TracerouteError> {
+    fn resolve_domain(domain: &str) -> Result<Vec<IpAddr>, TracerouteError> {
         let start = Instant::now();
 
-        for addr in resolve_host(&domain)? {
-            match addr {
-                IpAddr::V4(ip) => hosts.push(IpAddr::V4(ip)),
-                IpAddr::V6(ip) => hosts.push(IpAddr::V6(ip)),
-            }
-        }
+        let hosts = resolve_host(&domain)?.collect::<Vec<IpAddr>>();
         let duration = Instant::now().duration_since(start);
         info!(
             "DNS resolution for {} took {:.3?} and found {} hosts",
@@ -102,6 +317,35 @@ impl Options {
             duration,
             hosts.len()
         );
-        Ok(())
+        Ok(hosts)
+    }
+}
+
+fn parse_size(value: &str) -> Result<u16, String> {
+    let size = value.parse::<u16>().map_err(|err| err.to_string())?;
+    if size < MIN_PROBE_SIZE {
+        return Err(format!("Probes are at least {} bytes", MIN_PROBE_SIZE));
+    }
+    Ok(size)
+}
+
+fn parse_dscp(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(dscp) if dscp <= 63 => Ok(dscp),
+        _ => Err("DSCP is a number from 0 to 63".to_string()),
+    }
+}
+
+fn parse_ecn(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(ecn) if ecn <= 3 => Ok(ecn),
+        _ => Err("ECN is a number from 0 to 3".to_string()),
+    }
+}
+
+fn parse_attempts(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(attempts) if attempts >= 1 => Ok(attempts),
+        _ => Err("attempts is a number from 1 to 255".to_string()),
     }
 }
@@ -1,10 +1,10 @@
 use crate::TracerouteError;
 use crate::probe::ProbeBundle;
-use crate::protocol::Protocol;
+use crate::trace::TraceOptions;
 
 pub trait PacketBuilderTrait<A, P> {
     fn build(
-        protocol: Protocol,
+        options: &TraceOptions,
         source: A,
         dest: A,
         ttl: u8,
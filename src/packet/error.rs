@@ -0,0 +1,27 @@
+use std::{error::Error, fmt};
+
+/// Represents an error which occurred whilst parsing a payload pattern.
+#[derive(Copy, Debug, PartialEq, Eq, Clone)]
+pub enum ParsePayloadErr {
+    /// Pattern isn't made of pairs of hex digits
+    InvalidHex,
+    /// Pattern is longer than [`MAX_PATTERN`](super::MAX_PATTERN) bytes
+    TooLong,
+}
+
+impl Error for ParsePayloadErr {}
+
+impl fmt::Display for ParsePayloadErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsePayloadErr::InvalidHex => write!(f, "Payload pattern must be hex bytes"),
+            ParsePayloadErr::TooLong => {
+                write!(
+                    f,
+                    "Payload pattern is longer than {} bytes",
+                    super::MAX_PATTERN
+                )
+            }
+        }
+    }
+}
@@ -1,50 +1,89 @@
 use crate::TracerouteError;
-use crate::packet::{PacketBuilder, PacketBuilderTrait};
+use crate::packet::{PacketBuilder, PacketBuilderTrait, Payload};
 use crate::probe::{Probe, ProbeBundle};
 use crate::protocol::{Protocol, UdpParams};
+use crate::trace::{DEFAULT_PROBE_SIZE, MIN_PROBE_SIZE, TraceOptions};
 
-use pnet::packet::MutablePacket;
+use pnet::packet::{MutablePacket, Packet};
 use pnet::packet::icmp::{self, IcmpCode, IcmpTypes, MutableIcmpPacket};
-use pnet::packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::ipv4::{self, Ipv4Flags, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpFlags, TcpOption};
 use pnet::packet::udp::{self, MutableUdpPacket};
 use rand::prelude::*;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::net::{IpAddr,Ipv4Addr};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
 
-// 24 is the size of the payload we attached to the udp packet;
-const IPV4_BUFFER_SIZE: usize =
-    MutableIpv4Packet::minimum_packet_size() + MutableUdpPacket::minimum_packet_size() + 24;
+// Ports of TCP probes. Web servers are the most likely to be let through
+pub(crate) const TCP_SOURCE_PORT: u16 = 33434;
+pub(crate) const TCP_DESTINATION_PORT: u16 = 80;
+
+// TCP header with the options of a SYN from a typical host: MSS, SACK permitted and window scale
+const TCP_HEADER_SIZE: usize = 32;
+const TCP_MIN_PROBE_SIZE: u16 = 20 + TCP_HEADER_SIZE as u16;
+const TCP_MSS: u16 = 1460;
+const TCP_WINDOW: u16 = 64240;
+const TCP_WINDOW_SCALE: u8 = 7;
+
+// Ids handed out so far, added to a random start so traceroutes running side by side differ
+static NEXT_IP_ID: AtomicU16 = AtomicU16::new(0);
+static IP_ID_START: OnceLock<u16> = OnceLock::new();
+
+// IPv4 id of the next probe. Ids only repeat after 65535 more probes, far more than are ever
+// waiting on a reply. Checksums can't take every value so neither can ids standing in for them
+fn next_ip_id(broken_nat: bool) -> u16 {
+    let start = *IP_ID_START.get_or_init(|| rand::rng().random());
+    loop {
+        let id = start.wrapping_add(NEXT_IP_ID.fetch_add(1, Ordering::Relaxed));
+        if !broken_nat || (id != 0 && id != u16::MAX) {
+            return id;
+        }
+    }
+}
 
 impl PacketBuilderTrait<Ipv4Addr, Ipv4Packet<'_>> for PacketBuilder {
     fn build(
-        protocol: Protocol,
+        options: &TraceOptions,
         source: Ipv4Addr,
         dest: Ipv4Addr,
         ttl: u8,
     ) -> Result<ProbeBundle<Ipv4Packet<'static>>, TracerouteError> {
+        let protocol = options.protocol;
         // Validate if protocol is supported
         let _ = match protocol {
-            Protocol::ICMP | Protocol::UDP(_) => (),
+            Protocol::ICMP | Protocol::TCP | Protocol::UDP(_) => (),
             protocol => Err(TracerouteError::UnimplimentedProtocol(protocol))?,
         };
 
         // Create buffer for packet to fill into
-        let buf = vec![0u8; IPV4_BUFFER_SIZE];
+        let size = match protocol {
+            Protocol::TCP => options.size.max(TCP_MIN_PROBE_SIZE),
+            _ => options.size.max(MIN_PROBE_SIZE),
+        };
+        let buf = vec![0u8; size.into()];
         let mut ip_header =
             MutableIpv4Packet::owned(buf).ok_or(TracerouteError::MalformedPacket)?;
 
         // get source of randomness
         let mut rng = rand::rng();
 
-        // Generate random IPv4 packet id
-        let ip_id = rng.random::<u16>();
+        let ip_id = next_ip_id(options.broken_nat);
 
+        // Fields left to the options are set before the header checksum is calculated
+        ip_header.set_dscp(options.dscp);
+        ip_header.set_ecn(options.ecn);
+        if options.dont_fragment {
+            ip_header.set_flags(Ipv4Flags::DontFragment);
+        }
         // Return is only for errors
         let _ = set_ip_header_values(&mut ip_header, ttl, protocol, source, dest, ip_id)?;
 
-        let (flowhash, checksum) = match protocol {
+        let (flowhash, checksum, transport_length) = match protocol {
             Protocol::UDP(params) => {
+                let params = options.ports.probe_params(params, ttl);
                 let flowhash = flowhash(
                     &ip_header,
                     source,
@@ -52,8 +91,27 @@ impl PacketBuilderTrait<Ipv4Addr, Ipv4Packet<'_>> for PacketBuilder {
                     Some(params.source_port),
                     Some(params.destination_port),
                 );
-                let checksum = build_udp_packet(&mut ip_header, &source, &dest, params)?;
-                (flowhash, checksum)
+                let mut checksum =
+                    build_udp_packet(&mut ip_header, &source, &dest, params, &options.payload)?;
+                // As Dublin Traceroute does, the checksum also identifies the probe so replies
+                // can be matched when a NAT mangles the quoted id
+                if options.broken_nat {
+                    checksum = fix_udp_checksum(&mut ip_header, &source, &dest, ip_id)?;
+                }
+                (flowhash, checksum, MutableUdpPacket::minimum_packet_size())
+            }
+            Protocol::TCP => {
+                let flowhash = flowhash(
+                    &ip_header,
+                    source,
+                    dest,
+                    Some(TCP_SOURCE_PORT),
+                    Some(TCP_DESTINATION_PORT),
+                );
+                let sequence = rng.random::<u32>();
+                let checksum =
+                    build_tcp_packet(&mut ip_header, &source, &dest, sequence, &options.payload)?;
+                (flowhash, checksum, TCP_HEADER_SIZE)
             }
             //Protocol::ICMP => {
             //    let flowhash = flowhash(&ip_header, source, dest, None, None);
@@ -63,13 +121,46 @@ impl PacketBuilderTrait<Ipv4Addr, Ipv4Packet<'_>> for PacketBuilder {
             protocol => Err(TracerouteError::UnimplimentedProtocol(protocol))?,
         };
 
+        let headers_length = MutableIpv4Packet::minimum_packet_size() + transport_length;
+        let headers = ip_header.packet()[..headers_length].to_vec();
         let packet = ip_header.consume_to_immutable();
-        let probe = Probe::new(IpAddr::V4(source), ttl, ip_id, checksum, flowhash);
+        let tos = options.tos();
+        let probe = Probe::new(IpAddr::V4(source), ttl, ip_id, checksum, flowhash, tos, headers);
+
+        let timeout = Duration::from_millis(options.timeout.into());
 
-        Ok(ProbeBundle { packet, probe })
+        Ok(ProbeBundle {
+            packet,
+            probe,
+            timeout,
+        })
     }
 }
 
+/// Build a UDP probe with the given IP id and UDP checksum, leaving the bookkeeping to the caller
+///
+/// Used by stateless probing where everything needed to match a reply is carried in the headers.
+/// The payload is adjusted to land on `checksum`, which can't be 0xffff.
+pub fn build_udp_probe(
+    source: Ipv4Addr,
+    dest: Ipv4Addr,
+    ttl: u8,
+    ip_id: u16,
+    params: UdpParams,
+    checksum: u16,
+) -> Result<Ipv4Packet<'static>, TracerouteError> {
+    let buf = vec![0u8; DEFAULT_PROBE_SIZE.into()];
+    let mut ip_header =
+        MutableIpv4Packet::owned(buf).ok_or(TracerouteError::MalformedPacket)?;
+
+    let protocol = Protocol::UDP(params);
+    set_ip_header_values(&mut ip_header, ttl, protocol, source, dest, ip_id)?;
+    let _checksum = build_udp_packet(&mut ip_header, &source, &dest, params, &Payload::default())?;
+    let _checksum = fix_udp_checksum(&mut ip_header, &source, &dest, checksum)?;
+
+    Ok(ip_header.consume_to_immutable())
+}
+
 fn set_ip_header_values(
     ip_header: &mut MutableIpv4Packet,
     ttl: u8,
@@ -80,9 +171,8 @@ fn set_ip_header_values(
 ) -> Result<(), TracerouteError> {
     ip_header.set_version(4);
     ip_header.set_header_length(5);
-    ip_header.set_dscp(0);
-    ip_header.set_ecn(0);
-    ip_header.set_total_length(52);
+    // The buffer was sized to the probe
+    ip_header.set_total_length(ip_header.packet().len() as u16);
     ip_header.set_ttl(ttl);
     ip_header.set_next_level_protocol(protocol.into());
     ip_header.set_source(source);
@@ -125,15 +215,47 @@ fn build_udp_packet(
     source: &Ipv4Addr,
     destination_ip: &Ipv4Addr,
     params: UdpParams,
+    payload: &Payload,
 ) -> Result<u16, TracerouteError> {
     let mut udp_header =
         MutableUdpPacket::new(ip_header.payload_mut()).ok_or(TracerouteError::MalformedPacket)?;
 
     udp_header.set_source(params.source_port);
     udp_header.set_destination(params.destination_port);
-    // 8 bytes for the udp header and 24 for the payload
-    udp_header.set_length(32_u16);
-    udp_header.set_payload(&[0_u8; 24]);
+    // 8 bytes for the udp header and whatever the probe size leaves for the zeroed payload
+    let length = udp_header.packet().len();
+    udp_header.set_length(length as u16);
+    payload.fill(udp_header.payload_mut());
+
+    let checksum = udp::ipv4_checksum(&udp_header.to_immutable(), source, destination_ip);
+    udp_header.set_checksum(checksum);
+
+    Ok(checksum)
+}
+
+// Rewrite the first two payload bytes so the UDP checksum comes out as `target`
+//
+// Probes without a payload keep their checksum.
+fn fix_udp_checksum(
+    ip_header: &mut MutableIpv4Packet,
+    source: &Ipv4Addr,
+    destination_ip: &Ipv4Addr,
+    target: u16,
+) -> Result<u16, TracerouteError> {
+    let mut udp_header =
+        MutableUdpPacket::new(ip_header.payload_mut()).ok_or(TracerouteError::MalformedPacket)?;
+    if udp_header.payload().len() < 2 {
+        return Ok(udp_header.get_checksum());
+    }
+
+    udp_header.payload_mut()[..2].fill(0);
+    udp_header.set_checksum(0);
+    let checksum = udp::ipv4_checksum(&udp_header.to_immutable(), source, destination_ip);
+
+    // The checksum is the complement of a ones' complement sum. Adding a word to the sum moves
+    // the checksum from `checksum` to `target`
+    let word = ones_complement_add(!target, checksum);
+    udp_header.payload_mut()[..2].copy_from_slice(&word.to_be_bytes());
 
     let checksum = udp::ipv4_checksum(&udp_header.to_immutable(), source, destination_ip);
     udp_header.set_checksum(checksum);
@@ -141,6 +263,44 @@ fn build_udp_packet(
     Ok(checksum)
 }
 
+fn ones_complement_add(a: u16, b: u16) -> u16 {
+    let sum = a as u32 + b as u32;
+    ((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+// Build TCP SYN probe. Hops quote its options back so middleboxes rewriting them stand out
+fn build_tcp_packet(
+    ip_header: &mut MutableIpv4Packet,
+    source: &Ipv4Addr,
+    destination_ip: &Ipv4Addr,
+    sequence: u32,
+    payload: &Payload,
+) -> Result<u16, TracerouteError> {
+    let mut tcp_header =
+        MutableTcpPacket::new(ip_header.payload_mut()).ok_or(TracerouteError::MalformedPacket)?;
+
+    tcp_header.set_source(TCP_SOURCE_PORT);
+    tcp_header.set_destination(TCP_DESTINATION_PORT);
+    tcp_header.set_sequence(sequence);
+    tcp_header.set_data_offset((TCP_HEADER_SIZE / 4) as u8);
+    tcp_header.set_flags(TcpFlags::SYN);
+    tcp_header.set_window(TCP_WINDOW);
+    tcp_header.set_options(&[
+        TcpOption::mss(TCP_MSS),
+        TcpOption::nop(),
+        TcpOption::nop(),
+        TcpOption::sack_perm(),
+        TcpOption::nop(),
+        TcpOption::wscale(TCP_WINDOW_SCALE),
+    ]);
+    payload.fill(tcp_header.payload_mut());
+
+    let checksum = tcp::ipv4_checksum(&tcp_header.to_immutable(), source, destination_ip);
+    tcp_header.set_checksum(checksum);
+
+    Ok(checksum)
+}
+
 fn build_icmp_packet(ip_header: &mut MutableIpv4Packet) -> Result<u16, TracerouteError> {
     let mut icmp_header =
         MutableIcmpPacket::new(ip_header.payload_mut()).ok_or(TracerouteError::MalformedPacket)?;
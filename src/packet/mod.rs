@@ -1,4 +1,10 @@
 mod builder;
+mod error;
 mod ipv4;
+mod payload;
 
 pub use builder::{PacketBuilder, PacketBuilderTrait};
+pub use error::ParsePayloadErr;
+pub use ipv4::build_udp_probe;
+pub(crate) use ipv4::{TCP_DESTINATION_PORT, TCP_SOURCE_PORT};
+pub use payload::{MAX_PATTERN, Payload};
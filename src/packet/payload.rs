@@ -0,0 +1,179 @@
+use std::{fmt, str::FromStr};
+
+use super::ParsePayloadErr;
+
+/// Longest pattern a payload repeats
+pub const MAX_PATTERN: usize = 16;
+
+/// Bytes repeated over the payload of a probe. An empty pattern leaves the payload zeroed
+///
+/// Written as hex like `ping -p`. ex: `ff00` or `0xdeadbeef`
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct Payload {
+    pattern: [u8; MAX_PATTERN],
+    len: u8,
+}
+
+impl Payload {
+    pub fn new(pattern: &[u8]) -> Result<Self, ParsePayloadErr> {
+        if pattern.len() > MAX_PATTERN {
+            return Err(ParsePayloadErr::TooLong);
+        }
+        let mut payload = Self {
+            len: pattern.len() as u8,
+            ..Default::default()
+        };
+        payload.pattern[..pattern.len()].copy_from_slice(pattern);
+        Ok(payload)
+    }
+
+    /// Bytes being repeated
+    pub fn pattern(&self) -> &[u8] {
+        &self.pattern[..self.len.into()]
+    }
+
+    /// Write the pattern over `buf` from the start, cutting the last repetition short
+    pub fn fill(&self, buf: &mut [u8]) {
+        let pattern = self.pattern();
+        if pattern.is_empty() {
+            buf.fill(0);
+            return;
+        }
+        for (byte, value) in buf.iter_mut().zip(pattern.iter().cycle()) {
+            *byte = *value;
+        }
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.pattern() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Payload {
+    type Err = ParsePayloadErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        // from_str_radix would also take a sign
+        if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ParsePayloadErr::InvalidHex);
+        }
+
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_err| ParsePayloadErr::InvalidHex)?;
+
+        Self::new(&bytes)
+    }
+}
+
+impl TryFrom<String> for Payload {
+    type Error = ParsePayloadErr;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Payload> for String {
+    fn from(payload: Payload) -> Self {
+        payload.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Result<Vec<u8>, ParsePayloadErr> {
+        s.parse::<Payload>()
+            .map(|payload| payload.pattern().to_vec())
+    }
+
+    #[test]
+    fn hex_is_parsed_with_or_without_prefix() {
+        assert_eq!(pattern("ff00"), Ok(vec![0xff, 0x00]));
+        assert_eq!(pattern("0xDeadBeef"), Ok(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(pattern(""), Ok(vec![]));
+        assert_eq!(pattern("0x"), Ok(vec![]));
+        assert_eq!(pattern("0x0x"), Err(ParsePayloadErr::InvalidHex));
+    }
+
+    #[test]
+    fn only_pairs_of_hex_digits_are_taken() {
+        for invalid in ["f", "fff", "zz", "+f", "-1", " ff", "ff ", "\u{e9}"] {
+            assert_eq!(
+                pattern(invalid),
+                Err(ParsePayloadErr::InvalidHex),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn patterns_are_limited() {
+        let longest = "ab".repeat(MAX_PATTERN);
+        assert_eq!(pattern(&longest).map(|bytes| bytes.len()), Ok(MAX_PATTERN));
+        assert_eq!(
+            pattern(&format!("{}ab", longest)),
+            Err(ParsePayloadErr::TooLong)
+        );
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for s in [
+            "",
+            "00",
+            "0a0b",
+            "deadbeef",
+            "0123456789abcdef0123456789abcdef",
+        ] {
+            let payload: Payload = s.parse().unwrap();
+            assert_eq!(payload.to_string(), s);
+            assert_eq!(payload.to_string().parse::<Payload>(), Ok(payload));
+        }
+    }
+
+    #[test]
+    fn fill_repeats_the_pattern() {
+        let payload: Payload = "a1b2c3".parse().unwrap();
+        let mut buf = [0xee; 8];
+        payload.fill(&mut buf);
+        assert_eq!(buf, [0xa1, 0xb2, 0xc3, 0xa1, 0xb2, 0xc3, 0xa1, 0xb2]);
+
+        // Shorter than a single repetition
+        let mut buf = [0xee; 2];
+        payload.fill(&mut buf);
+        assert_eq!(buf, [0xa1, 0xb2]);
+    }
+
+    #[test]
+    fn empty_patterns_zero_the_payload() {
+        let mut buf = [0xee; 4];
+        Payload::default().fill(&mut buf);
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialized_as_hex() {
+        let payload: Payload = "ff00".parse().unwrap();
+        assert_eq!(serde_json::to_string(&payload).unwrap(), "\"ff00\"");
+        assert_eq!(
+            serde_json::from_str::<Payload>("\"0xff00\"").unwrap(),
+            payload
+        );
+        assert!(serde_json::from_str::<Payload>("\"xyz\"").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+mod pmtu;
+mod report;
+
+pub use pmtu::{PathMtu, PmtuOptions};
+pub use report::{HopMtu, PmtuReport};
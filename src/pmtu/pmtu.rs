@@ -0,0 +1,378 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use async_std::task;
+
+use log::*;
+
+use super::{HopMtu, PmtuReport};
+use crate::TracerouteError;
+use crate::prelude::TTL;
+use crate::probe::ReplyKind;
+use crate::trace::{MIN_PROBE_SIZE, TraceOptions, TraceResponse, next_rounds};
+use crate::traceroute::Traceroute;
+
+/// Configuration of [`PathMtu`] discovery
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PmtuOptions {
+    /// Probe size in bytes the search starts from
+    pub max_size: u16,
+    /// Smallest probe size tried. 68 bytes is the MTU every IPv4 link must carry
+    pub min_size: u16,
+    /// Extra probes sent before a silent size is taken as lost
+    pub retries: u8,
+    /// Pause before each retry. Routers rate limit their ICMP messages, often to one a second
+    pub retry_delay: Duration,
+}
+
+impl Default for PmtuOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 1500,
+            min_size: 68,
+            retries: 2,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+// What became of a single probe
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    // Got through to the probed distance
+    Passed { address: IpAddr, reached: bool },
+    // A router or our own kernel refused to fragment it
+    TooBig { mtu: Option<u16> },
+    Silent,
+}
+
+// Sizes left to search, `lower` passes the distance and `upper` doesn't
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Bounds {
+    lower: u16,
+    upper: u16,
+}
+
+impl Bounds {
+    fn new(lower: u16, upper: u16) -> Self {
+        Self { lower, upper }
+    }
+
+    // Next size to probe, None once `lower` is the largest to pass
+    fn middle(&self) -> Option<u16> {
+        let gap = self.upper.saturating_sub(self.lower);
+        (gap > 1).then(|| self.lower + gap / 2)
+    }
+
+    // Narrow the search down to the side of `middle` the outcome points to
+    fn narrow(&mut self, middle: u16, outcome: Outcome) {
+        match outcome {
+            Outcome::Passed { .. } => self.lower = middle,
+            // A reported MTU below the middle narrows the search further
+            Outcome::TooBig { mtu: Some(mtu) } if (self.lower..middle).contains(&mtu) => {
+                self.upper = mtu + 1
+            }
+            Outcome::TooBig { .. } | Outcome::Silent => self.upper = middle,
+        }
+    }
+}
+
+// Where probes go and how they are sent
+struct Route<'a> {
+    traceroute: &'a Traceroute,
+    source: IpAddr,
+    destination: IpAddr,
+    options: TraceOptions,
+}
+
+/// Path MTU discovery probing each hop with Don't Fragment probes
+///
+/// The size shrinks to the next hop MTU routers report in Fragmentation Needed messages. When
+/// none is reported or large probes vanish while small ones get through, the largest size to pass
+/// is binary searched instead. Hops dropping large probes silently are called out as black holes.
+#[derive(Debug, Default)]
+pub struct PathMtu {
+    options: PmtuOptions,
+    probes_sent: usize,
+}
+
+impl PathMtu {
+    pub fn new(options: PmtuOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Probes sent over every discovery
+    pub fn probes_sent(&self) -> usize {
+        self.probes_sent
+    }
+
+    /// Find the largest probe reaching each hop towards `destination`
+    ///
+    /// Probes use the protocol, distances and timeout of `options`.
+    pub async fn discover(
+        &mut self,
+        traceroute: &Traceroute,
+        source: IpAddr,
+        destination: IpAddr,
+        options: TraceOptions,
+    ) -> Result<PmtuReport, TracerouteError> {
+        let route = Route {
+            traceroute,
+            source,
+            destination,
+            options,
+        };
+        let min_size = self.options.min_size.max(MIN_PROBE_SIZE);
+        let mut size = self.options.max_size.max(min_size);
+        let mut hops: Vec<HopMtu> = Vec::new();
+        let mut reached = false;
+
+        let mut ttl = options.min_ttl;
+        while ttl <= options.max_ttl {
+            let outcome = self.probe(&route, ttl, size).await?;
+
+            match outcome {
+                Outcome::Passed {
+                    address,
+                    reached: at_destination,
+                } => {
+                    push_hop(&mut hops, ttl, Some(address), Some(size));
+                    if at_destination {
+                        reached = true;
+                        break;
+                    }
+                }
+                Outcome::TooBig { mtu } => {
+                    let hop = hop_entry(&mut hops, ttl);
+                    hop.reported = mtu.or(hop.reported);
+                    // Nothing smaller is left to try, further hops won't pass either
+                    if size <= min_size {
+                        warn!(
+                            "Probes of {} bytes are too big for TTL {} towards {}",
+                            size, ttl, destination
+                        );
+                        break;
+                    }
+                    let smaller = match mtu {
+                        Some(mtu) if (min_size..size).contains(&mtu) => mtu,
+                        _ => self.search(&route, ttl, min_size, size).await?,
+                    };
+                    debug!(
+                        "Probe of {} bytes too big at TTL {}, trying {}",
+                        size, ttl, smaller
+                    );
+                    size = smaller;
+                    // Probe the same distance again with the smaller size
+                    continue;
+                }
+                Outcome::Silent => {
+                    if size > min_size {
+                        let small = self.probe(&route, ttl, min_size).await?;
+                        if let Outcome::Passed { .. } = small {
+                            let largest = self.search(&route, ttl, min_size, size).await?;
+                            warn!(
+                                "Probes over {} bytes vanish before TTL {} towards {}",
+                                largest, ttl, destination
+                            );
+                            hop_entry(&mut hops, ttl).black_hole = true;
+                            size = largest;
+                            continue;
+                        }
+                    }
+                    // Not answering at any size
+                    push_hop(&mut hops, ttl, None, None);
+                }
+            }
+            ttl += 1;
+        }
+
+        let path_mtu = hops.iter().rev().find_map(|hop| hop.mtu).unwrap_or(size);
+
+        Ok(PmtuReport {
+            source,
+            destination,
+            path_mtu,
+            reached,
+            hops,
+        })
+    }
+
+    // Largest size in lower..upper passing the distance. `lower` is assumed to pass and `upper`
+    // is known not to
+    async fn search(
+        &mut self,
+        route: &Route<'_>,
+        ttl: TTL,
+        lower: u16,
+        upper: u16,
+    ) -> Result<u16, TracerouteError> {
+        let mut bounds = Bounds::new(lower, upper);
+        while let Some(middle) = bounds.middle() {
+            let outcome = self.probe(route, ttl, middle).await?;
+            bounds.narrow(middle, outcome);
+        }
+        Ok(bounds.lower)
+    }
+
+    // Send a single Don't Fragment probe, retrying when nothing comes back
+    async fn probe(
+        &mut self,
+        route: &Route<'_>,
+        ttl: TTL,
+        size: u16,
+    ) -> Result<Outcome, TracerouteError> {
+        let Route {
+            traceroute,
+            source,
+            destination,
+            options,
+        } = *route;
+        let options = TraceOptions {
+            min_ttl: ttl,
+            max_ttl: ttl,
+            size,
+            dont_fragment: true,
+            ..options
+        };
+
+        for attempt in 0..=self.options.retries {
+            if attempt > 0 {
+                task::sleep(self.options.retry_delay).await;
+            }
+            let mut traces = [traceroute.trace(source, destination, options)?];
+            let round = next_rounds(&mut traces).await.pop().flatten();
+            self.probes_sent += traces[0].probes_sent();
+
+            let responses = match round {
+                Some(Ok(responses)) => responses,
+                // Our own kernel already knows the route can't carry this size
+                Some(Err(TracerouteError::Io(err)))
+                    if err.raw_os_error() == Some(libc::EMSGSIZE) =>
+                {
+                    return Ok(Outcome::TooBig { mtu: None });
+                }
+                Some(Err(err)) => return Err(err),
+                None => Vec::new(),
+            };
+
+            for response in responses {
+                if let TraceResponse::Received(resp) = response {
+                    let outcome = match resp.kind {
+                        ReplyKind::FragmentationNeeded { mtu } => Outcome::TooBig { mtu },
+                        ReplyKind::TimeExceeded => Outcome::Passed {
+                            address: resp.destination,
+                            reached: false,
+                        },
+                        ReplyKind::EchoReply
+                        | ReplyKind::Unreachable { .. }
+                        | ReplyKind::SynAck
+                        | ReplyKind::Reset => Outcome::Passed {
+                            address: resp.destination,
+                            reached: resp.destination == destination,
+                        },
+                    };
+                    return Ok(outcome);
+                }
+            }
+        }
+        Ok(Outcome::Silent)
+    }
+}
+
+fn hop_entry(hops: &mut Vec<HopMtu>, ttl: TTL) -> &mut HopMtu {
+    let index = match hops.iter().rposition(|hop| hop.ttl == ttl) {
+        Some(index) => index,
+        None => {
+            hops.push(HopMtu {
+                ttl,
+                address: None,
+                mtu: None,
+                reported: None,
+                black_hole: false,
+            });
+            hops.len() - 1
+        }
+    };
+    &mut hops[index]
+}
+
+fn push_hop(hops: &mut Vec<HopMtu>, ttl: TTL, address: Option<IpAddr>, mtu: Option<u16>) {
+    let hop = hop_entry(hops, ttl);
+    hop.address = address;
+    hop.mtu = mtu;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const HOP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    const PASSED: Outcome = Outcome::Passed {
+        address: HOP,
+        reached: false,
+    };
+
+    // Largest size to pass and the number of probes it took, `outcome` standing in for the path
+    fn search(lower: u16, upper: u16, outcome: impl Fn(u16) -> Outcome) -> (u16, usize) {
+        let mut bounds = Bounds::new(lower, upper);
+        let mut probes = 0;
+        while let Some(middle) = bounds.middle() {
+            assert!(lower < middle && middle < upper);
+            bounds.narrow(middle, outcome(middle));
+            probes += 1;
+        }
+        (bounds.lower, probes)
+    }
+
+    #[test]
+    fn silent_drops_are_bisected() {
+        for mtu in [68, 69, 576, 1280, 1400, 1499] {
+            let (found, probes) = search(68, 1500, |size| match size <= mtu {
+                true => PASSED,
+                false => Outcome::Silent,
+            });
+            assert_eq!(found, mtu);
+            // log2 of the 1432 sizes in between
+            assert!(probes <= 11, "{} probes for {}", probes, mtu);
+        }
+    }
+
+    #[test]
+    fn reported_mtus_narrow_the_search() {
+        let mut bounds = Bounds::new(68, 1500);
+        bounds.narrow(1321, Outcome::TooBig { mtu: Some(1280) });
+        assert_eq!(bounds, Bounds::new(68, 1281));
+
+        let (found, _probes) = search(68, 1500, |size| match size <= 1280 {
+            true => PASSED,
+            false => Outcome::TooBig { mtu: Some(1280) },
+        });
+        assert_eq!(found, 1280);
+    }
+
+    #[test]
+    fn unusable_reported_mtus_are_ignored() {
+        // Routers reporting nothing, their incoming MTU or the size they were sent
+        let reports: [fn(u16) -> Option<u16>; 3] = [|_| None, |_| Some(1500), Some];
+        for report in reports {
+            let (found, _probes) = search(68, 1500, |size| match size <= 1000 {
+                true => PASSED,
+                false => Outcome::TooBig { mtu: report(size) },
+            });
+            assert_eq!(found, 1000);
+        }
+    }
+
+    #[test]
+    fn nothing_is_left_between_neighbours() {
+        assert_eq!(Bounds::new(1000, 1001).middle(), None);
+        assert_eq!(Bounds::new(1000, 1000).middle(), None);
+        assert_eq!(Bounds::new(1000, 68).middle(), None);
+        assert_eq!(Bounds::new(1000, 1002).middle(), Some(1001));
+    }
+}
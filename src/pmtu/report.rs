@@ -0,0 +1,85 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::prelude::TTL;
+
+/// Largest probe known to reach a hop
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HopMtu {
+    pub ttl: TTL,
+    /// Interface which replied. None when the hop stayed silent at every size
+    pub address: Option<IpAddr>,
+    /// Largest probe size in bytes which reached this hop
+    pub mtu: Option<u16>,
+    /// Next hop MTU from a Fragmentation Needed message received while probing this distance
+    pub reported: Option<u16>,
+    /// Larger probes vanished here without a Fragmentation Needed message
+    pub black_hole: bool,
+}
+
+impl fmt::Display for HopMtu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let address = match self.address {
+            Some(address) => address.to_string(),
+            None => "*".to_string(),
+        };
+        write!(f, "{:>2} {:<15}", self.ttl, address)?;
+        if let Some(mtu) = self.mtu {
+            write!(f, " {:>5}", mtu)?;
+        }
+        if let Some(reported) = self.reported {
+            write!(f, " reported {}", reported)?;
+        }
+        if self.black_hole {
+            write!(f, " black hole")?;
+        }
+        Ok(())
+    }
+}
+
+/// Path MTU towards a destination with the hops where it shrinks
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PmtuReport {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    /// Largest probe which got through to the last replying hop
+    pub path_mtu: u16,
+    /// The destination itself replied
+    pub reached: bool,
+    pub hops: Vec<HopMtu>,
+}
+
+impl PmtuReport {
+    /// Hops which drop oversized probes silently
+    pub fn black_holes(&self) -> impl Iterator<Item = &HopMtu> {
+        self.hops.iter().filter(|hop| hop.black_hole)
+    }
+}
+
+impl fmt::Display for PmtuReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Path MTU {} -> {}: {}",
+            self.source, self.destination, self.path_mtu
+        )?;
+        if !self.reached {
+            write!(f, " (destination not reached)")?;
+        }
+        writeln!(f)?;
+        for hop in &self.hops {
+            writeln!(f, "{}", hop)?;
+        }
+        for hop in self.black_holes() {
+            writeln!(
+                f,
+                "Warning: probes over {} bytes are dropped without notice before hop {}",
+                hop.mtu.unwrap_or_default(),
+                hop.ttl
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -15,8 +15,25 @@ pub type TTL = u8;
 pub type TcpId = u16;
 pub type Checksum = u16;
 pub type Flowhash = u16;
+pub type TraceId = u64;
 
-pub use crate::protocol::Protocol;
-pub use crate::sockets::SocketJoinResult;
-pub use crate::trace::{Trace, TraceOptions, TraceData};
-pub use crate::traceroute::{Traceroute, TracerouteError};
+pub use crate::alias::{Alias, AliasOptions, AliasReport, AliasResolver, Evidence, Router};
+pub use crate::analysis::Anomaly;
+pub use crate::discovery::{Doubletree, DoubletreeOptions, StopSet};
+pub use crate::export::{ExportOptions, Format};
+pub use crate::middlebox::{Middlebox, MiddleboxKind};
+pub use crate::packet::Payload;
+pub use crate::pmtu::{HopMtu, PathMtu, PmtuOptions, PmtuReport};
+pub use crate::probe::{HeaderChange, ReplyKind};
+pub use crate::protocol::{PortList, PortStrategy, Protocol};
+pub use crate::sockets::{
+    Backend, Binding, CaptureHook, Direction, EngineCounters, EngineStats, RateLimit, Route,
+    ShutdownHandle, SocketJoinResult,
+};
+pub use crate::targets::{Sampling, Target, read_targets};
+pub use crate::trace::{
+    HopEvent, HopEvents, MIN_BROKEN_NAT_SIZE, RttEstimate, Trace, TraceData, TraceFlow,
+    TraceOptions,
+};
+pub use crate::traceroute::{Traceroute, TracerouteBuilder, TracerouteError};
+pub use crate::yarrp::{Yarrp, YarrpOptions, YarrpRecord, YarrpSummary};
@@ -1,7 +1,10 @@
 use crate::probe::Probe;
+use std::time::Duration;
 
 /// Package containing the raw packet and corresponding [`Probe`] needed to correlate the two later
 pub struct ProbeBundle<P> {
     pub probe: Probe,
     pub packet: P,
+    /// How long to wait for a reply once sent
+    pub timeout: Duration,
 }
@@ -0,0 +1,40 @@
+//! Made up probes and replies for the tests of whatever reads them
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+use super::{ProbeResponse, ProbeSent, ReplyKind};
+use crate::prelude::TTL;
+
+/// Source every made up probe leaves from
+pub(crate) const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+/// A UDP probe sent with `ttl` and nothing else set
+pub(crate) fn sent(ttl: TTL) -> ProbeSent {
+    ProbeSent {
+        source: SOURCE,
+        ttl,
+        id: ttl.into(),
+        checksum: 0,
+        flowhash: 0,
+        tos: 0,
+        headers: Vec::new(),
+        instant: Instant::now(),
+    }
+}
+
+/// A time exceeded from `hop` to the probe sent with `ttl`, quoting the probe untouched
+pub(crate) fn received(ttl: TTL, hop: IpAddr, ping: Duration) -> ProbeResponse {
+    ProbeResponse {
+        ttl,
+        destination: hop,
+        ping,
+        sent: sent(ttl),
+        kind: ReplyKind::TimeExceeded,
+        quoted_tos: Some(0),
+        reply_id: 0,
+        reply_ttl: 0,
+        quoted_ttl: Some(1),
+        quoted_checksum: Some(0),
+        modifications: Vec::new(),
+    }
+}
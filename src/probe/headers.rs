@@ -0,0 +1,574 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+
+use super::ReplyKind;
+
+// TCP option kinds
+const TCP_OPTION_END: u8 = 0;
+const TCP_OPTION_NOP: u8 = 1;
+const TCP_OPTION_MSS: u8 = 2;
+
+/// A header field of a probe which arrived at a hop different from how it was sent
+///
+/// Found by comparing the headers quoted back in an ICMP reply with the ones sent. Fields are only
+/// compared when the reply quoted enough of the probe to hold them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "field", rename_all = "snake_case"))]
+pub enum HeaderChange {
+    /// Type of service byte holding the DSCP and ECN marking
+    Tos {
+        sent: u8,
+        received: u8,
+    },
+    IpId {
+        sent: u16,
+        received: u16,
+    },
+    /// TTL still left when the probe expired. Only checked on time exceeded replies
+    Ttl {
+        sent: u8,
+        received: u8,
+    },
+    DontFragment {
+        sent: bool,
+        received: bool,
+    },
+    TotalLength {
+        sent: u16,
+        received: u16,
+    },
+    SourceAddress {
+        sent: IpAddr,
+        received: IpAddr,
+    },
+    SourcePort {
+        sent: u16,
+        received: u16,
+    },
+    DestinationPort {
+        sent: u16,
+        received: u16,
+    },
+    /// Checksum of the UDP or TCP header
+    Checksum {
+        sent: u16,
+        received: u16,
+    },
+    UdpLength {
+        sent: u16,
+        received: u16,
+    },
+    Sequence {
+        sent: u32,
+        received: u32,
+    },
+    Window {
+        sent: u16,
+        received: u16,
+    },
+    /// TCP maximum segment size option. None when the option is missing
+    Mss {
+        sent: Option<u16>,
+        received: Option<u16>,
+    },
+    /// Kinds of the TCP options in order, padding left out
+    TcpOptions {
+        sent: Vec<u8>,
+        received: Vec<u8>,
+    },
+}
+
+impl HeaderChange {
+    /// Sent and received DSCP when a type of service change remarked it
+    pub fn dscp_change(&self) -> Option<(u8, u8)> {
+        match *self {
+            Self::Tos { sent, received } => {
+                let (sent, received) = (sent >> 2, received >> 2);
+                (sent != received).then_some((sent, received))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sent and received ECN codepoint when a type of service change altered it
+    ///
+    /// Going from ECT to Not-ECT is bleaching. Going to CE is a router signaling congestion.
+    pub fn ecn_change(&self) -> Option<(u8, u8)> {
+        match *self {
+            Self::Tos { sent, received } => {
+                let (sent, received) = (sent & 0b11, received & 0b11);
+                (sent != received).then_some((sent, received))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compare the headers of a probe as sent with the ones quoted back by a hop
+    ///
+    /// `sent` and `quoted` both start at the IPv4 header. Replies quoting less than a full
+    /// header yield no changes.
+    pub fn compare(sent: &[u8], quoted: &[u8], kind: ReplyKind) -> Vec<Self> {
+        let mut changes = Vec::new();
+
+        let (sent_ip, quoted_ip) = match (Ipv4Packet::new(sent), Ipv4Packet::new(quoted)) {
+            (Some(sent_ip), Some(quoted_ip)) => (sent_ip, quoted_ip),
+            _ => return changes,
+        };
+
+        let tos = |ip: &Ipv4Packet| (ip.get_dscp() << 2) | ip.get_ecn();
+        let dont_fragment = |ip: &Ipv4Packet| ip.get_flags() & Ipv4Flags::DontFragment != 0;
+
+        push_change(
+            &mut changes,
+            tos(&sent_ip),
+            tos(&quoted_ip),
+            |sent, received| Self::Tos { sent, received },
+        );
+        push_change(
+            &mut changes,
+            sent_ip.get_identification(),
+            quoted_ip.get_identification(),
+            |sent, received| Self::IpId { sent, received },
+        );
+        // The probe reaches the hop it expires at with a TTL of 1, some routers quote it after
+        // the decrement
+        if kind == ReplyKind::TimeExceeded && quoted_ip.get_ttl() > 1 {
+            changes.push(Self::Ttl {
+                sent: sent_ip.get_ttl(),
+                received: quoted_ip.get_ttl(),
+            });
+        }
+        push_change(
+            &mut changes,
+            dont_fragment(&sent_ip),
+            dont_fragment(&quoted_ip),
+            |sent, received| Self::DontFragment { sent, received },
+        );
+        push_change(
+            &mut changes,
+            sent_ip.get_total_length(),
+            quoted_ip.get_total_length(),
+            |sent, received| Self::TotalLength { sent, received },
+        );
+        push_change(
+            &mut changes,
+            IpAddr::V4(sent_ip.get_source()),
+            IpAddr::V4(quoted_ip.get_source()),
+            |sent, received| Self::SourceAddress { sent, received },
+        );
+
+        let sent_transport = transport(sent);
+        let quoted_transport = transport(quoted);
+        let field = |bytes: &[u8], start: usize| -> Option<u16> {
+            bytes
+                .get(start..start + 2)
+                .map(|field| u16::from_be_bytes([field[0], field[1]]))
+        };
+        let mut compare_u16 = |start: usize, change: fn(u16, u16) -> Self| {
+            if let (Some(sent), Some(received)) =
+                (field(sent_transport, start), field(quoted_transport, start))
+            {
+                push_change(&mut changes, sent, received, change);
+            }
+        };
+
+        match sent_ip.get_next_level_protocol() {
+            IpNextHeaderProtocols::Udp => {
+                compare_u16(0, |sent, received| Self::SourcePort { sent, received });
+                compare_u16(2, |sent, received| Self::DestinationPort { sent, received });
+                compare_u16(4, |sent, received| Self::UdpLength { sent, received });
+                compare_u16(6, |sent, received| Self::Checksum { sent, received });
+            }
+            IpNextHeaderProtocols::Tcp => {
+                compare_u16(0, |sent, received| Self::SourcePort { sent, received });
+                compare_u16(2, |sent, received| Self::DestinationPort { sent, received });
+                compare_u16(14, |sent, received| Self::Window { sent, received });
+                compare_u16(16, |sent, received| Self::Checksum { sent, received });
+
+                let sequence = |bytes: &[u8]| {
+                    bytes
+                        .get(4..8)
+                        .map(|field| u32::from_be_bytes([field[0], field[1], field[2], field[3]]))
+                };
+                if let (Some(sent), Some(received)) =
+                    (sequence(sent_transport), sequence(quoted_transport))
+                {
+                    push_change(&mut changes, sent, received, |sent, received| {
+                        Self::Sequence { sent, received }
+                    });
+                }
+
+                // Options can only be compared when the whole header was quoted
+                if let (Some(sent), Some(received)) =
+                    (tcp_options(sent_transport), tcp_options(quoted_transport))
+                {
+                    let sent_mss = tcp_mss(sent);
+                    let received_mss = tcp_mss(received);
+                    push_change(&mut changes, sent_mss, received_mss, |sent, received| {
+                        Self::Mss { sent, received }
+                    });
+                    push_change(
+                        &mut changes,
+                        tcp_option_kinds(sent),
+                        tcp_option_kinds(received),
+                        |sent, received| Self::TcpOptions { sent, received },
+                    );
+                }
+            }
+            _ => (),
+        }
+
+        changes
+    }
+}
+
+impl fmt::Display for HeaderChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tos { .. } => {
+                let mut notes = Vec::new();
+                if let Some((sent, received)) = self.dscp_change() {
+                    notes.push(format!("dscp {} -> {}", sent, received));
+                }
+                match self.ecn_change() {
+                    Some((_sent, 0)) => notes.push("ecn bleached".to_string()),
+                    Some((sent, received)) => notes.push(format!("ecn {} -> {}", sent, received)),
+                    None => (),
+                }
+                write!(f, "{}", notes.join(", "))
+            }
+            Self::IpId { sent, received } => write!(f, "ip id {} -> {}", sent, received),
+            Self::Ttl { sent, received } => write!(f, "ttl {} -> {}", sent, received),
+            Self::DontFragment { sent, received } => write!(f, "df {} -> {}", sent, received),
+            Self::TotalLength { sent, received } => {
+                write!(f, "length {} -> {}", sent, received)
+            }
+            Self::SourceAddress { sent, received } => {
+                write!(f, "source {} -> {}", sent, received)
+            }
+            Self::SourcePort { sent, received } => {
+                write!(f, "source port {} -> {}", sent, received)
+            }
+            Self::DestinationPort { sent, received } => {
+                write!(f, "destination port {} -> {}", sent, received)
+            }
+            Self::Checksum { sent, received } => {
+                write!(f, "checksum {:#06x} -> {:#06x}", sent, received)
+            }
+            Self::UdpLength { sent, received } => {
+                write!(f, "udp length {} -> {}", sent, received)
+            }
+            Self::Sequence { sent, received } => write!(f, "seq {} -> {}", sent, received),
+            Self::Window { sent, received } => write!(f, "window {} -> {}", sent, received),
+            Self::Mss { sent, received } => {
+                let mss = |mss: &Option<u16>| match mss {
+                    Some(mss) => mss.to_string(),
+                    None => "none".to_string(),
+                };
+                write!(f, "mss {} -> {}", mss(sent), mss(received))
+            }
+            Self::TcpOptions { sent, received } => {
+                write!(f, "tcp options {:?} -> {:?}", sent, received)
+            }
+        }
+    }
+}
+
+fn push_change<T: PartialEq>(
+    changes: &mut Vec<HeaderChange>,
+    sent: T,
+    received: T,
+    change: impl FnOnce(T, T) -> HeaderChange,
+) {
+    if sent != received {
+        changes.push(change(sent, received));
+    }
+}
+
+// Bytes following the IP header
+fn transport(packet: &[u8]) -> &[u8] {
+    let header_length = packet.first().map(|byte| (byte & 0x0f) as usize * 4);
+    header_length
+        .and_then(|length| packet.get(length..))
+        .unwrap_or_default()
+}
+
+// Options of a TCP header if all of them are present
+fn tcp_options(header: &[u8]) -> Option<&[u8]> {
+    let data_offset = (header.get(12)? >> 4) as usize * 4;
+    header.get(20..data_offset)
+}
+
+// Walk the options calling back with each kind and its value
+fn walk_tcp_options(options: &[u8], mut visit: impl FnMut(u8, &[u8])) {
+    let mut index = 0;
+    while let Some(kind) = options.get(index).copied() {
+        match kind {
+            TCP_OPTION_END => break,
+            TCP_OPTION_NOP => index += 1,
+            _ => {
+                let length = match options.get(index + 1) {
+                    Some(length) if *length >= 2 => *length as usize,
+                    _ => break,
+                };
+                let value = options.get(index + 2..index + length).unwrap_or_default();
+                visit(kind, value);
+                index += length;
+            }
+        }
+    }
+}
+
+fn tcp_option_kinds(options: &[u8]) -> Vec<u8> {
+    let mut kinds = Vec::new();
+    walk_tcp_options(options, |kind, _value| kinds.push(kind));
+    kinds
+}
+
+fn tcp_mss(options: &[u8]) -> Option<u16> {
+    let mut mss = None;
+    walk_tcp_options(options, |kind, value| {
+        if kind == TCP_OPTION_MSS && value.len() == 2 {
+            mss = Some(u16::from_be_bytes([value[0], value[1]]));
+        }
+    });
+    mss
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UDP: u8 = 17;
+    const TCP: u8 = 6;
+
+    // MSS 1460, two NOPs and SACK permitted
+    const OPTIONS: [u8; 8] = [2, 4, 0x05, 0xb4, 1, 1, 4, 2];
+
+    // 20 byte IPv4 header with DF set and a TTL of 1 followed by `transport`
+    fn ipv4(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let length = (20 + transport.len()) as u16;
+        let [high, low] = length.to_be_bytes();
+        let mut packet = vec![0x45, 0, high, low, 0x12, 0x34, 0x40, 0, 1, protocol, 0, 0];
+        // From 192.0.2.2 to 198.51.100.1
+        packet.extend_from_slice(&[192, 0, 2, 2, 198, 51, 100, 1]);
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    // Ports 33434 and 33435 and checksum 0xbeef with 4 bytes of payload
+    fn udp() -> Vec<u8> {
+        let header = [0x82, 0x9a, 0x82, 0x9b, 0, 12, 0xbe, 0xef];
+        ipv4(UDP, &[&header[..], &[0; 4]].concat())
+    }
+
+    fn tcp(options: &[u8]) -> Vec<u8> {
+        let offset = ((20 + options.len()) / 4) as u8;
+        // Ports 33434 and 80, sequence 7, a SYN with window 65535 and checksum 0xbeef
+        let mut header = vec![0x82, 0x9a, 0, 80];
+        header.extend_from_slice(&7_u32.to_be_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&[offset << 4, 0x02, 0xff, 0xff, 0xbe, 0xef, 0, 0]);
+        header.extend_from_slice(options);
+        ipv4(TCP, &header)
+    }
+
+    fn compare(sent: &[u8], quoted: &[u8]) -> Vec<HeaderChange> {
+        HeaderChange::compare(sent, quoted, ReplyKind::TimeExceeded)
+    }
+
+    #[test]
+    fn untouched_probes_have_no_changes() {
+        assert!(compare(&udp(), &udp()).is_empty());
+        assert!(compare(&tcp(&OPTIONS), &tcp(&OPTIONS)).is_empty());
+    }
+
+    #[test]
+    fn short_quotes_compare_nothing() {
+        let mut quoted = udp();
+        quoted[1] = 0xff;
+        assert!(compare(&udp(), &quoted[..19]).is_empty());
+        assert!(compare(&udp()[..19], &quoted).is_empty());
+    }
+
+    #[test]
+    fn ip_fields_are_compared() {
+        let mut quoted = udp();
+        quoted[1] = 0b10;
+        quoted[3] += 4;
+        quoted[5] = 0x35;
+        quoted[6] = 0;
+        quoted[15] = 9;
+        assert_eq!(
+            compare(&udp(), &quoted),
+            vec![
+                HeaderChange::Tos {
+                    sent: 0,
+                    received: 0b10
+                },
+                HeaderChange::IpId {
+                    sent: 0x1234,
+                    received: 0x1235
+                },
+                HeaderChange::DontFragment {
+                    sent: true,
+                    received: false
+                },
+                HeaderChange::TotalLength {
+                    sent: 32,
+                    received: 36
+                },
+                HeaderChange::SourceAddress {
+                    sent: "192.0.2.2".parse().unwrap(),
+                    received: "192.0.2.9".parse().unwrap()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ttl_is_only_checked_on_expiry() {
+        let quoted_ttl = |ttl| {
+            let mut quoted = udp();
+            quoted[8] = ttl;
+            quoted
+        };
+        // Quoted before or after the decrement
+        assert!(compare(&udp(), &quoted_ttl(1)).is_empty());
+        assert!(compare(&udp(), &quoted_ttl(0)).is_empty());
+        assert_eq!(
+            compare(&udp(), &quoted_ttl(3)),
+            vec![HeaderChange::Ttl {
+                sent: 1,
+                received: 3
+            }]
+        );
+        let unreachable = ReplyKind::Unreachable { code: 3 };
+        assert!(HeaderChange::compare(&udp(), &quoted_ttl(3), unreachable).is_empty());
+    }
+
+    #[test]
+    fn only_quoted_transport_fields_are_compared() {
+        let mut quoted = udp();
+        quoted[23] = 0x9c;
+        quoted[26] = 0;
+        // Only the ports made it into the reply
+        assert_eq!(
+            compare(&udp(), &quoted[..24]),
+            vec![HeaderChange::DestinationPort {
+                sent: 33435,
+                received: 33436
+            }]
+        );
+        assert_eq!(
+            compare(&udp(), &quoted)[1],
+            HeaderChange::Checksum {
+                sent: 0xbeef,
+                received: 0x00ef
+            }
+        );
+    }
+
+    #[test]
+    fn tcp_rewrites_are_compared() {
+        let mut quoted = tcp(&OPTIONS);
+        quoted[27] = 9;
+        quoted[34] = 0x80;
+        assert_eq!(
+            compare(&tcp(&OPTIONS), &quoted),
+            vec![
+                HeaderChange::Window {
+                    sent: 0xffff,
+                    received: 0x80ff
+                },
+                HeaderChange::Sequence {
+                    sent: 7,
+                    received: 9
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn clamped_mss_is_found() {
+        let clamped = [2, 4, 0x05, 0x50, 1, 1, 4, 2];
+        assert_eq!(
+            compare(&tcp(&OPTIONS), &tcp(&clamped)),
+            vec![HeaderChange::Mss {
+                sent: Some(1460),
+                received: Some(1360)
+            }]
+        );
+    }
+
+    #[test]
+    fn stripped_options_are_found() {
+        // SACK permitted overwritten with NOPs
+        let stripped = [2, 4, 0x05, 0xb4, 1, 1, 1, 1];
+        assert_eq!(
+            compare(&tcp(&OPTIONS), &tcp(&stripped)),
+            vec![HeaderChange::TcpOptions {
+                sent: vec![2, 4],
+                received: vec![2]
+            }]
+        );
+
+        assert_eq!(
+            compare(&tcp(&OPTIONS), &tcp(&[])),
+            vec![
+                HeaderChange::TotalLength {
+                    sent: 48,
+                    received: 40
+                },
+                HeaderChange::Mss {
+                    sent: Some(1460),
+                    received: None
+                },
+                HeaderChange::TcpOptions {
+                    sent: vec![2, 4],
+                    received: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn options_cut_off_by_the_quote_are_skipped() {
+        let clamped = [2, 4, 0x05, 0x50, 1, 1, 4, 2];
+        // Quotes ending within the options leave them all out
+        assert!(compare(&tcp(&OPTIONS), &tcp(&clamped)[..44]).is_empty());
+    }
+
+    #[test]
+    fn option_walk_stops_at_the_end_or_bad_lengths() {
+        assert_eq!(tcp_option_kinds(&OPTIONS), vec![2, 4]);
+        assert_eq!(tcp_option_kinds(&[1, 2, 4, 5, 0xb4, 0, 4, 2]), vec![2]);
+        // A length too short to cover itself
+        assert_eq!(tcp_option_kinds(&[8, 1, 2, 4, 5, 0xb4]), Vec::<u8>::new());
+        assert_eq!(tcp_option_kinds(&[2, 0]), Vec::<u8>::new());
+        // Running past the end of the header
+        assert_eq!(tcp_option_kinds(&[1, 2, 4, 5]), vec![2]);
+        assert_eq!(tcp_mss(&[1, 2, 4, 5]), None);
+        assert_eq!(tcp_mss(&[2, 3, 5, 2, 4, 5, 0xb4]), Some(1460));
+    }
+
+    #[test]
+    fn tos_changes_are_shown_per_codepoint() {
+        let change = HeaderChange::Tos {
+            sent: 46 << 2 | 0b10,
+            received: 0,
+        };
+        assert_eq!(change.to_string(), "dscp 46 -> 0, ecn bleached");
+
+        let change = HeaderChange::Tos {
+            sent: 0b10,
+            received: 0b11,
+        };
+        assert_eq!(change.dscp_change(), None);
+        assert_eq!(change.to_string(), "ecn 2 -> 3");
+    }
+}
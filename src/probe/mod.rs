@@ -1,9 +1,27 @@
 mod bundle;
+#[cfg(test)]
+pub(crate) mod fake;
+mod headers;
 mod probe;
+mod reply;
 mod response;
 mod sent;
 
 pub use bundle::ProbeBundle;
+pub use headers::HeaderChange;
 pub use probe::Probe;
+pub use reply::{IcmpReply, ReplyKind};
 pub use response::ProbeResponse;
 pub use sent::ProbeSent;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+// Offset of the destination address in an IPv4 header
+const DESTINATION_OFFSET: usize = 16;
+
+// Destination of the IPv4 header `header` starts with
+fn ipv4_destination(header: &[u8]) -> Option<IpAddr> {
+    let address = header.get(DESTINATION_OFFSET..DESTINATION_OFFSET + 4)?;
+    let address = Ipv4Addr::new(address[0], address[1], address[2], address[3]);
+    Some(IpAddr::V4(address))
+}
@@ -18,16 +18,30 @@ pub struct Probe {
     pub checksum: Checksum,
     /// Flowhash
     pub flowhash: Flowhash,
+    /// Type of service byte holding the DSCP and ECN marking
+    pub tos: u8,
+    /// IP and transport headers as sent, to compare with the ones quoted in replies
+    pub headers: Vec<u8>,
 }
 
 impl Probe {
-    pub fn new(source: IpAddr, ttl: TTL, id: TcpId, checksum: Checksum, flowhash: Flowhash) -> Self {
+    pub fn new(
+        source: IpAddr,
+        ttl: TTL,
+        id: TcpId,
+        checksum: Checksum,
+        flowhash: Flowhash,
+        tos: u8,
+        headers: Vec<u8>,
+    ) -> Self {
         Self {
             source,
             ttl,
             id,
             checksum,
             flowhash,
+            tos,
+            headers,
         }
     }
 
@@ -39,6 +53,8 @@ impl Probe {
             id,
             checksum,
             flowhash,
+            tos,
+            headers,
         } = self;
 
         ProbeSent {
@@ -47,6 +63,8 @@ impl Probe {
             id,
             checksum,
             flowhash,
+            tos,
+            headers,
             instant: Instant::now(),
         }
     }
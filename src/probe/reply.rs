@@ -0,0 +1,74 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::prelude::{Checksum, TTL, TcpId};
+
+/// What kind of message answered a probe, ICMP unless a TCP probe reached the destination
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ReplyKind {
+    /// The TTL ran out on the way
+    #[default]
+    TimeExceeded,
+    /// The destination answered an echo request
+    EchoReply,
+    /// The packet was too large to forward without fragmenting and DF was set
+    FragmentationNeeded {
+        /// MTU of the next hop if the router reported it
+        mtu: Option<u16>,
+    },
+    /// Any other destination unreachable. Port unreachable is how UDP probes reach the target
+    Unreachable { code: u8 },
+    /// The destination accepted the connection a TCP probe opened
+    SynAck,
+    /// The destination refused the connection a TCP probe opened
+    Reset,
+}
+
+impl fmt::Display for ReplyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TimeExceeded => write!(f, "time exceeded"),
+            Self::EchoReply => write!(f, "echo reply"),
+            Self::FragmentationNeeded { mtu: Some(mtu) } => {
+                write!(f, "fragmentation needed, next hop mtu {}", mtu)
+            }
+            Self::FragmentationNeeded { mtu: None } => write!(f, "fragmentation needed"),
+            Self::Unreachable { code } => write!(f, "unreachable code {}", code),
+            Self::SynAck => write!(f, "syn-ack"),
+            Self::Reset => write!(f, "reset"),
+        }
+    }
+}
+
+/// Details of an incoming reply needed to match and describe it
+#[derive(Clone, Debug)]
+pub struct IcmpReply {
+    /// IP of the machine which replied
+    pub source: IpAddr,
+    /// Identification of the reply itself, taken from a counter on many routers
+    pub ip_id: u16,
+    /// TTL left on the reply when it arrived
+    pub ttl: TTL,
+    /// Identification of the quoted probe
+    pub id: TcpId,
+    /// Checksum of the quoted probe. None when the replying hop quoted too little of it
+    pub checksum: Option<Checksum>,
+    /// Sequence number of the TCP probe the destination answered
+    pub acknowledges: Option<u32>,
+    /// The probe as quoted by the replying hop starting at the IP header
+    pub quoted: Vec<u8>,
+    pub kind: ReplyKind,
+}
+
+impl IcmpReply {
+    /// Address the answered probe was sent to, as quoted back. TCP replies come from the
+    /// destination itself
+    pub fn probe_destination(&self) -> Option<IpAddr> {
+        match self.acknowledges {
+            Some(_sequence) => Some(self.source),
+            None => super::ipv4_destination(&self.quoted),
+        }
+    }
+}
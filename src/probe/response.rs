@@ -1,21 +1,24 @@
 use std::net::IpAddr;
 use std::time::{Duration, Instant};
 
-use super::ProbeSent;
+use super::{HeaderChange, IcmpReply, ProbeSent, ReplyKind};
 
-use crate::prelude::TTL;
+use crate::prelude::{Checksum, TTL};
+
+// TTLs replies are commonly sent with
+const INITIAL_TTLS: [TTL; 3] = [64, 128, 255];
 
 /// Information received from the returned [`ProbeSent`]
 ///
-/// The response doesn't currently take into account the checksum of the sent probe vs the
-/// response. Also there is no attempt to inspect the payload of the probe sent. There is no
-/// current payload being sent but it may be useful to take advantage of the payload to investigate
-/// when routers are spliting packets or other funky stuff.
+/// The checksum of the sent probe is kept next to the one quoted back so NATs rewriting it can be
+/// told apart. There is no attempt to inspect the payload of the probe sent. It may be useful to
+/// take advantage of the payload to investigate when routers are spliting packets or other funky
+/// stuff.
 ///
 /// Upon creation the following values are being discarded for reference
 /// TcpId
-/// Checksum (sent and received)
 #[derive(Clone,Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProbeResponse {
     /// How many hops away the `destination` is
     pub ttl: TTL,
@@ -25,17 +28,236 @@ pub struct ProbeResponse {
     pub ping: Duration,
     /// Probe that was sent
     pub sent: ProbeSent,
+    /// ICMP message the reply came as
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub kind: ReplyKind,
+    /// Type of service byte of the probe as the replying hop received it. None when the reply
+    /// didn't quote it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub quoted_tos: Option<u8>,
+    /// Identification of the reply. Routers drawing it from a single counter give away which
+    /// interfaces they own
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reply_id: u16,
+    /// TTL left on the reply when it arrived
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reply_ttl: TTL,
+    /// TTL of the probe as the replying hop received it. 0 gives away a router in front which
+    /// forwarded the probe when it should have expired
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub quoted_ttl: Option<TTL>,
+    /// Transport checksum of the probe as the replying hop received it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub quoted_checksum: Option<Checksum>,
+    /// Headers which arrived at the replying hop different from how they were sent
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub modifications: Vec<HeaderChange>,
 }
 
 impl ProbeResponse {
-    pub fn new(sent: ProbeSent, destination: IpAddr, moment_received: Instant) -> Self {
+    pub fn new(sent: ProbeSent, reply: IcmpReply, moment_received: Instant) -> Self {
         let ping = moment_received.duration_since(sent.instant);
+        let modifications = HeaderChange::compare(&sent.headers, &reply.quoted, reply.kind);
 
         Self {
             ttl: sent.ttl,
-            destination,
+            destination: reply.source,
             ping,
             sent,
+            kind: reply.kind,
+            // Second byte of the quoted IP header
+            quoted_tos: reply.quoted.get(1).copied(),
+            // Ninth byte of the quoted IP header
+            quoted_ttl: reply.quoted.get(8).copied(),
+            reply_id: reply.ip_id,
+            reply_ttl: reply.ttl,
+            quoted_checksum: reply.checksum,
+            modifications,
+        }
+    }
+
+    /// Sent and received DSCP when the probe was remarked on the way
+    pub fn dscp_change(&self) -> Option<(u8, u8)> {
+        self.tos_change()?.dscp_change()
+    }
+
+    /// Sent and received ECN codepoint when it changed on the way. See
+    /// [`HeaderChange::ecn_change`]
+    pub fn ecn_change(&self) -> Option<(u8, u8)> {
+        self.tos_change()?.ecn_change()
+    }
+
+    // Type of service byte as sent and as quoted back, changed or not
+    fn tos_change(&self) -> Option<HeaderChange> {
+        Some(HeaderChange::Tos {
+            sent: self.sent.tos,
+            received: self.quoted_tos?,
+        })
+    }
+
+    /// TTL the replying hop most likely sent its reply with
+    ///
+    /// Operating systems start from 64, 128 or 255, the reply lost one along each hop of the way
+    /// back.
+    pub fn initial_ttl(&self) -> TTL {
+        INITIAL_TTLS
+            .into_iter()
+            .find(|initial| *initial >= self.reply_ttl)
+            .unwrap_or(TTL::MAX)
+    }
+
+    /// Hops the reply went through on its way back, counting the replying hop. None when the
+    /// reply TTL wasn't recorded
+    pub fn return_hops(&self) -> Option<TTL> {
+        match self.reply_ttl {
+            0 => None,
+            reply_ttl => Some(self.initial_ttl() - reply_ttl + 1),
+        }
+    }
+
+    /// Return hops less forward hops when the reply came back a different length of path
+    ///
+    /// Only time exceeded replies are compared. Probes answered by the destination may have been
+    /// sent further than it is.
+    pub fn asymmetry(&self) -> Option<i16> {
+        if self.kind != ReplyKind::TimeExceeded {
+            return None;
+        }
+        let difference = self.return_hops()? as i16 - self.ttl as i16;
+        (difference != 0).then_some(difference)
+    }
+
+    /// Identifier of the NAT the probe went through, 0 when it wasn't translated and None when
+    /// the checksum wasn't quoted
+    ///
+    /// A NAT rewriting the addresses or ports of the probe adjusts its checksum to match. Restoring
+    /// the quoted headers in the ICMP reply often leaves the checksum behind, so the difference
+    /// between the quoted and sent checksum tells NATs apart, as Dublin Traceroute does. Such a
+    /// NAT restores the quoted IP id, which the reply was matched by.
+    ///
+    /// [`broken_nat`](crate::trace::TraceOptions::broken_nat) handles the other kind, which
+    /// rewrites the IP id and leaves the checksum alone. Replies matched through their checksum
+    /// always report 0 here and show the translation as a [`HeaderChange::IpId`] instead.
+    pub fn nat_id(&self) -> Option<Checksum> {
+        self.quoted_checksum
+            .map(|checksum| checksum.wrapping_sub(self.sent.checksum))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn response(tos: u8, quoted_tos: Option<u8>) -> ProbeResponse {
+        let sent = ProbeSent {
+            source: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            ttl: 4,
+            id: 7,
+            checksum: 0x1234,
+            flowhash: 0,
+            tos,
+            headers: Vec::new(),
+            instant: Instant::now(),
+        };
+        ProbeResponse {
+            ttl: sent.ttl,
+            destination: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)),
+            ping: Duration::from_millis(10),
+            sent,
+            kind: ReplyKind::TimeExceeded,
+            quoted_tos,
+            reply_id: 0,
+            reply_ttl: 0,
+            quoted_ttl: None,
+            quoted_checksum: None,
+            modifications: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn remarking_is_reported_per_codepoint() {
+        // DSCP 46 with ECT(0)
+        let marked = 46 << 2 | 0b10;
+        assert_eq!(response(marked, Some(marked)).dscp_change(), None);
+        assert_eq!(response(marked, Some(marked)).ecn_change(), None);
+
+        let remarked = response(marked, Some(0b10));
+        assert_eq!(remarked.dscp_change(), Some((46, 0)));
+        assert_eq!(remarked.ecn_change(), None);
+
+        let bleached = response(marked, Some(46 << 2));
+        assert_eq!(bleached.dscp_change(), None);
+        assert_eq!(bleached.ecn_change(), Some((0b10, 0)));
+    }
+
+    #[test]
+    fn unquoted_tos_is_no_change() {
+        let unquoted = response(46 << 2 | 0b01, None);
+        assert_eq!(unquoted.dscp_change(), None);
+        assert_eq!(unquoted.ecn_change(), None);
+    }
+
+    #[test]
+    fn nat_id_is_the_checksum_difference() {
+        let mut resp = response(0, Some(0));
+        assert_eq!(resp.nat_id(), None);
+
+        resp.quoted_checksum = Some(0x1234);
+        assert_eq!(resp.nat_id(), Some(0));
+        resp.quoted_checksum = Some(0x1240);
+        assert_eq!(resp.nat_id(), Some(0x000c));
+        // Checksums below the one sent wrap around
+        resp.quoted_checksum = Some(0x1233);
+        assert_eq!(resp.nat_id(), Some(0xffff));
+    }
+
+    fn replied(reply_ttl: TTL) -> ProbeResponse {
+        ProbeResponse {
+            reply_ttl,
+            ..response(0, Some(0))
         }
     }
+
+    #[test]
+    fn initial_ttl_is_the_next_common_one() {
+        assert_eq!(replied(1).initial_ttl(), 64);
+        assert_eq!(replied(64).initial_ttl(), 64);
+        assert_eq!(replied(65).initial_ttl(), 128);
+        assert_eq!(replied(128).initial_ttl(), 128);
+        assert_eq!(replied(129).initial_ttl(), 255);
+        assert_eq!(replied(255).initial_ttl(), 255);
+    }
+
+    #[test]
+    fn return_hops_count_the_replying_hop() {
+        assert_eq!(replied(0).return_hops(), None);
+        assert_eq!(replied(64).return_hops(), Some(1));
+        assert_eq!(replied(61).return_hops(), Some(4));
+        assert_eq!(replied(120).return_hops(), Some(9));
+        assert_eq!(replied(1).return_hops(), Some(64));
+    }
+
+    #[test]
+    fn asymmetry_is_return_less_forward_hops() {
+        // The probe was sent with a TTL of 4
+        assert_eq!(replied(0).asymmetry(), None);
+        assert_eq!(replied(61).asymmetry(), None);
+        assert_eq!(replied(59).asymmetry(), Some(2));
+        assert_eq!(replied(63).asymmetry(), Some(-2));
+        assert_eq!(replied(1).asymmetry(), Some(60));
+    }
+
+    #[test]
+    fn asymmetry_only_compares_time_exceeded() {
+        let mut resp = replied(59);
+        resp.kind = ReplyKind::EchoReply;
+        assert_eq!(resp.return_hops(), Some(6));
+        assert_eq!(resp.asymmetry(), None);
+        resp.kind = ReplyKind::Unreachable { code: 3 };
+        assert_eq!(resp.asymmetry(), None);
+    }
 }
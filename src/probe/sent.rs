@@ -8,6 +8,7 @@ use crate::prelude::{Checksum, Flowhash, TTL, TcpId};
 /// Created by [`Probe`](crate::probe::Probe) when a packet is passed to the network to mark the [`Instant`] it was
 /// sent
 #[derive(Clone,Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProbeSent {
     /// Source IP Address
     pub source: IpAddr,
@@ -19,10 +20,45 @@ pub struct ProbeSent {
     pub checksum: Checksum,
     /// Flowhash
     pub flowhash: Flowhash,
+    /// Type of service byte holding the DSCP and ECN marking
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tos: u8,
+    /// IP and transport headers as sent
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub headers: Vec<u8>,
     /// The instant the probe was sent
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
     pub instant: Instant,
 }
 
+// Offset of the IP protocol field and of the TCP sequence number in the headers sent
+const PROTOCOL_OFFSET: usize = 9;
+const TCP_SEQUENCE_OFFSET: usize = 20 + 4;
+const TCP: u8 = 6;
+
+impl ProbeSent {
+    /// Address the probe was sent to
+    pub fn destination(&self) -> Option<IpAddr> {
+        super::ipv4_destination(&self.headers)
+    }
+
+    /// Sequence number of a TCP probe, the destination acknowledges it when answering
+    pub fn tcp_sequence(&self) -> Option<u32> {
+        if self.headers.get(PROTOCOL_OFFSET) != Some(&TCP) {
+            return None;
+        }
+        let sequence = self
+            .headers
+            .get(TCP_SEQUENCE_OFFSET..TCP_SEQUENCE_OFFSET + 4)?;
+        Some(u32::from_be_bytes([
+            sequence[0],
+            sequence[1],
+            sequence[2],
+            sequence[3],
+        ]))
+    }
+}
+
 impl PartialEq for ProbeSent {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
@@ -4,6 +4,10 @@ use std::{error::Error, fmt};
 #[derive(Copy, Debug, PartialEq, Eq, Clone)]
 pub enum ParseProtocolErr {
     UnknownProtocol,
+    /// Not a known strategy nor a list of ports
+    UnknownPortStrategy,
+    /// More than [`MAX_PORTS`](super::MAX_PORTS) ports listed
+    TooManyPorts,
 }
 
 impl Error for ParseProtocolErr {}
@@ -12,6 +16,13 @@ impl fmt::Display for ParseProtocolErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ParseProtocolErr::UnknownProtocol => write!(f, "Unknown Protocol"),
+            ParseProtocolErr::UnknownPortStrategy => write!(
+                f,
+                "Port strategy must be fixed, incrementing, random-source or a list of ports"
+            ),
+            ParseProtocolErr::TooManyPorts => {
+                write!(f, "More than {} ports listed", super::MAX_PORTS)
+            }
         }
     }
 }
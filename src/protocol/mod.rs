@@ -1,6 +1,8 @@
 mod error;
+mod ports;
 mod protocol;
 mod udp;
 
+pub use ports::{MAX_PORTS, PortList, PortStrategy};
 pub use protocol::Protocol;
 pub use udp::UdpParams;
@@ -0,0 +1,285 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+
+use super::{Protocol, UdpParams, error::ParseProtocolErr};
+use crate::prelude::TTL;
+
+/// Most destination ports a list strategy can hold
+pub const MAX_PORTS: usize = 8;
+
+// Source ports picked at random come from the dynamic range
+const DYNAMIC_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// How the ports of UDP probes are chosen
+///
+/// Load balancers hash the ports into the path they pick. Keeping them fixed follows a single
+/// path like Paris traceroute, changing them with every probe spreads the probes over the paths
+/// like classic traceroute.
+///
+/// Written as `fixed`, `incrementing`, `random-source` or a list of destination ports. ex:
+/// `33434,33435,33500`
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub enum PortStrategy {
+    /// Every probe uses the ports of the protocol
+    #[default]
+    Fixed,
+    /// The destination port goes up by one with each TTL from the one of the protocol
+    Incrementing,
+    /// A random source port picked once for each flow
+    RandomSource,
+    /// Destination ports taken in turn with each TTL
+    List(PortList),
+}
+
+/// Between 1 and [`MAX_PORTS`] destination ports, made with [`PortStrategy::list`]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PortList {
+    ports: [u16; MAX_PORTS],
+    len: u8,
+}
+
+impl PortList {
+    pub fn ports(&self) -> &[u16] {
+        &self.ports[..self.len.into()]
+    }
+}
+
+impl PortStrategy {
+    pub fn list(ports: &[u16]) -> Result<Self, ParseProtocolErr> {
+        if ports.is_empty() {
+            return Err(ParseProtocolErr::UnknownPortStrategy);
+        }
+        if ports.len() > MAX_PORTS {
+            return Err(ParseProtocolErr::TooManyPorts);
+        }
+        let mut list = [0; MAX_PORTS];
+        list[..ports.len()].copy_from_slice(ports);
+        Ok(Self::List(PortList {
+            ports: list,
+            len: ports.len() as u8,
+        }))
+    }
+
+    /// Protocol a new flow uses, picking its random source port
+    pub fn flow_protocol(&self, protocol: Protocol) -> Protocol {
+        match (self, protocol) {
+            (Self::RandomSource, Protocol::UDP(params)) => Protocol::UDP(UdpParams {
+                source_port: rand::rng().random_range(DYNAMIC_PORTS),
+                ..params
+            }),
+            (_, protocol) => protocol,
+        }
+    }
+
+    /// Ports of the probe sent at `ttl` by a flow using `params`
+    pub fn probe_params(&self, params: UdpParams, ttl: TTL) -> UdpParams {
+        let step = ttl.saturating_sub(1);
+        match self {
+            Self::Fixed | Self::RandomSource => params,
+            Self::Incrementing => UdpParams {
+                destination_port: params.destination_port.wrapping_add(step.into()),
+                ..params
+            },
+            Self::List(list) => {
+                let ports = list.ports();
+                UdpParams {
+                    destination_port: ports[usize::from(step) % ports.len()],
+                    ..params
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for PortStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Fixed => write!(f, "fixed"),
+            Self::Incrementing => write!(f, "incrementing"),
+            Self::RandomSource => write!(f, "random-source"),
+            Self::List(list) => {
+                let ports = list
+                    .ports()
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<String>>();
+                write!(f, "{}", ports.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for PortStrategy {
+    type Err = ParseProtocolErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let strategy = match s {
+            "fixed" | "FIXED" => Self::Fixed,
+            "incrementing" | "INCREMENTING" => Self::Incrementing,
+            "random-source" | "RANDOM-SOURCE" => Self::RandomSource,
+            list => {
+                let ports = list
+                    .split(',')
+                    .map(|port| port.trim().parse::<u16>())
+                    .collect::<Result<Vec<u16>, _>>()
+                    .map_err(|_err| ParseProtocolErr::UnknownPortStrategy)?;
+                Self::list(&ports)?
+            }
+        };
+
+        Ok(strategy)
+    }
+}
+
+impl TryFrom<String> for PortStrategy {
+    type Error = ParseProtocolErr;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortStrategy> for String {
+    fn from(strategy: PortStrategy) -> Self {
+        strategy.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: UdpParams = UdpParams {
+        source_port: 40000,
+        destination_port: 33434,
+    };
+
+    fn destination_ports(strategy: PortStrategy, ttls: std::ops::RangeInclusive<TTL>) -> Vec<u16> {
+        ttls.map(|ttl| strategy.probe_params(PARAMS, ttl).destination_port)
+            .collect()
+    }
+
+    #[test]
+    fn lists_hold_one_to_max_ports() {
+        assert_eq!(
+            PortStrategy::list(&[]),
+            Err(ParseProtocolErr::UnknownPortStrategy)
+        );
+        assert_eq!(
+            PortStrategy::list(&[1; MAX_PORTS + 1]),
+            Err(ParseProtocolErr::TooManyPorts)
+        );
+
+        let full = [1, 2, 3, 4, 5, 6, 7, 8];
+        match PortStrategy::list(&full) {
+            Ok(PortStrategy::List(list)) => assert_eq!(list.ports(), full),
+            other => panic!("expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lists_only_compare_their_ports() {
+        // The unused slots never tell two lists apart
+        assert_eq!(PortStrategy::list(&[53]), "53".parse());
+        assert_ne!(PortStrategy::list(&[53]), PortStrategy::list(&[53, 0]));
+    }
+
+    #[test]
+    fn strategies_parse_and_display_the_same() {
+        for s in [
+            "fixed",
+            "incrementing",
+            "random-source",
+            "33434",
+            "33434,33435,33500",
+        ] {
+            assert_eq!(s.parse::<PortStrategy>().unwrap().to_string(), s);
+        }
+        assert_eq!("FIXED".parse(), Ok(PortStrategy::Fixed));
+        assert_eq!("53, 123".parse(), PortStrategy::list(&[53, 123]));
+    }
+
+    #[test]
+    fn bad_lists_dont_parse() {
+        for s in ["", "53,", "53,,123", "65536", "-1", "fast"] {
+            assert_eq!(
+                s.parse::<PortStrategy>(),
+                Err(ParseProtocolErr::UnknownPortStrategy),
+                "{:?}",
+                s
+            );
+        }
+        assert_eq!(
+            "1,2,3,4,5,6,7,8,9".parse::<PortStrategy>(),
+            Err(ParseProtocolErr::TooManyPorts)
+        );
+    }
+
+    #[test]
+    fn lists_are_taken_in_turn() {
+        let strategy = PortStrategy::list(&[53, 123, 443]).unwrap();
+        assert_eq!(
+            destination_ports(strategy, 1..=5),
+            vec![53, 123, 443, 53, 123]
+        );
+        assert_eq!(strategy.probe_params(PARAMS, 2).source_port, 40000);
+        // A TTL of 0 takes the first port rather than underflowing
+        assert_eq!(destination_ports(strategy, 0..=0), vec![53]);
+    }
+
+    #[test]
+    fn incrementing_starts_at_the_protocol_port_and_wraps() {
+        let strategy = PortStrategy::Incrementing;
+        assert_eq!(
+            destination_ports(strategy, 1..=3),
+            vec![33434, 33435, 33436]
+        );
+
+        let last = UdpParams {
+            destination_port: u16::MAX,
+            ..PARAMS
+        };
+        assert_eq!(strategy.probe_params(last, 2).destination_port, 0);
+    }
+
+    #[test]
+    fn fixed_ports_never_change() {
+        assert_eq!(
+            destination_ports(PortStrategy::Fixed, 1..=2),
+            vec![33434, 33434]
+        );
+        assert_eq!(
+            PortStrategy::Fixed.flow_protocol(Protocol::UDP(PARAMS)),
+            Protocol::UDP(PARAMS)
+        );
+    }
+
+    #[test]
+    fn random_source_ports_are_dynamic() {
+        let strategy = PortStrategy::RandomSource;
+        match strategy.flow_protocol(Protocol::UDP(PARAMS)) {
+            Protocol::UDP(params) => {
+                assert!(DYNAMIC_PORTS.contains(&params.source_port));
+                assert_eq!(params.destination_port, PARAMS.destination_port);
+            }
+            other => panic!("expected UDP, got {:?}", other),
+        }
+        assert_eq!(strategy.flow_protocol(Protocol::ICMP), Protocol::ICMP);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn strategies_serialize_as_strings() {
+        let strategy = PortStrategy::list(&[53, 123]).unwrap();
+        let json = serde_json::to_string(&strategy).unwrap();
+        assert_eq!(json, "\"53,123\"");
+        assert_eq!(
+            serde_json::from_str::<PortStrategy>(&json).unwrap(),
+            strategy
+        );
+        assert!(serde_json::from_str::<PortStrategy>("\"\"").is_err());
+    }
+}
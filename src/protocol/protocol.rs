@@ -6,6 +6,8 @@ use super::{error::ParseProtocolErr, udp::UdpParams};
 
 /// Protocol to be used for traceroute
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Protocol {
     /// Datagram Congestion Control Protocol
     DCCP,
@@ -0,0 +1,92 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trace::{MIN_BROKEN_NAT_SIZE, MIN_PROBE_SIZE, TraceOptions, TraceResponse};
+
+// Keep a single request from hogging the sockets
+const MAX_ROUNDS: usize = 10;
+const MAX_ATTEMPTS: u8 = 5;
+// Retries back off to at most the same 10 seconds
+const MAX_TIMEOUT: u16 = 10_000;
+
+/// Body of a `POST /trace` request
+///
+/// ex: `{"destination": "1.1.1.1", "rounds": 2, "options": {"max_ttl": 16}}`
+#[derive(Clone, Debug, Deserialize)]
+pub struct TraceJob {
+    pub destination: IpAddr,
+    /// Must be an address of the machine. Defaults to the one the routing table picks for the
+    /// destination
+    pub source: Option<IpAddr>,
+    /// Times to trace the path
+    #[serde(default = "default_rounds")]
+    pub rounds: usize,
+    /// Missing fields use the [`TraceOptions`] defaults
+    #[serde(default)]
+    pub options: TraceOptions,
+}
+
+fn default_rounds() -> usize {
+    1
+}
+
+impl TraceJob {
+    /// Reason the job can't be run, if any
+    pub fn invalid(&self) -> Option<&'static str> {
+        let options = &self.options;
+        if self.rounds == 0 || self.rounds > MAX_ROUNDS {
+            return Some("rounds must be between 1 and 10");
+        }
+        if options.attempts == 0 || options.attempts > MAX_ATTEMPTS {
+            return Some("attempts must be between 1 and 5");
+        }
+        if options.timeout > MAX_TIMEOUT {
+            return Some("timeout must be at most 10000");
+        }
+        if options.min_ttl == 0 {
+            return Some("min_ttl must be at least 1");
+        }
+        if options.max_ttl < options.min_ttl || options.max_ttl as usize > options.mask.len() {
+            return Some("max_ttl must be between min_ttl and 32");
+        }
+        if options.size < MIN_PROBE_SIZE {
+            return Some("size must be at least 28");
+        }
+        if options.broken_nat && options.size < MIN_BROKEN_NAT_SIZE {
+            return Some("broken_nat needs a size of at least 30");
+        }
+        if options.dscp > 63 || options.ecn > 3 {
+            return Some("dscp must be at most 63 and ecn at most 3");
+        }
+        if !self.destination.is_ipv4() {
+            return Some("only ipv4 destinations are supported");
+        }
+        None
+    }
+}
+
+/// Event streamed back while a [`TraceJob`] runs
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent<'a> {
+    /// Outcome of a single probe
+    Response {
+        round: usize,
+        response: &'a TraceResponse,
+    },
+    /// Every probe of the round has been accounted for
+    RoundComplete { round: usize },
+    /// The trace stopped early
+    Error { message: String },
+}
+
+impl TraceEvent<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Response { .. } => "response",
+            Self::RoundComplete { .. } => "round_complete",
+            Self::Error { .. } => "error",
+        }
+    }
+}
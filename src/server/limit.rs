@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const REFILL_PERIOD: Duration = Duration::from_secs(60);
+
+/// Caps how many traces run at the same time
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    active: AtomicUsize,
+}
+
+/// Slot held while a trace runs, released on drop
+#[derive(Debug)]
+pub struct ConcurrencyPermit<'a> {
+    limit: &'a ConcurrencyLimit,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take a slot if one is free
+    pub fn acquire(&self) -> Option<ConcurrencyPermit<'_>> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| ConcurrencyPermit { limit: self })
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        let _ = self.limit.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Token bucket per client address
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    // tokens left and when they were last topped up
+    clients: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Allow each client `per_minute` requests a minute with bursts of the same size
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for the client. False when the client is over its limit
+    pub fn allow(&self, client: IpAddr) -> bool {
+        let now = Instant::now();
+        let capacity = self.per_minute as f64;
+        let refill = capacity / REFILL_PERIOD.as_secs_f64();
+
+        let mut clients = self.clients.lock().unwrap_or_else(|err| err.into_inner());
+        // A minute is enough for any bucket to fill back up, forget those clients
+        clients.retain(|_client, (_tokens, last)| now.duration_since(*last) < REFILL_PERIOD);
+
+        let (tokens, last) = clients.entry(client).or_insert((capacity, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * refill).min(capacity);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
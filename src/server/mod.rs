@@ -0,0 +1,7 @@
+mod job;
+mod limit;
+mod server;
+
+pub use job::{TraceEvent, TraceJob};
+pub use limit::{ConcurrencyLimit, RateLimiter};
+pub use server::{Server, ServerOptions};
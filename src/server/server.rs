@@ -0,0 +1,245 @@
+use std::io::{self, BufReader, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use async_std::stream::StreamExt;
+use async_std::task;
+use log::*;
+
+use super::{ConcurrencyLimit, RateLimiter, TraceEvent, TraceJob};
+use crate::TracerouteError;
+use crate::http::{Request, Response};
+use crate::trace::{HopEvent, TraceResponse};
+use crate::traceroute::Traceroute;
+
+// How long a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const TEXT: &str = "text/plain; charset=utf-8";
+
+/// Configuration of the [`Server`]
+#[derive(Clone, Debug)]
+pub struct ServerOptions {
+    /// Address to listen on. Keep it local, there is no authentication
+    pub address: SocketAddr,
+    /// Connections, and so traces, served at the same time. Others are refused with a 503
+    pub max_concurrent: usize,
+    /// Trace requests each client may make per minute
+    pub rate_limit: u32,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 9185)),
+            max_concurrent: 4,
+            rate_limit: 30,
+        }
+    }
+}
+
+/// Run traces on request over a local HTTP JSON API
+///
+/// `POST /trace` with a [`TraceJob`] body streams [`TraceEvent`]s back as NDJSON, or as
+/// server-sent events when the request accepts `text/event-stream`.
+pub struct Server {
+    options: ServerOptions,
+    concurrency: ConcurrencyLimit,
+    rate_limiter: RateLimiter,
+}
+
+impl Server {
+    pub fn new(options: ServerOptions) -> Self {
+        Self {
+            concurrency: ConcurrencyLimit::new(options.max_concurrent),
+            rate_limiter: RateLimiter::new(options.rate_limit),
+            options,
+        }
+    }
+
+    /// Serve requests with the sockets of `traceroute` until the listener fails
+    pub fn run(&self, traceroute: &Traceroute) -> Result<(), TracerouteError> {
+        let listener = TcpListener::bind(self.options.address)?;
+        info!("Serving traces on http://{}/trace", listener.local_addr()?);
+
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept connection: {}", err);
+                        continue;
+                    }
+                };
+                // Each connection holds a slot from before its thread starts until it closes
+                let permit = match self.concurrency.acquire() {
+                    Some(permit) => permit,
+                    None => {
+                        refuse(stream);
+                        continue;
+                    }
+                };
+                let spawned = thread::Builder::new()
+                    .name("client".to_string())
+                    .spawn_scoped(scope, move || {
+                        let _permit = permit;
+                        if let Err(err) = self.handle(traceroute, stream) {
+                            debug!("Client connection failed: {}", err);
+                        }
+                    });
+                if let Err(err) = spawned {
+                    warn!("Failed to spawn client thread: {}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn handle(&self, traceroute: &Traceroute, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let client = stream.peer_addr()?.ip();
+
+        let request = match Request::read(&mut BufReader::new(&stream)) {
+            Ok(request) => request,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                return Response::new(400, TEXT, format!("{}\n", err)).write_to(&mut stream);
+            }
+            Err(err) => return Err(err),
+        };
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/trace") => (),
+            (_, "/trace") => return Response::error(405).write_to(&mut stream),
+            _ => return Response::error(404).write_to(&mut stream),
+        }
+
+        if !self.rate_limiter.allow(client) {
+            info!("Rate limited trace request from {}", client);
+            return Response::error(429).write_to(&mut stream);
+        }
+
+        let job: TraceJob = match serde_json::from_slice(&request.body) {
+            Ok(job) => job,
+            Err(err) => {
+                let body = format!("Invalid trace request: {}\n", err);
+                return Response::new(400, TEXT, body).write_to(&mut stream);
+            }
+        };
+        if let Some(reason) = job.invalid() {
+            let body = format!("Invalid trace request: {}\n", reason);
+            return Response::new(400, TEXT, body).write_to(&mut stream);
+        }
+        let source = match self.source(traceroute, job.source, job.destination) {
+            Ok(source) => source,
+            Err(reason) => {
+                let body = format!("Invalid trace request: {}\n", reason);
+                return Response::new(400, TEXT, body).write_to(&mut stream);
+            }
+        };
+
+        let mut trace = match traceroute.trace(source, job.destination, job.options) {
+            Ok(trace) => trace,
+            Err(err) => {
+                return Response::new(500, TEXT, format!("{}\n", err)).write_to(&mut stream);
+            }
+        };
+
+        let sse = request
+            .header("accept")
+            .is_some_and(|accept| accept.contains("text/event-stream"));
+        let content_type = if sse {
+            "text/event-stream"
+        } else {
+            "application/x-ndjson"
+        };
+        Response::write_stream_head(&mut stream, 200, content_type)?;
+        info!(
+            "Tracing {} from {} for {} ({} running)",
+            job.destination,
+            source,
+            client,
+            self.concurrency.active()
+        );
+
+        // Responses go out as they arrive rather than once the round is over
+        let mut events = trace.events();
+        loop {
+            let (round, response) = match task::block_on(StreamExt::next(&mut events)) {
+                Some(Ok(HopEvent::HopReplied {
+                    round, response, ..
+                }))
+                | Some(Ok(HopEvent::DestinationReached {
+                    round, response, ..
+                })) => (round, TraceResponse::Received(response)),
+                Some(Ok(HopEvent::HopTimedOut { round, sent, .. })) => {
+                    (round, TraceResponse::TimedOut(sent))
+                }
+                Some(Ok(HopEvent::ProbeSent { .. })) | Some(Ok(HopEvent::HopRetried { .. })) => {
+                    continue;
+                }
+                Some(Ok(HopEvent::RoundComplete {
+                    round, responses, ..
+                })) => {
+                    // Masked distances are never probed so only show up with the round
+                    for response in responses
+                        .iter()
+                        .filter(|response| matches!(response, TraceResponse::Masked(_ttl)))
+                    {
+                        write_event(&mut stream, sse, &TraceEvent::Response { round, response })?;
+                    }
+                    write_event(&mut stream, sse, &TraceEvent::RoundComplete { round })?;
+                    if round >= job.rounds {
+                        break;
+                    }
+                    continue;
+                }
+                Some(Err(err)) => {
+                    let message = err.to_string();
+                    return write_event(&mut stream, sse, &TraceEvent::Error { message });
+                }
+                None => break,
+            };
+            let response = &response;
+            write_event(&mut stream, sse, &TraceEvent::Response { round, response })?;
+        }
+        Ok(())
+    }
+
+    // Requested source if it belongs to this machine, otherwise the one routing picks
+    fn source(
+        &self,
+        traceroute: &Traceroute,
+        requested: Option<IpAddr>,
+        destination: IpAddr,
+    ) -> Result<IpAddr, &'static str> {
+        match requested {
+            Some(source) if traceroute.addresses().contains(&source) => Ok(source),
+            Some(_source) => Err("source isn't an address of this machine"),
+            None => Ok(traceroute.source_for(destination)),
+        }
+    }
+}
+
+// Turn a connection away without reading its request when every slot is taken
+fn refuse(mut stream: TcpStream) {
+    match stream.peer_addr() {
+        Ok(client) => info!("Too many connections open, refused {}", client.ip()),
+        Err(_err) => info!("Too many connections open, refused a client"),
+    }
+    let written = stream
+        .set_write_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|()| Response::error(503).write_to(&mut stream));
+    if let Err(err) = written {
+        debug!("Failed to refuse client: {}", err);
+    }
+}
+
+fn write_event(stream: &mut TcpStream, sse: bool, event: &TraceEvent) -> io::Result<()> {
+    let json = serde_json::to_string(event).map_err(io::Error::other)?;
+    if sse {
+        write!(stream, "event: {}\ndata: {}\n\n", event.name(), json)?;
+    } else {
+        writeln!(stream, "{}", json)?;
+    }
+    stream.flush()
+}
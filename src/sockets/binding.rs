@@ -0,0 +1,148 @@
+use super::route::{self, Route};
+use crate::TracerouteError;
+use crate::utils::get_available_interfaces;
+use log::*;
+use pnet::datalink::NetworkInterface;
+use pnet::transport::TransportSender;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// Where probes leave this machine from
+///
+/// By default the routing table picks the source of each trace from the addresses of every usable
+/// interface.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Binding {
+    /// Name of the only interface probes are sent and received through
+    pub interface: Option<String>,
+    /// Only address probes are sent from and replies are received on
+    pub source: Option<IpAddr>,
+}
+
+/// Addresses a [`Binding`] allows and how to pick between them
+#[derive(Clone, Debug)]
+pub(crate) struct Bound {
+    pub addresses: Vec<Ipv4Addr>,
+    interface: Option<NetworkInterface>,
+    source: Option<IpAddr>,
+}
+
+impl Binding {
+    /// Check the interface and source exist and gather the addresses they allow
+    pub(crate) fn resolve(&self) -> Result<Bound, TracerouteError> {
+        let interfaces = match self.interface {
+            Some(ref name) => {
+                let interface = pnet::datalink::interfaces()
+                    .into_iter()
+                    .find(|interface| interface.name == *name)
+                    .ok_or_else(|| TracerouteError::UnknownInterface(name.clone()))?;
+                vec![interface]
+            }
+            None => get_available_interfaces(),
+        };
+
+        let mut addresses = interfaces
+            .iter()
+            .flat_map(|interface| interface.ips.iter())
+            .filter_map(|network| match network.ip() {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_ip) => None,
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(source) = self.source {
+            match source {
+                IpAddr::V4(ip) if addresses.contains(&ip) => addresses = vec![ip],
+                _ => return Err(TracerouteError::UnusableSource(source)),
+            }
+        }
+        if addresses.is_empty() {
+            return Err(TracerouteError::Io(io::Error::other(
+                "Couldn't find an interface IPv4 address",
+            )));
+        }
+
+        Ok(Bound {
+            addresses,
+            interface: match self.interface {
+                Some(_) => interfaces.into_iter().next(),
+                None => None,
+            },
+            source: self.source,
+        })
+    }
+}
+
+impl Bound {
+    /// Restrict the socket of a channel, shared by both its ends, to the interface and source
+    pub fn apply(&self, tx: &TransportSender) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::fd::BorrowedFd;
+
+            // SAFETY: the descriptor stays open for as long as the sender holds it
+            let borrowed = unsafe { BorrowedFd::borrow_raw(tx.socket.fd) };
+            let socket = socket2::SockRef::from(&borrowed);
+
+            if let Some(ref interface) = self.interface {
+                bind_device(&socket, &interface.name)?;
+            }
+            if let Some(source) = self.source {
+                socket.bind(&SocketAddr::new(source, 0).into())?;
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        match (&self.interface, self.source) {
+            (None, None) => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "binding isn't supported on this platform",
+            )),
+        }
+    }
+
+    /// Route the kernel takes to `destination` out of the interface
+    pub fn route(&self, destination: IpAddr) -> io::Result<Route> {
+        let index = self.interface.as_ref().map(|interface| interface.index);
+        route::lookup(destination, index)
+    }
+
+    /// Source address the routing table uses to reach `destination`
+    ///
+    /// The source of the binding always wins. Addresses the binding doesn't allow fall back to the
+    /// first one it does.
+    pub fn source_for(&self, destination: IpAddr) -> IpAddr {
+        if let Some(source) = self.source {
+            return source;
+        }
+        let first = IpAddr::V4(self.addresses[0]);
+
+        let route = match self.route(destination) {
+            Ok(route) => route,
+            Err(err) => {
+                warn!("Couldn't look up the route to {}: {}", destination, err);
+                return first;
+            }
+        };
+        debug!("Route to {}: {:?}", destination, route);
+
+        match route.source {
+            Some(IpAddr::V4(ip)) if self.addresses.contains(&ip) => IpAddr::V4(ip),
+            _ => first,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &socket2::SockRef, name: &str) -> io::Result<()> {
+    socket.bind_device(Some(name.as_bytes()))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn bind_device(_socket: &socket2::SockRef, name: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("can't bind to {} on this platform", name),
+    ))
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Which way a captured packet was going
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Called with every packet the engine sends or receives, starting at the IP header
+///
+/// Hooks run on the network threads, anything slow holds up the probes.
+pub type CaptureHook = Arc<dyn Fn(Direction, &[u8]) + Send + Sync>;
+
+/// Every hook packets are handed to
+#[derive(Clone, Default)]
+pub(crate) struct Captures {
+    hooks: Vec<CaptureHook>,
+}
+
+impl Captures {
+    pub fn push(&mut self, hook: CaptureHook) {
+        self.hooks.push(hook);
+    }
+
+    pub fn record(&self, direction: Direction, packet: &[u8]) {
+        for hook in &self.hooks {
+            hook(direction, packet);
+        }
+    }
+}
+
+impl fmt::Debug for Captures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} capture hooks", self.hooks.len())
+    }
+}
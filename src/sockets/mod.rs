@@ -1,7 +1,25 @@
+mod binding;
+mod capture;
+mod options;
+mod rate;
 mod receivers;
+mod route;
 mod senders;
+mod shutdown;
 mod sockets;
+mod stats;
 
+pub use binding::Binding;
+pub(crate) use binding::Bound;
+pub use capture::{CaptureHook, Direction};
+pub(crate) use capture::Captures;
+pub use options::Backend;
+pub(crate) use options::SocketOptions;
+pub use rate::RateLimit;
+pub(crate) use rate::{DestinationBudgets, TokenBucket};
 use receivers::{SocketReceiver, SocketReceivers};
+pub use route::Route;
 use senders::{SocketSender, SocketSenders};
+pub use shutdown::ShutdownHandle;
 pub use sockets::{SocketJoinResult, Sockets};
+pub use stats::{EngineCounters, EngineStats};
@@ -0,0 +1,51 @@
+use super::{Binding, Captures, RateLimit};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use std::time::Duration;
+
+// Bytes the receive socket buffers for each packet
+pub(crate) const BUFFER_SIZE: usize = 4096;
+
+// How long to wait for new packets from the outside world before breaking the loop and
+// allowing other things to happen
+pub(crate) const RECEIVE_TIMEOUT: Duration = Duration::from_micros(100);
+
+// How long we will wait for an unmatched packet to stay around before dropping them
+pub(crate) const UNMATCHED_PACKETS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How probes are put on the wire and replies taken off it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Raw IP sockets we write the whole IP header on. Needs root or CAP_NET_RAW
+    #[default]
+    RawSocket,
+}
+
+/// Settings of the network threads
+#[derive(Clone, Debug)]
+pub(crate) struct SocketOptions {
+    pub rate_limit: RateLimit,
+    pub binding: Binding,
+    pub backend: Backend,
+    pub buffer_size: usize,
+    pub receive_timeout: Duration,
+    pub unmatched_timeout: Duration,
+    /// Protocols a receive socket is opened for, ICMP first
+    pub listen: Vec<IpNextHeaderProtocol>,
+    pub captures: Captures,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            rate_limit: RateLimit::default(),
+            binding: Binding::default(),
+            backend: Backend::default(),
+            buffer_size: BUFFER_SIZE,
+            receive_timeout: RECEIVE_TIMEOUT,
+            unmatched_timeout: UNMATCHED_PACKETS_TIMEOUT,
+            listen: vec![IpNextHeaderProtocols::Icmp],
+            captures: Captures::default(),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+// Bursts may use up to this much time worth of tokens
+const BURST: Duration = Duration::from_millis(50);
+
+/// Packets per second budgets of the sender
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Packets per second shared by every trace. 0 is unlimited
+    pub packets_per_second: u32,
+    /// Packets per second sent to any single destination. `None` is unlimited
+    pub per_destination: Option<u32>,
+}
+
+impl RateLimit {
+    /// Budget matching a delay between each packet sent
+    pub fn from_delay(packet_delay: Duration) -> Self {
+        let packets_per_second = match packet_delay.as_micros() {
+            0 => 0,
+            micros => (1_000_000 / micros).max(1) as u32,
+        };
+        Self {
+            packets_per_second,
+            per_destination: None,
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            packets_per_second: 200,
+            per_destination: None,
+        }
+    }
+}
+
+/// Token bucket refilled at a steady rate
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    // tokens per second, 0 is unlimited
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(packets_per_second: u32) -> Self {
+        let rate = packets_per_second as f64;
+        let capacity = (rate * BURST.as_secs_f64()).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Time until a token is available
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        if self.rate == 0.0 {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    /// Use up a token. Check [`wait`](Self::wait) first
+    pub fn take(&mut self) {
+        if self.rate != 0.0 {
+            self.tokens -= 1.0;
+        }
+    }
+}
+
+/// Token buckets of every destination being sent to
+///
+/// Buckets only exist when there is a per destination limit.
+#[derive(Clone, Debug, Default)]
+pub struct DestinationBudgets {
+    limit: Option<u32>,
+    buckets: HashMap<Ipv4Addr, TokenBucket>,
+}
+
+impl DestinationBudgets {
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Time until a packet may go to `destination`
+    pub fn wait(&mut self, destination: Ipv4Addr, now: Instant) -> Duration {
+        self.buckets
+            .get_mut(&destination)
+            .map_or(Duration::ZERO, |bucket| bucket.wait(now))
+    }
+
+    /// Use up a token of `destination`. Check [`wait`](Self::wait) first
+    pub fn take(&mut self, destination: Ipv4Addr) {
+        if let Some(limit) = self.limit {
+            self.buckets
+                .entry(destination)
+                .or_insert_with(|| TokenBucket::new(limit))
+                .take();
+        }
+    }
+
+    /// Forget the budgets of destinations no longer sent to
+    pub fn retain(&mut self, sending: impl Fn(Ipv4Addr) -> bool) {
+        self.buckets
+            .retain(|destination, _bucket| sending(*destination));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Full bucket last refilled at `start`
+    fn bucket(packets_per_second: u32, start: Instant) -> TokenBucket {
+        TokenBucket {
+            last: start,
+            ..TokenBucket::new(packets_per_second)
+        }
+    }
+
+    // Take every token available at `now`
+    fn drain(bucket: &mut TokenBucket, now: Instant) -> usize {
+        let mut taken = 0;
+        while bucket.wait(now).is_zero() {
+            bucket.take();
+            taken += 1;
+        }
+        taken
+    }
+
+    #[test]
+    fn unlimited_never_waits() {
+        let start = Instant::now();
+        let mut bucket = bucket(0, start);
+        for _ in 0..10_000 {
+            assert_eq!(bucket.wait(start), Duration::ZERO);
+            bucket.take();
+        }
+    }
+
+    #[test]
+    fn bursts_are_capped() {
+        let start = Instant::now();
+        let mut bucket = bucket(1000, start);
+        // 50ms worth of tokens
+        assert_eq!(drain(&mut bucket, start), 50);
+        assert_eq!(bucket.wait(start), ms(1));
+
+        // A long idle doesn't save up more than a burst
+        assert_eq!(drain(&mut bucket, start + ms(10_000)), 50);
+    }
+
+    #[test]
+    fn slow_rates_still_send_one_at_a_time() {
+        let start = Instant::now();
+        let mut bucket = bucket(10, start);
+        assert_eq!(drain(&mut bucket, start), 1);
+        assert_eq!(bucket.wait(start), ms(100));
+        assert_eq!(bucket.wait(start + ms(60)), ms(40));
+        assert_eq!(drain(&mut bucket, start + ms(100)), 1);
+    }
+
+    #[test]
+    fn time_going_backwards_refills_nothing() {
+        let start = Instant::now() + ms(1000);
+        let mut bucket = bucket(10, start);
+        bucket.take();
+        assert_eq!(bucket.wait(start - ms(500)), ms(100));
+    }
+
+    #[test]
+    fn destinations_have_their_own_budget() {
+        let a = Ipv4Addr::new(192, 0, 2, 1);
+        let b = Ipv4Addr::new(192, 0, 2, 2);
+        let start = Instant::now();
+
+        let mut budgets = DestinationBudgets::new(Some(10));
+        budgets.take(a);
+        assert!(budgets.wait(a, start) > ms(90));
+        assert_eq!(budgets.wait(b, start), Duration::ZERO);
+
+        // Destinations traced again start with a full bucket
+        budgets.retain(|destination| destination == b);
+        assert_eq!(budgets.wait(a, start), Duration::ZERO);
+    }
+
+    #[test]
+    fn destinations_are_unlimited_without_a_limit() {
+        let a = Ipv4Addr::new(192, 0, 2, 1);
+        let mut budgets = DestinationBudgets::new(None);
+        for _ in 0..100 {
+            assert_eq!(budgets.wait(a, Instant::now()), Duration::ZERO);
+            budgets.take(a);
+        }
+        assert!(budgets.buckets.is_empty());
+    }
+
+    #[test]
+    fn delays_become_rates() {
+        assert_eq!(RateLimit::from_delay(Duration::ZERO).packets_per_second, 0);
+        assert_eq!(RateLimit::from_delay(ms(5)).packets_per_second, 200);
+        assert_eq!(
+            RateLimit::from_delay(Duration::from_micros(3)).packets_per_second,
+            333_333
+        );
+        // Delays over a second still send
+        assert_eq!(RateLimit::from_delay(ms(2500)).packets_per_second, 1);
+        assert_eq!(RateLimit::from_delay(ms(5)).per_destination, None);
+    }
+}
@@ -1,30 +1,26 @@
+use super::{Direction, EngineStats, SocketOptions};
 use crate::prelude::*;
-use crate::probe::{ProbeResponse, ProbeSent};
-use crate::trace::{TraceResponse, TraceResult, TraceSent};
+use crate::probe::{IcmpReply, ProbeResponse, ProbeSent};
+use crate::trace::{Activity, SentActivity, TraceResponse, TraceResult, TraceSent};
 use crate::utils::handle_ipv4_packet;
 use core::sync::atomic::{AtomicBool, Ordering};
 use log::*;
+use pnet::packet::Packet;
 use pnet::transport::{TransportReceiver, ipv4_packet_iter};
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::Duration;
 use std::time::Instant;
 
-// How long to wait for new packets from the outside world before breaking the loop and
-// allowing other things to happen
-const RECEIVE_TIMEOUT: Duration = Duration::from_micros(100);
-
-// How long we will wait for an unmatched packet to stay around before dropping them
-const UNMATCHED_PACKETS_TIMEOUT: Duration = Duration::from_secs(10);
-
 pub struct SocketReceiver {
-    rx: TransportReceiver,
+    // One per protocol listened for, replies to probes come in on the first
+    channels: Vec<TransportReceiver>,
 }
 impl SocketReceiver {
-    pub fn new(rx: TransportReceiver) -> Self {
-        Self { rx }
+    pub fn new(channels: Vec<TransportReceiver>) -> Self {
+        Self { channels }
     }
 }
 
@@ -36,175 +32,320 @@ pub enum SocketReceivers {
         v6: SocketReceiver,
     },
 }
-type FlowMap = HashMap<Flowhash, (Duration, Sender<TraceResult>)>;
-type ProbeMap = HashMap<TcpId, ProbeSent>;
-type PacketMap = HashMap<TcpId, (IpAddr, Instant)>;
+// Probes to different destinations may share an id
+type ProbeKey = (IpAddr, TcpId);
+// A probe with its trace, timeout and the connection back to the requester
+type InFlightProbe = (TraceId, ProbeSent, Duration, Sender<TraceResult>);
+type PacketMap = HashMap<ProbeKey, (IcmpReply, Instant)>;
+
+// Probes awaiting responses from the network
+#[derive(Default)]
+struct InFlight {
+    probes: HashMap<ProbeKey, InFlightProbe>,
+    // Ids of probes behind a broken NAT which may be matched by checksum instead
+    checksums: HashMap<(IpAddr, Checksum), TcpId>,
+}
+
+impl InFlight {
+    // Wait on a reply to the probe, handing back a probe it displaced. Ids only repeat after
+    // many probes so the displaced one waited far longer than any timeout
+    fn track(&mut self, probe: InFlightProbe, broken_nat: bool) -> Option<InFlightProbe> {
+        let (destination, id) = key(&probe.1);
+        let checksum = probe.1.checksum;
+        let displaced = self.probes.insert((destination, id), probe);
+        if let Some((_trace_id, sent, _timeout, _sender)) = &displaced {
+            warn!(
+                "Probe to {} reused the id {} of a probe in flight",
+                destination, id
+            );
+            self.forget_checksum(sent);
+        }
+        if broken_nat && let Some(other) = self.checksums.insert((destination, checksum), id) {
+            debug!("Probe {} took over the checksum of probe {}", id, other);
+        }
+        displaced
+    }
+
+    // Take the probe `reply` answers. A broken NAT may have rewritten the quoted id
+    fn claim(&mut self, reply: &IcmpReply) -> Option<InFlightProbe> {
+        let destination = reply.probe_destination()?;
+        let key = match reply.acknowledges {
+            // The destination answering a TCP probe quotes nothing of it
+            Some(sequence) => self
+                .probes
+                .iter()
+                .find(|((probed, _id), (_trace_id, sent, _timeout, _sender))| {
+                    *probed == destination && sent.tcp_sequence() == Some(sequence)
+                })
+                .map(|(key, _probe)| *key)?,
+            None if self.probes.contains_key(&(destination, reply.id)) => (destination, reply.id),
+            None => {
+                let checksum = reply.checksum?;
+                (destination, *self.checksums.get(&(destination, checksum))?)
+            }
+        };
+        let probe = self.probes.remove(&key)?;
+        self.forget_checksum(&probe.1);
+        Some(probe)
+    }
+
+    // Remove the probes which waited longer than their timeout
+    fn expire(&mut self, now: &Instant) -> Vec<InFlightProbe> {
+        let expired: Vec<ProbeKey> = self
+            .probes
+            .iter()
+            .filter(|(_key, (_trace_id, sent, timeout, _sender))| {
+                now.duration_since(sent.instant) > *timeout
+            })
+            .map(|(key, _probe)| *key)
+            .collect();
+        self.remove(expired)
+    }
+
+    // Remove the probes of the traces picked
+    fn forget(&mut self, cancelled: impl Fn(TraceId) -> bool) -> Vec<InFlightProbe> {
+        let forgotten: Vec<ProbeKey> = self
+            .probes
+            .iter()
+            .filter(|(_key, (trace_id, _sent, _timeout, _sender))| cancelled(*trace_id))
+            .map(|(key, _probe)| *key)
+            .collect();
+        self.remove(forgotten)
+    }
+
+    fn remove(&mut self, keys: Vec<ProbeKey>) -> Vec<InFlightProbe> {
+        let mut removed = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(probe) = self.probes.remove(&key) {
+                self.forget_checksum(&probe.1);
+                removed.push(probe);
+            }
+        }
+        removed
+    }
+
+    // Stop matching a probe by checksum, unless another probe took over the checksum
+    fn forget_checksum(&mut self, sent: &ProbeSent) {
+        let (destination, id) = key(sent);
+        let checksum = (destination, sent.checksum);
+        if self.checksums.get(&checksum) == Some(&id) {
+            let _ = self.checksums.remove(&checksum);
+        }
+    }
+}
+
+// Where a probe is kept while in flight
+fn key(sent: &ProbeSent) -> ProbeKey {
+    let destination = sent
+        .destination()
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    (destination, sent.id)
+}
 
 impl SocketReceivers {
     pub fn receive(
         &mut self,
-        probe_receiver: Receiver<TraceSent>,
+        probe_receiver: Receiver<SentActivity>,
+        options: &SocketOptions,
         runnable: Arc<AtomicBool>,
+        stats: Arc<EngineStats>,
     ) -> Result<(), TracerouteError> {
-        // Flows and their connection back to the requester
-        let mut flows: FlowMap = HashMap::new();
         // Probes awaiting responses from the network
-        let mut probes: ProbeMap = HashMap::new();
+        let mut in_flight = InFlight::default();
         // Packets received without a matching probe
         let mut unmatched_packets: PacketMap = HashMap::new();
 
         while runnable.load(Ordering::SeqCst) {
             //debug!("num flows {}; num probes {};", flows.len(), probes.len());
             if let Self::V4(socket) = self {
-                let mut packet_iter = ipv4_packet_iter(&mut socket.rx);
-                loop {
-                    // Aggressively handle new probes sent
-                    while let Ok(trace_sent) = probe_receiver.try_recv() {
-                        let TraceSent {
-                            probes: sent_probes,
-                            timeout,
-                            activity_sender,
-                        } = trace_sent;
-
-                        debug!(
-                            "Receiver has received TraceSent with {} probes",
-                            sent_probes.len()
-                        );
-
-                        let flowhash = sent_probes.first().unwrap().flowhash;
-
-                        for sent in sent_probes {
-                            // Was this packet seen before the TraceSent package got here
-                            // IRL packets from immediate router could respond faster
-                            //
-                            // source from the unmatched packet is the would be a destination
-                            // from this machine perspective
-                            if let Some((source, instant)) = unmatched_packets.remove(&sent.id) {
-                                let activity = TraceResponse::Received(ProbeResponse::new(
-                                    sent, source, instant,
-                                ));
+                for channel in socket.channels.iter_mut() {
+                    let mut packet_iter = ipv4_packet_iter(channel);
+                    loop {
+                        // Aggressively handle new probes sent
+                        while let Ok(activity) = probe_receiver.try_recv() {
+                            let trace_sent = match activity {
+                                SentActivity::Sent(trace_sent) => trace_sent,
+                                SentActivity::Cancel(trace_id) => {
+                                    // Dropping the senders lets the trace see it has
+                                    // nothing left to wait for
+                                    forget_probes(&mut in_flight, &stats, |id| id == trace_id);
+                                    continue;
+                                }
+                                SentActivity::Shutdown => {
+                                    forget_probes(&mut in_flight, &stats, |_id| true);
+                                    continue;
+                                }
+                            };
+                            let TraceSent {
+                                trace_id,
+                                probes: sent_probes,
+                                timeout,
+                                broken_nat,
+                                activity_sender,
+                            } = trace_sent;
 
-                                // If sender is closed there isn't anything we can do about it here
-                                let _ = activity_sender.send(Ok(activity));
-                            }
-                            // watch for probe in the future
-                            else {
-                                let _ = probes.insert(sent.id, sent);
-                            }
-                        }
+                            debug!(
+                                "Receiver has received TraceSent with {} probes",
+                                sent_probes.len()
+                            );
 
-                        let _ = flows.insert(flowhash, (timeout, activity_sender));
-                    }
+                            for sent in sent_probes {
+                                // Was this packet seen before the TraceSent package got here
+                                // IRL packets from immediate router could respond faster
+                                //
+                                // source from the unmatched packet is the would be a destination
+                                // from this machine perspective
+                                let early = unmatched_packets.remove(&key(&sent)).or_else(|| {
+                                    if !broken_nat {
+                                        return None;
+                                    }
+                                    let (destination, _id) = key(&sent);
+                                    let key = unmatched_packets
+                                        .iter()
+                                        .find(|((probed, _id), (reply, _instant))| {
+                                            *probed == destination
+                                                && reply.checksum == Some(sent.checksum)
+                                        })
+                                        .map(|(key, _packet)| *key)?;
+                                    unmatched_packets.remove(&key)
+                                });
+                                if let Some((reply, instant)) = early {
+                                    let activity = TraceResponse::Received(ProbeResponse::new(
+                                        sent, reply, instant,
+                                    ));
+                                    stats.reply_matched();
 
-                    // Grab packets until timeout
-                    let option = packet_iter
-                        .next_with_timeout(RECEIVE_TIMEOUT)
-                        .map_err(TracerouteError::Io)?;
+                                    // If sender is closed there isn't anything we can do about it here
+                                    let _ = activity_sender.send(Ok(Activity::Response(activity)));
+                                }
+                                // watch for probe in the future
+                                else {
+                                    let probe = (trace_id, sent, timeout, activity_sender.clone());
+                                    if let Some(displaced) = in_flight.track(probe, broken_nat) {
+                                        time_out(displaced, &stats);
+                                    }
+                                }
+                            }
+                        }
 
-                    // Did we time out
-                    let packet = match option {
-                        None => break, // We didn't see any probes
-                        Some((payload, _ip)) => payload,
-                    };
+                        // Grab packets until timeout
+                        let option = packet_iter
+                            .next_with_timeout(options.receive_timeout)
+                            .map_err(TracerouteError::Io)?;
 
-                    // The moment we acknowledge the packet is received
-                    let instant = Instant::now();
+                        // Did we time out
+                        let packet = match option {
+                            None => break, // We didn't see any probes
+                            Some((payload, _ip)) => payload,
+                        };
 
-                    //
-                    let (source, id, _checksum) = match handle_ipv4_packet(packet) {
-                        Ok(data) => data,
-                        Err(err) => todo!("traceroute error relating to packet parsing, {}", err),
-                    };
+                        // The moment we acknowledge the packet is received
+                        let instant = Instant::now();
+                        options
+                            .captures
+                            .record(Direction::Received, packet.packet());
 
-                    // Match packet and return
-                    match probes.remove(&id) {
-                        Some(sent) => {
-                            let (_timeout, sender) = flows.get(&sent.flowhash).unwrap();
+                        //
+                        let reply = match handle_ipv4_packet(packet) {
+                            Ok(data) => data,
+                            Err(TracerouteError::MalformedPacket) => {
+                                debug!("Received malformed packet");
+                                stats.parse_error();
+                                continue;
+                            }
+                            // ICMP traffic which isn't a reply to a probe
+                            Err(err) => {
+                                debug!("Ignoring packet: {}", err);
+                                stats.unmatched_packet();
+                                continue;
+                            }
+                        };
 
-                            let activity =
-                                TraceResponse::Received(ProbeResponse::new(sent, source, instant));
+                        // Match packet and return
+                        match in_flight.claim(&reply) {
+                            Some((_trace_id, sent, _timeout, sender)) => {
+                                let activity = TraceResponse::Received(ProbeResponse::new(
+                                    sent, reply, instant,
+                                ));
+                                stats.reply_matched();
 
-                            // If sender is closed there isn't anything we can do about it here
-                            let _ = sender.send(Ok(activity));
-                        }
-                        None => {
-                            debug!("Received packet not found in probes from {}", source);
-                            // store packet to see if a TraceSent comes to claim it
-                            let _ = unmatched_packets.insert(id, (source, instant));
-                        }
-                    };
+                                // If sender is closed there isn't anything we can do about it here
+                                let _ = sender.send(Ok(Activity::Response(activity)));
+                            }
+                            None => {
+                                debug!("Received packet not found in probes from {}", reply.source);
+                                stats.unmatched_packet();
+                                // TCP replies have no id to be claimed by
+                                if reply.acknowledges.is_some() {
+                                    continue;
+                                }
+                                // Replies quoting too little can't be told apart
+                                let Some(destination) = reply.probe_destination() else {
+                                    continue;
+                                };
+                                // store packet to see if a TraceSent comes to claim it. A
+                                // repeated reply replaces the one before
+                                let key = (destination, reply.id);
+                                if unmatched_packets.insert(key, (reply, instant)).is_some() {
+                                    debug!("Replaced earlier unmatched reply {:?}", key);
+                                }
+                            }
+                        };
+                    }
                 }
 
                 let now = Instant::now();
 
-                remove_expired_unmatched_packets(&now, &mut unmatched_packets);
-
-                remove_timed_out_probes(&now, &mut probes, &flows);
+                remove_expired_unmatched_packets(
+                    &now,
+                    &mut unmatched_packets,
+                    options.unmatched_timeout,
+                );
 
-                remove_empty_flows(&probes, &mut flows);
+                for probe in in_flight.expire(&now) {
+                    time_out(probe, &stats);
+                }
             }
         }
         Ok(())
     }
 }
 
-fn remove_empty_flows(probes: &ProbeMap, flows: &mut FlowMap) {
-    // identify flows there are no probes for
-    let flows_to_remove: Vec<Flowhash> = flows
-        .keys()
-        .filter(|flowhash| {
-            // count probes with a specific flowhash
-            let count = probes
-                .values()
-                .filter(|sent| sent.flowhash == **flowhash)
-                .count();
-            count == 0
-        })
-        // take copy of flowhash to eliminate borrowing
-        .map(|flowhash| *flowhash)
-        .collect();
-    // close flows we don't have probes for
-    for flowhash in flows_to_remove {
-        let _ = flows.remove(&flowhash);
-    }
+// Report an unanswered probe to its trace
+fn time_out((_trace_id, sent, _timeout, sender): InFlightProbe, stats: &EngineStats) {
+    stats.probe_expired();
+    // If sender is closed there isn't anything we can do about it here
+    let _ = sender.send(Ok(Activity::Response(TraceResponse::TimedOut(sent))));
 }
 
-fn remove_timed_out_probes(now: &Instant, probes: &mut ProbeMap, flows: &FlowMap) {
-    // Check for probes which have timed out and collect their ids
-    let probes_to_remove: Option<Vec<TcpId>> = probes
-        .iter()
-        .map(|(id, sent)| {
-            let (timeout, _sender) = flows.get(&sent.flowhash).unwrap();
-
-            if now.duration_since(sent.instant) > *timeout {
-                return Some(*id);
-            }
-            None
-        })
-        .collect();
-    // remove probes that have expired
-    if let Some(probes_to_remove) = probes_to_remove {
-        for id in probes_to_remove {
-            // remove probe and grab the owned value
-            let sent = probes
-                .remove(&id)
-                .expect("couldn't find probe seen moments ago");
-
-            // get the sender
-            let (_timeout, sender) = flows.get(&sent.flowhash).unwrap();
-            // Send unresponsive response for unseen probes
-            // If sender is closed there isn't anything we can do about it here
-            let _ = sender.send(Ok(TraceResponse::TimedOut(sent)));
-        }
+// Drop the probes of the traces picked, telling the traces they won't be answered
+fn forget_probes(
+    in_flight: &mut InFlight,
+    stats: &EngineStats,
+    cancelled: impl Fn(TraceId) -> bool,
+) {
+    let forgotten = in_flight.forget(cancelled);
+    for (_trace_id, _sent, _timeout, sender) in &forgotten {
+        // If sender is closed there isn't anything we can do about it here
+        let _ = sender.send(Ok(Activity::Cancelled));
     }
+    debug!(
+        "Receiver has forgotten {} probes of cancelled traces",
+        forgotten.len()
+    );
+    stats.probes_cancelled(forgotten.len());
 }
 
-fn remove_expired_unmatched_packets(now: &Instant, unmatched_packets: &mut PacketMap) {
+fn remove_expired_unmatched_packets(
+    now: &Instant,
+    unmatched_packets: &mut PacketMap,
+    unmatched_timeout: Duration,
+) {
     // remove unmatched packets that have lingered around too long
-    let packets_to_remove: Option<Vec<TcpId>> = unmatched_packets
+    let packets_to_remove: Option<Vec<ProbeKey>> = unmatched_packets
         .iter()
         .map(|(id, (_source, received))| {
-            if now.duration_since(*received) > UNMATCHED_PACKETS_TIMEOUT {
+            if now.duration_since(*received) > unmatched_timeout {
                 return Some(*id);
             }
             None
@@ -220,3 +361,125 @@ fn remove_expired_unmatched_packets(now: &Instant, unmatched_packets: &mut Packe
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::ReplyKind;
+    use std::sync::mpsc::{self, Receiver};
+
+    const ID: TcpId = 7;
+
+    // IPv4 header of a probe followed by the first bytes of its transport header
+    fn headers(destination: Ipv4Addr, protocol: u8, sequence: u32) -> Vec<u8> {
+        let mut headers = vec![0u8; 28];
+        headers[0] = 0x45;
+        headers[4..6].copy_from_slice(&ID.to_be_bytes());
+        headers[9] = protocol;
+        headers[12..16].copy_from_slice(&[192, 0, 2, 2]);
+        headers[16..20].copy_from_slice(&destination.octets());
+        headers[24..28].copy_from_slice(&sequence.to_be_bytes());
+        headers
+    }
+
+    fn probe(trace_id: TraceId, headers: Vec<u8>) -> (InFlightProbe, Receiver<TraceResult>) {
+        let (sender, receiver) = mpsc::channel();
+        let sent = ProbeSent {
+            source: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            ttl: 3,
+            id: ID,
+            checksum: 0x1234,
+            flowhash: 0,
+            tos: 0,
+            headers,
+            instant: Instant::now(),
+        };
+        ((trace_id, sent, Duration::from_secs(1), sender), receiver)
+    }
+
+    fn reply(source: Ipv4Addr, quoted: Vec<u8>, acknowledges: Option<u32>) -> IcmpReply {
+        IcmpReply {
+            source: IpAddr::V4(source),
+            ip_id: 0,
+            ttl: 60,
+            id: ID,
+            checksum: Some(0x1234),
+            acknowledges,
+            quoted,
+            kind: ReplyKind::TimeExceeded,
+        }
+    }
+
+    #[test]
+    fn probes_sharing_an_id_are_told_apart_by_destination() {
+        let first = Ipv4Addr::new(198, 51, 100, 1);
+        let second = Ipv4Addr::new(203, 0, 113, 1);
+        let router = Ipv4Addr::new(192, 0, 2, 1);
+        let mut in_flight = InFlight::default();
+
+        let (first_probe, _first_receiver) = probe(1, headers(first, 17, 0));
+        let (second_probe, _second_receiver) = probe(2, headers(second, 17, 0));
+        assert!(in_flight.track(first_probe, false).is_none());
+        assert!(in_flight.track(second_probe, false).is_none());
+
+        let claimed = in_flight.claim(&reply(router, headers(second, 17, 0), None));
+        assert_eq!(claimed.map(|probe| probe.0), Some(2));
+        let claimed = in_flight.claim(&reply(router, headers(first, 17, 0), None));
+        assert_eq!(claimed.map(|probe| probe.0), Some(1));
+        assert!(in_flight.probes.is_empty());
+    }
+
+    #[test]
+    fn broken_nat_checksums_are_kept_per_destination() {
+        let first = Ipv4Addr::new(198, 51, 100, 1);
+        let second = Ipv4Addr::new(203, 0, 113, 1);
+        let router = Ipv4Addr::new(192, 0, 2, 1);
+        let mut in_flight = InFlight::default();
+
+        let (first_probe, _first_receiver) = probe(1, headers(first, 17, 0));
+        let (second_probe, _second_receiver) = probe(2, headers(second, 17, 0));
+        assert!(in_flight.track(first_probe, true).is_none());
+        assert!(in_flight.track(second_probe, true).is_none());
+
+        // The NAT rewrote the id but left the checksum
+        let mut mangled = reply(router, headers(first, 17, 0), None);
+        mangled.id = ID + 1;
+        let claimed = in_flight.claim(&mangled);
+        assert_eq!(claimed.map(|probe| probe.0), Some(1));
+        assert_eq!(in_flight.checksums.len(), 1);
+    }
+
+    #[test]
+    fn tcp_replies_are_matched_by_destination_and_sequence() {
+        let first = Ipv4Addr::new(198, 51, 100, 1);
+        let second = Ipv4Addr::new(203, 0, 113, 1);
+        let mut in_flight = InFlight::default();
+
+        let (first_probe, _first_receiver) = probe(1, headers(first, 6, 99));
+        let (second_probe, _second_receiver) = probe(2, headers(second, 6, 99));
+        assert!(in_flight.track(first_probe, false).is_none());
+        assert!(in_flight.track(second_probe, false).is_none());
+
+        let claimed = in_flight.claim(&reply(second, Vec::new(), Some(99)));
+        assert_eq!(claimed.map(|probe| probe.0), Some(2));
+        assert!(
+            in_flight
+                .claim(&reply(second, Vec::new(), Some(99)))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn a_reused_id_hands_back_the_displaced_probe() {
+        let destination = Ipv4Addr::new(198, 51, 100, 1);
+        let mut in_flight = InFlight::default();
+
+        let (first_probe, _first_receiver) = probe(1, headers(destination, 17, 0));
+        let (second_probe, _second_receiver) = probe(2, headers(destination, 17, 0));
+        assert!(in_flight.track(first_probe, true).is_none());
+        let displaced = in_flight.track(second_probe, true);
+        assert_eq!(displaced.map(|probe| probe.0), Some(1));
+        assert_eq!(in_flight.probes.len(), 1);
+        assert_eq!(in_flight.checksums.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::net::IpAddr;
+
+/// Where the kernel sends packets bound for a destination
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Route {
+    /// Address packets leave from
+    pub source: Option<IpAddr>,
+    /// Index of the interface packets leave through
+    pub interface: Option<u32>,
+    /// Next hop, None when the destination is on a local network
+    pub gateway: Option<IpAddr>,
+}
+
+/// Ask the routing table of the kernel how it reaches `destination`
+///
+/// Restricting the lookup to the interface with index `interface` finds the route a socket bound
+/// to it takes.
+#[cfg(target_os = "linux")]
+pub fn lookup(destination: IpAddr, interface: Option<u32>) -> io::Result<Route> {
+    use netlink_packet_core::{NLM_F_REQUEST, NetlinkHeader, NetlinkMessage, NetlinkPayload};
+    use netlink_packet_route::route::Nla;
+    use netlink_packet_route::{AF_INET, AF_INET6, RouteMessage, RtnlMessage};
+    use netlink_sys::{Socket, SocketAddr, protocols::NETLINK_ROUTE};
+
+    let mut socket = Socket::new(NETLINK_ROUTE)?;
+    let _ = socket.bind_auto()?;
+    socket.connect(&SocketAddr::new(0, 0))?;
+
+    let mut message = RouteMessage::default();
+    match destination {
+        IpAddr::V4(ip) => {
+            message.header.address_family = AF_INET as u8;
+            message.header.destination_prefix_length = 32;
+            message.nlas.push(Nla::Destination(ip.octets().to_vec()));
+        }
+        IpAddr::V6(ip) => {
+            message.header.address_family = AF_INET6 as u8;
+            message.header.destination_prefix_length = 128;
+            message.nlas.push(Nla::Destination(ip.octets().to_vec()));
+        }
+    }
+    if let Some(index) = interface {
+        message.nlas.push(Nla::Oif(index));
+    }
+
+    let mut header = NetlinkHeader::default();
+    header.flags = NLM_F_REQUEST;
+    let mut request =
+        NetlinkMessage::new(header, NetlinkPayload::from(RtnlMessage::GetRoute(message)));
+    request.finalize();
+    let mut buffer = vec![0; request.buffer_len()];
+    request.serialize(&mut buffer);
+    let _ = socket.send(&buffer, 0)?;
+
+    let mut buffer = vec![0; 4096];
+    let size = socket.recv(&mut &mut buffer[..], 0)?;
+    let reply = NetlinkMessage::<RtnlMessage>::deserialize(&buffer[..size])
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let message = match reply.payload {
+        NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(message)) => message,
+        NetlinkPayload::Error(err) => return Err(err.to_io()),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected reply to a route lookup",
+            ));
+        }
+    };
+
+    let mut route = Route::default();
+    for nla in message.nlas {
+        match nla {
+            Nla::PrefSource(bytes) => route.source = address(&bytes),
+            Nla::Gateway(bytes) => route.gateway = address(&bytes),
+            Nla::Oif(index) => route.interface = Some(index),
+            _ => (),
+        }
+    }
+    Ok(route)
+}
+
+/// Ask the kernel which address it sends packets bound for `destination` from
+///
+/// Without netlink only the source is known, found by connecting a UDP socket which sends nothing.
+#[cfg(not(target_os = "linux"))]
+pub fn lookup(destination: IpAddr, _interface: Option<u32>) -> io::Result<Route> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+    let unspecified: IpAddr = match destination {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(SocketAddr::new(destination, 9))?;
+    Ok(Route {
+        source: Some(socket.local_addr()?.ip()),
+        ..Default::default()
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
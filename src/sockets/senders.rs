@@ -1,16 +1,23 @@
+use super::{DestinationBudgets, Direction, EngineStats, SocketOptions, TokenBucket};
 use crate::TracerouteError;
-use crate::probe::{ProbeBundle, ProbeSent};
-use crate::trace::{TraceRequest, TraceSent};
+use crate::prelude::TraceId;
+use crate::probe::ProbeBundle;
+use crate::trace::{Activity, SentActivity, TraceRequest, TraceResult, TraceSent};
 use core::sync::atomic::{AtomicBool, Ordering};
 use log::*;
+use pnet::packet::Packet;
 use pnet::packet::ipv4::Ipv4Packet;
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
 
 use pnet::transport::TransportSender;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+// How long to wait for new requests before checking if we should still be running
+const IDLE_TIMEOUT: Duration = Duration::from_millis(10);
 
 trait SocketSenderTrait<I, P> {
     fn send_packet(&mut self, packet: P, destination: I) -> Result<usize, TracerouteError>;
@@ -19,15 +26,23 @@ trait SocketSenderTrait<I, P> {
 pub struct SocketSender<I> {
     pub addresses: Vec<I>,
     tx: TransportSender,
-    packet_delay: Duration,
 }
 
 impl SocketSender<Ipv4Addr> {
-    pub fn new(addresses: Vec<Ipv4Addr>, tx: TransportSender, packet_delay: Duration) -> Self {
-        Self { addresses, tx, packet_delay }
+    pub fn new(addresses: Vec<Ipv4Addr>, tx: TransportSender) -> Self {
+        Self { addresses, tx }
     }
 }
 
+// Probes of a request still waiting for their turn
+struct PendingRequest<'trace> {
+    trace_id: TraceId,
+    destination: Ipv4Addr,
+    bundles: std::vec::IntoIter<ProbeBundle<Ipv4Packet<'trace>>>,
+    broken_nat: bool,
+    activity_sender: Sender<TraceResult>,
+}
+
 pub enum SocketSenders {
     V4(SocketSender<Ipv4Addr>),
     V6(SocketSender<Ipv6Addr>),
@@ -59,78 +74,193 @@ impl SocketSenders {
         }
     }
 
+    /// Send the probes of every request, sharing the rate limit fairly between them
+    ///
+    /// Requests take turns sending a single packet. Those waiting on their destination's budget
+    /// are skipped so they don't hold up the others.
     pub fn send(
         &mut self,
         packet_receiver: Receiver<TraceRequest<'_>>,
-        probe_sender: Sender<TraceSent>,
+        probe_sender: Sender<SentActivity>,
+        options: &SocketOptions,
         runnable: Arc<AtomicBool>,
+        stats: Arc<EngineStats>,
     ) -> Result<(), TracerouteError> {
+        let rate_limit = options.rate_limit;
+        let mut budget = TokenBucket::new(rate_limit.packets_per_second);
+        let mut destination_budgets = DestinationBudgets::new(rate_limit.per_destination);
+        // Requests with probes left to send, in turn order
+        let mut active: VecDeque<PendingRequest> = VecDeque::new();
+        // Once shut down new requests are turned away
+        let mut shut_down = false;
+
         while runnable.load(Ordering::SeqCst) {
-            let probe_request = match packet_receiver.try_recv() {
-                Ok(request) => request,
-                Err(TryRecvError::Empty) => {
-                    thread::sleep(self.get_delay());
-                    continue;
+            let mut accept = |request, active: &mut VecDeque<_>| -> Result<(), TracerouteError> {
+                if let Some(notice) = self.accept(request, active, &mut shut_down, &stats) {
+                    // The receiver forgets the probes it already has of cancelled traces
+                    probe_sender.send(notice)?;
                 }
-                Err(TryRecvError::Disconnected) => break,
+                Ok(())
             };
-
-            match probe_request {
-                TraceRequest::V4 {
-                    bundles,
-                    timeout,
-                    activity_sender,
-                } => {
-                    debug!(
-                        "Sender has received TraceRequest with {} packets",
-                        bundles.len()
-                    );
-                    let result: Result<Vec<ProbeSent>, TracerouteError> = bundles
-                        .into_iter()
-                        .map(|bundle| {
-                            let ProbeBundle { probe, packet } = bundle;
-
-                            let dest = packet.get_destination();
-
-                            thread::sleep(self.get_delay());
-
-                            self.send_packet(packet, dest)?;
-                            Ok(probe.sent())
-                        })
-                        .collect();
-
-                    debug!("Finished sending packet bundle");
-
-                    match result {
-                        Ok(probes) => {
-                            debug!("Probes to send {}", probes.len());
-                            let sent = TraceSent {
-                                probes,
-                                timeout,
-                                activity_sender,
-                            };
-                            probe_sender.send(sent)?;
-                        }
-                        Err(_err) => todo!("Received an error"),
+            // Only block while there is nothing else to do
+            if active.is_empty() {
+                match packet_receiver.recv_timeout(IDLE_TIMEOUT) {
+                    Ok(request) => accept(request, &mut active)?,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            loop {
+                match packet_receiver.try_recv() {
+                    Ok(request) => accept(request, &mut active)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        runnable.store(false, Ordering::SeqCst);
+                        break;
                     }
                 }
-                TraceRequest::V6 {
-                    bundles: _,
-                    timeout: _,
-                    activity_sender: _,
-                } => todo!(),
+            }
+
+            let now = Instant::now();
+            let wait = budget.wait(now);
+            if !wait.is_zero() {
+                thread::sleep(wait);
+                continue;
+            }
+
+            // First request in turn whose destination has budget left
+            let mut next_wait = Duration::MAX;
+            let position = active.iter().position(|pending| {
+                let wait = destination_budgets.wait(pending.destination, now);
+                next_wait = next_wait.min(wait);
+                wait.is_zero()
+            });
+            let mut pending = match position.and_then(|position| active.remove(position)) {
+                Some(pending) => pending,
+                None => {
+                    thread::sleep(next_wait.min(IDLE_TIMEOUT));
+                    continue;
+                }
+            };
+
+            let ProbeBundle {
+                probe,
+                packet,
+                timeout,
+            } = match pending.bundles.next() {
+                Some(bundle) => bundle,
+                None => continue,
+            };
+
+            budget.take();
+            destination_budgets.take(pending.destination);
+
+            // Stamp the probe first, replies over loopback can beat us back from the kernel
+            let sent = probe.sent();
+            options.captures.record(Direction::Sent, packet.packet());
+            if let Err(err) = self.send_packet(packet, pending.destination) {
+                warn!("Failed to send probe to {}: {}", pending.destination, err);
+                // The trace learns about the failure and the rest of its probes are dropped
+                let _ = pending.activity_sender.send(Err(err));
+                continue;
+            }
+            stats.probe_sent();
+            // If the trace is gone there isn't anything we can do about it here
+            let _ = pending.activity_sender.send(Ok(Activity::Sent(sent.clone())));
+
+            let sent = TraceSent {
+                trace_id: pending.trace_id,
+                probes: vec![sent],
+                timeout,
+                broken_nat: pending.broken_nat,
+                activity_sender: pending.activity_sender.clone(),
+            };
+            probe_sender.send(SentActivity::Sent(sent))?;
+
+            if pending.bundles.len() > 0 {
+                active.push_back(pending);
+            } else {
+                debug!("Finished sending probes to {}", pending.destination);
+                // Forget budgets of destinations no longer traced
+                destination_budgets.retain(|destination| {
+                    active.iter().any(|pending| pending.destination == destination)
+                });
             }
         }
         Ok(())
     }
 
-    fn get_delay(&self) -> Duration {
-        match *self {
-            Self::V4(ref socket) => socket.packet_delay,
-            Self::Both { ref v4, .. } => {
-                v4.packet_delay
+    // Queue the probes of a request for sending, or drop those of cancelled traces
+    //
+    // Returns what the receiver should be told about cancellations.
+    fn accept<'a>(
+        &self,
+        request: TraceRequest<'a>,
+        active: &mut VecDeque<PendingRequest<'a>>,
+        shut_down: &mut bool,
+        stats: &EngineStats,
+    ) -> Option<SentActivity> {
+        match request {
+            TraceRequest::V4 {
+                activity_sender, ..
+            }
+            | TraceRequest::V6 {
+                activity_sender, ..
+            } if *shut_down => {
+                // If the trace is gone there isn't anything we can do about it here
+                let _ = activity_sender.send(Err(TracerouteError::Cancelled));
+                None
+            }
+            TraceRequest::V4 {
+                trace_id,
+                bundles,
+                broken_nat,
+                activity_sender,
+            } => {
+                debug!("Sender has received TraceRequest with {} packets", bundles.len());
+                let destination = match bundles.first() {
+                    Some(bundle) => bundle.packet.get_destination(),
+                    None => return None,
+                };
+                active.push_back(PendingRequest {
+                    trace_id,
+                    destination,
+                    bundles: bundles.into_iter(),
+                    broken_nat,
+                    activity_sender,
+                });
+                None
+            }
+            TraceRequest::V6 {
+                activity_sender, ..
+            } => {
+                // If the trace is gone there isn't anything we can do about it here
+                let _ = activity_sender.send(Err(TracerouteError::NoIpv6));
+                None
+            }
+            TraceRequest::Cancel { trace_id } => {
+                debug!("Sender is cancelling trace {}", trace_id);
+                active.retain(|pending| {
+                    if pending.trace_id != trace_id {
+                        return true;
+                    }
+                    stats.probes_cancelled(pending.bundles.len());
+                    // If the trace is gone there isn't anything we can do about it here
+                    let _ = pending.activity_sender.send(Ok(Activity::Cancelled));
+                    false
+                });
+                Some(SentActivity::Cancel(trace_id))
+            }
+            TraceRequest::Shutdown => {
+                debug!("Sender is shutting down {} traces", active.len());
+                for pending in active.drain(..) {
+                    stats.probes_cancelled(pending.bundles.len());
+                    // If the trace is gone there isn't anything we can do about it here
+                    let _ = pending.activity_sender.send(Ok(Activity::Cancelled));
+                }
+                *shut_down = true;
+                Some(SentActivity::Shutdown)
             }
-            Self::V6(ref socket) => socket.packet_delay,
         }
     }
 }
@@ -0,0 +1,47 @@
+use crate::trace::TraceRequest;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+/// Shut down a [`Traceroute`](crate::Traceroute) engine from anywhere, such as a signal handler
+///
+/// Every trace is cancelled as by [`Trace::cancel`](crate::Trace::cancel) and new traces fail
+/// with [`TracerouteError::Cancelled`](crate::TracerouteError::Cancelled). The network threads
+/// keep running until the engine is closed.
+///
+/// A [`Yarrp`](crate::Yarrp) run handed one stops sending and returns what it has.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    target: Target,
+}
+
+#[derive(Clone, Debug)]
+enum Target {
+    Engine(Sender<TraceRequest<'static>>),
+    Flag(Arc<AtomicBool>),
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(packet_sender: Sender<TraceRequest<'static>>) -> Self {
+        Self {
+            target: Target::Engine(packet_sender),
+        }
+    }
+
+    // Shut down by raising a flag the holder checks
+    pub(crate) fn flag(flag: Arc<AtomicBool>) -> Self {
+        Self {
+            target: Target::Flag(flag),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        match &self.target {
+            // The engine is already closed when nobody is listening
+            Target::Engine(packet_sender) => {
+                let _ = packet_sender.send(TraceRequest::Shutdown);
+            }
+            Target::Flag(flag) => flag.store(true, Ordering::SeqCst),
+        }
+    }
+}
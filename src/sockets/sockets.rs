@@ -1,16 +1,16 @@
-use super::{SocketReceiver, SocketReceivers, SocketSender, SocketSenders};
+use super::{
+    Backend, Bound, EngineStats, ShutdownHandle, SocketOptions, SocketReceiver, SocketReceivers,
+    SocketSender, SocketSenders,
+};
 use crate::TracerouteError;
 use crate::trace::TraceRequest;
-use crate::utils::get_default_source_ip;
 use core::sync::atomic::{AtomicBool, Ordering};
-use pnet::packet::ip::IpNextHeaderProtocols;
 use pnet::transport::TransportChannelType::Layer3;
 use pnet::transport::transport_channel;
 use std::any::Any;
 use std::net::{IpAddr, Ipv6Addr};
 use std::sync::Arc;
 use std::sync::mpsc::{Sender, channel};
-use std::time::Duration;
 use std::thread::{self, JoinHandle};
 
 pub type SocketJoinResult = Vec<Result<Result<(), TracerouteError>, Box<dyn Any + Send>>>;
@@ -18,39 +18,48 @@ pub type SocketJoinResult = Vec<Result<Result<(), TracerouteError>, Box<dyn Any
 /// Creates network sockets to handle egress and ingress packets
 pub struct Sockets {
     addresses: Vec<IpAddr>,
+    bound: Bound,
     send_handle: Option<JoinHandle<Result<(), TracerouteError>>>,
     receive_handle: Option<JoinHandle<Result<(), TracerouteError>>>,
     runnable: Arc<AtomicBool>,
     packet_sender: Sender<TraceRequest<'static>>,
+    stats: Arc<EngineStats>,
 }
 
 impl Sockets {
-    pub fn new(packet_delay: Duration) -> Result<Self, TracerouteError> {
+    pub(crate) fn new(options: SocketOptions) -> Result<Self, TracerouteError> {
         let runnable = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(EngineStats::default());
 
-        let (mut tx, mut rx) = Self::setup_sockets(packet_delay)?;
+        let bound = options.binding.resolve()?;
+        let (mut tx, mut rx) = Self::setup_sockets(&options, &bound)?;
         let addresses = tx.addresses();
         let (packet_sender, packet_receiver) = channel();
         let (probe_sender, probe_receiver) = channel();
 
         let run = runnable.clone();
+        let send_stats = stats.clone();
+        let send_options = options.clone();
         let send_handle = thread::Builder::new()
             .name("send".to_string())
-            .spawn(move || tx.send(packet_receiver, probe_sender, run))
+            .spawn(move || tx.send(packet_receiver, probe_sender, &send_options, run, send_stats))
             .map_err(TracerouteError::Io)?;
 
         let run = runnable.clone();
+        let receive_stats = stats.clone();
         let receive_handle = thread::Builder::new()
             .name("receive".to_string())
-            .spawn(move || rx.receive(probe_receiver, run))
+            .spawn(move || rx.receive(probe_receiver, &options, run, receive_stats))
             .map_err(TracerouteError::Io)?;
 
         Ok(Self {
             addresses,
+            bound,
             send_handle: Some(send_handle),
             receive_handle: Some(receive_handle),
             runnable,
             packet_sender,
+            stats,
         })
     }
 
@@ -58,21 +67,48 @@ impl Sockets {
         &self.addresses
     }
 
+    pub(crate) fn bound(&self) -> &Bound {
+        &self.bound
+    }
+
+    pub fn stats(&self) -> Arc<EngineStats> {
+        self.stats.clone()
+    }
+
     pub fn packet_sender(&self) -> Sender<TraceRequest<'static>> {
         self.packet_sender.clone()
     }
 
-    fn setup_sockets(packet_delay: Duration) -> Result<(SocketSenders, SocketReceivers), TracerouteError> {
-        let ipv4_source = get_default_source_ip()?;
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.packet_sender())
+    }
+
+    fn setup_sockets(
+        options: &SocketOptions,
+        bound: &Bound,
+    ) -> Result<(SocketSenders, SocketReceivers), TracerouteError> {
+        // Raw sockets are the only backend for now
+        let Backend::RawSocket = options.backend;
 
-        // Set the protocol we are looking to recieve
-        let protocol = Layer3(IpNextHeaderProtocols::Icmp);
-        let mb_v4socket = transport_channel(4096, protocol).map(|(tx, rx)| {
-            (
-                SocketSender::new(vec![ipv4_source], tx, packet_delay),
-                SocketReceiver::new(rx),
-            )
-        });
+        // A socket for each protocol we are looking to recieve, probes go out the first one
+        let mut channels = Vec::new();
+        for protocol in &options.listen {
+            let (tx, rx) = transport_channel(options.buffer_size, Layer3(*protocol))?;
+            bound.apply(&tx)?;
+            channels.push((tx, rx));
+        }
+        let mut channels = channels.into_iter();
+        let mb_v4socket = match channels.next() {
+            Some((tx, rx)) => {
+                let mut receivers = vec![rx];
+                receivers.extend(channels.map(|(_tx, rx)| rx));
+                Ok((
+                    SocketSender::new(bound.addresses.clone(), tx),
+                    SocketReceiver::new(receivers),
+                ))
+            }
+            None => Err(TracerouteError::InvalidEngine("nothing to listen for")),
+        };
 
         let mb_v6socket: Result<(SocketSender<Ipv6Addr>, SocketReceiver), TracerouteError> =
             Err(TracerouteError::NoIpv6);
@@ -108,6 +144,8 @@ impl Sockets {
     /// This shouldn't happen but in the case it does there is a way to handle it from within
     /// the application logic and not here.
     pub fn close(&mut self) -> SocketJoinResult {
+        // Let traces still running know they won't hear back
+        self.shutdown_handle().shutdown();
         // Tell network loop to stop
         self.runnable.store(false, Ordering::SeqCst);
 
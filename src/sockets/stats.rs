@@ -0,0 +1,66 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters updated by the socket threads
+#[derive(Debug, Default)]
+pub struct EngineStats {
+    probes_sent: AtomicU64,
+    replies_matched: AtomicU64,
+    unmatched_packets: AtomicU64,
+    probes_expired: AtomicU64,
+    probes_cancelled: AtomicU64,
+    parse_errors: AtomicU64,
+}
+
+/// Point in time copy of the [`EngineStats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EngineCounters {
+    /// Probes handed to the network
+    pub probes_sent: u64,
+    /// Replies correlated to a probe
+    pub replies_matched: u64,
+    /// Packets received without a probe waiting for them
+    pub unmatched_packets: u64,
+    /// Probes which timed out without a reply
+    pub probes_expired: u64,
+    /// Probes dropped unsent or unanswered because their trace was cancelled
+    pub probes_cancelled: u64,
+    /// Packets which couldn't be parsed
+    pub parse_errors: u64,
+}
+
+impl EngineStats {
+    pub fn probe_sent(&self) {
+        self.probes_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reply_matched(&self) {
+        self.replies_matched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unmatched_packet(&self) {
+        self.unmatched_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn probe_expired(&self) {
+        self.probes_expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn probes_cancelled(&self, probes: usize) {
+        self.probes_cancelled.fetch_add(probes as u64, Ordering::Relaxed);
+    }
+
+    pub fn parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> EngineCounters {
+        EngineCounters {
+            probes_sent: self.probes_sent.load(Ordering::Relaxed),
+            replies_matched: self.replies_matched.load(Ordering::Relaxed),
+            unmatched_packets: self.unmatched_packets.load(Ordering::Relaxed),
+            probes_expired: self.probes_expired.load(Ordering::Relaxed),
+            probes_cancelled: self.probes_cancelled.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+        }
+    }
+}
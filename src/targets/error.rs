@@ -0,0 +1,27 @@
+use std::{error::Error, fmt};
+
+/// Represents an error which occurred whilst parsing a Target or Sampling.
+#[derive(Copy, Debug, PartialEq, Eq, Clone)]
+pub enum ParseTargetErr {
+    InvalidHost,
+    InvalidNetwork,
+    Ipv6Network,
+    NetworkTooLarge,
+    UnknownSampling,
+}
+
+impl Error for ParseTargetErr {}
+
+impl fmt::Display for ParseTargetErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseTargetErr::InvalidHost => write!(f, "Invalid Host"),
+            ParseTargetErr::InvalidNetwork => write!(f, "Invalid Network"),
+            ParseTargetErr::Ipv6Network => write!(f, "Ipv6 Networks aren't supported"),
+            ParseTargetErr::NetworkTooLarge => {
+                write!(f, "Networks larger than a /8 aren't supported")
+            }
+            ParseTargetErr::UnknownSampling => write!(f, "Unknown Sampling"),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod error;
+mod sampling;
+mod target;
+
+pub use error::ParseTargetErr;
+pub use sampling::Sampling;
+pub use target::{Target, read_targets};
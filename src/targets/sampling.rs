@@ -0,0 +1,73 @@
+use std::{fmt, str::FromStr};
+
+use super::ParseTargetErr;
+
+/// Addresses picked from each /24 of a network target
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Sampling {
+    /// The .1 address, usually the gateway
+    #[default]
+    First,
+    /// This many random host addresses
+    Random(u8),
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::First => write!(f, "first"),
+            Self::Random(count) => write!(f, "random:{}", count),
+        }
+    }
+}
+
+impl FromStr for Sampling {
+    type Err = ParseTargetErr;
+
+    /// ex: `first`, `random` or `random:4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sampling = match s.split_once(':') {
+            None if s.eq_ignore_ascii_case("first") => Self::First,
+            None if s.eq_ignore_ascii_case("random") => Self::Random(1),
+            Some((kind, count)) if kind.eq_ignore_ascii_case("random") => {
+                match count.parse::<u8>() {
+                    Ok(count) if count > 0 => Self::Random(count),
+                    _ => Err(ParseTargetErr::UnknownSampling)?,
+                }
+            }
+            _ => Err(ParseTargetErr::UnknownSampling)?,
+        };
+
+        Ok(sampling)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samplings_parse_and_display_alike() {
+        for (text, sampling) in [
+            ("first", Sampling::First),
+            ("random:1", Sampling::Random(1)),
+            ("random:255", Sampling::Random(255)),
+        ] {
+            assert_eq!(text.parse(), Ok(sampling));
+            assert_eq!(sampling.to_string(), text);
+        }
+        assert_eq!("RANDOM".parse(), Ok(Sampling::Random(1)));
+    }
+
+    #[test]
+    fn unknown_samplings_are_rejected() {
+        for text in ["", "last", "random:0", "random:256", "random:", "first:2"] {
+            assert_eq!(
+                text.parse::<Sampling>(),
+                Err(ParseTargetErr::UnknownSampling),
+                "{}",
+                text
+            );
+        }
+    }
+}
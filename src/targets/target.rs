@@ -0,0 +1,250 @@
+use std::io::{self, BufRead};
+use std::net::{IpAddr, Ipv4Addr};
+use std::{fmt, str::FromStr};
+
+use pnet::ipnetwork::{IpNetwork, Ipv4Network};
+use rand::seq::IteratorRandom;
+use url::Host;
+
+use super::{ParseTargetErr, Sampling};
+use crate::TracerouteError;
+
+// Keep a typo from expanding into millions of traces
+const MIN_PREFIX: u8 = 8;
+
+/// Something to trace towards
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// Hostname or single IP address
+    Host(Host),
+    /// Range of addresses sampled with a [`Sampling`]
+    Network(Ipv4Network),
+}
+
+impl Target {
+    /// Addresses of a network target picked per /24. Hosts have none until they are resolved
+    pub fn sample(&self, sampling: Sampling) -> Vec<IpAddr> {
+        let network = match self {
+            Self::Host(_host) => return Vec::new(),
+            Self::Network(network) => *network,
+        };
+
+        // Networks smaller than a /24 are sampled as a whole
+        let block_prefix = network.prefix().max(24);
+        let blocks = 1u32 << (block_prefix - network.prefix());
+        let block_size = 1u32 << (32 - block_prefix);
+        let base = u32::from(network.network());
+
+        let mut rng = rand::rng();
+        let mut addresses = Vec::new();
+        for block in 0..blocks {
+            let start = base + block * block_size;
+            // Inclusive so the block ending at 255.255.255.255 doesn't overflow
+            let end = start + (block_size - 1);
+            // Skip the network and broadcast address when there are others to pick from
+            let hosts = if block_size > 2 {
+                start + 1..=end - 1
+            } else {
+                start..=end
+            };
+
+            match sampling {
+                Sampling::First => addresses.push(*hosts.start()),
+                Sampling::Random(count) => {
+                    let mut picked = hosts.choose_multiple(&mut rng, count.into());
+                    picked.sort();
+                    addresses.extend(picked);
+                }
+            }
+        }
+
+        addresses
+            .into_iter()
+            .map(|address| IpAddr::V4(Ipv4Addr::from(address)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Host(host) => write!(f, "{}", host),
+            Self::Network(network) => write!(f, "{}", network),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = ParseTargetErr;
+
+    /// ex: `example.com`, `192.0.2.1` or `192.0.2.0/24`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') {
+            return match s.parse::<IpNetwork>() {
+                Ok(IpNetwork::V4(network)) if network.prefix() < MIN_PREFIX => {
+                    Err(ParseTargetErr::NetworkTooLarge)
+                }
+                Ok(IpNetwork::V4(network)) => Ok(Self::Network(network)),
+                Ok(IpNetwork::V6(_network)) => Err(ParseTargetErr::Ipv6Network),
+                Err(_err) => Err(ParseTargetErr::InvalidNetwork),
+            };
+        }
+
+        Host::parse(s)
+            .map(Self::Host)
+            .map_err(|_err| ParseTargetErr::InvalidHost)
+    }
+}
+
+/// Read one target per line. Blank lines and lines starting with `#` are skipped
+pub fn read_targets<R: BufRead>(reader: R) -> Result<Vec<Target>, TracerouteError> {
+    let mut targets = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.parse::<Target>() {
+            Ok(target) => targets.push(target),
+            Err(err) => {
+                let message = format!("Target on line {} \"{}\": {}", index + 1, line, err);
+                let err = io::Error::new(io::ErrorKind::InvalidData, message);
+                return Err(TracerouteError::Io(err));
+            }
+        }
+    }
+
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(s: &str) -> Target {
+        s.parse().expect("valid network")
+    }
+
+    fn addresses(addresses: &[[u8; 4]]) -> Vec<IpAddr> {
+        addresses
+            .iter()
+            .map(|octets| IpAddr::V4(Ipv4Addr::from(*octets)))
+            .collect()
+    }
+
+    #[test]
+    fn single_address_networks_are_traced_as_is() {
+        let target = network("192.0.2.7/32");
+        assert_eq!(target.sample(Sampling::First), addresses(&[[192, 0, 2, 7]]));
+        assert_eq!(
+            target.sample(Sampling::Random(4)),
+            addresses(&[[192, 0, 2, 7]])
+        );
+    }
+
+    #[test]
+    fn point_to_point_networks_keep_both_addresses() {
+        let target = network("192.0.2.6/31");
+        assert_eq!(target.sample(Sampling::First), addresses(&[[192, 0, 2, 6]]));
+        assert_eq!(
+            target.sample(Sampling::Random(2)),
+            addresses(&[[192, 0, 2, 6], [192, 0, 2, 7]])
+        );
+    }
+
+    #[test]
+    fn network_and_broadcast_addresses_are_skipped() {
+        let target = network("192.0.2.0/24");
+        assert_eq!(target.sample(Sampling::First), addresses(&[[192, 0, 2, 1]]));
+
+        let picked = target.sample(Sampling::Random(254));
+        assert_eq!(picked.len(), 254);
+        assert!(!picked.contains(&IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0))));
+        assert!(!picked.contains(&IpAddr::V4(Ipv4Addr::new(192, 0, 2, 255))));
+    }
+
+    #[test]
+    fn every_24_of_a_larger_network_is_sampled() {
+        let target = network("192.0.0.0/22");
+        assert_eq!(
+            target.sample(Sampling::First),
+            addresses(&[
+                [192, 0, 0, 1],
+                [192, 0, 1, 1],
+                [192, 0, 2, 1],
+                [192, 0, 3, 1]
+            ])
+        );
+        assert_eq!(target.sample(Sampling::Random(3)).len(), 12);
+    }
+
+    #[test]
+    fn the_top_of_the_address_space_is_sampled() {
+        let target = network("255.255.255.255/32");
+        assert_eq!(
+            target.sample(Sampling::First),
+            addresses(&[[255, 255, 255, 255]])
+        );
+
+        let target = network("255.255.255.0/24");
+        assert_eq!(
+            target.sample(Sampling::First),
+            addresses(&[[255, 255, 255, 1]])
+        );
+        assert_eq!(target.sample(Sampling::Random(254)).len(), 254);
+
+        let target = network("255.0.0.0/8");
+        let picked = target.sample(Sampling::First);
+        assert_eq!(picked.len(), 1 << 16);
+        assert_eq!(picked.last(), addresses(&[[255, 255, 255, 1]]).last());
+    }
+
+    #[test]
+    fn hosts_have_nothing_to_sample() {
+        let target = network("example.com");
+        assert_eq!(
+            target,
+            Target::Host(Host::Domain("example.com".to_string()))
+        );
+        assert!(target.sample(Sampling::First).is_empty());
+    }
+
+    #[test]
+    fn networks_which_cant_be_traced_are_rejected() {
+        assert_eq!(
+            "10.0.0.0/7".parse::<Target>(),
+            Err(ParseTargetErr::NetworkTooLarge)
+        );
+        assert_eq!(
+            "2001:db8::/64".parse::<Target>(),
+            Err(ParseTargetErr::Ipv6Network)
+        );
+        assert_eq!(
+            "192.0.2.0/33".parse::<Target>(),
+            Err(ParseTargetErr::InvalidNetwork)
+        );
+    }
+
+    #[test]
+    fn target_files_skip_blank_lines_and_comments() {
+        let file = "# gateways\n192.0.2.1\n\n  example.com  \n198.51.100.0/24\n";
+        let targets = read_targets(file.as_bytes()).expect("valid targets");
+        assert_eq!(
+            targets,
+            vec![
+                network("192.0.2.1"),
+                network("example.com"),
+                network("198.51.100.0/24"),
+            ]
+        );
+    }
+
+    #[test]
+    fn target_files_report_the_line_of_a_bad_target() {
+        let err = read_targets("192.0.2.1\n192.0.2.0/99\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+}
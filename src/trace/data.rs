@@ -1,20 +1,21 @@
 use std::fmt;
 use std::net::IpAddr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use log::*;
-use crate::prelude::Flowhash;
+use crate::alias::{AliasReport, AliasResolver};
+use crate::analysis::Anomaly;
+use crate::prelude::{Flowhash, TTL};
+use crate::traceroute::Traceroute;
 use crate::{Edge, Node};
-use crate::{TraceOptions, TracerouteError};
-use crate::probe::ProbeResponse;
-use crate::trace::{Trace, TraceResponse};
-use async_std::stream::StreamExt;
+use crate::{ExportOptions, TraceOptions, TracerouteError};
+use crate::probe::{HeaderChange, ProbeResponse};
+use crate::trace::{Trace, TraceFlow, TraceResponse, next_rounds};
 use std::collections::HashMap;
 
-use petgraph::dot::Dot;
 use petgraph::Direction::Outgoing;
 use petgraph::graphmap::DiGraphMap;
-type Graph = DiGraphMap<Node, Edge>;
+pub type Graph = DiGraphMap<Node, Edge>;
 
 /// Collect Trace data for visualizing to user
 pub struct TraceData {
@@ -23,6 +24,12 @@ pub struct TraceData {
     pings: HashMap<(IpAddr,IpAddr),Vec<Duration>>,
     // List of all flows we have seen
     flows: Vec<Flowhash>,
+    // Responses of every trace processed
+    responses: Vec<TraceFlow>,
+    // Reverse DNS names of the hops
+    hostnames: HashMap<IpAddr, String>,
+    // Interfaces merged into routers
+    aliases: Option<AliasReport>,
     // All endpoints placed into a graph
     graph: Graph,
 }
@@ -32,97 +39,274 @@ impl TraceData {
         let graph = Graph::new();
         let pings = HashMap::new();
         let flows = Vec::new();
+        let responses = Vec::new();
+        let hostnames = HashMap::new();
         Self {
             options,
             pings,
             flows,
+            responses,
+            hostnames,
+            aliases: None,
             graph,
         }
     }
 
+    /// Reverse DNS name of a hop if it was resolved
+    pub fn hostname(&self, ip: &IpAddr) -> Option<&String> {
+        self.hostnames.get(ip)
+    }
+
+    /// Look up the reverse DNS name of every hop which replied
+    ///
+    /// Lookups are done one after another and block until each answers or fails. Hops without a
+    /// name are skipped.
+    pub fn resolve_hostnames(&mut self) {
+        let start = Instant::now();
+
+        let nodes = self.graph.nodes().collect::<Vec<Node>>();
+        for node in nodes {
+            let ip = match node {
+                Node::Hop(ip) => ip,
+                _ => continue,
+            };
+            if self.hostnames.contains_key(&ip) {
+                continue;
+            }
+            match resolve::resolve_addr(&ip) {
+                Ok(hostname) => {
+                    let _ = self.hostnames.insert(ip, hostname);
+                }
+                Err(err) => debug!("Reverse DNS for {} failed: {}", ip, err),
+            }
+        }
+
+        info!(
+            "Reverse DNS resolution took {:.3?} and found {} names",
+            Instant::now().duration_since(start),
+            self.hostnames.len()
+        );
+    }
+
+    /// Routers the interfaces were merged into if aliases were resolved
+    pub fn aliases(&self) -> Option<&AliasReport> {
+        self.aliases.as_ref()
+    }
+
+    /// Routing loops, rate limited hops and other oddities found in the responses
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        Anomaly::detect(&self.responses)
+    }
+
+    /// Every interface which replied with the closest distance it was seen at
+    pub fn interfaces(&self) -> Vec<(TTL, IpAddr)> {
+        let mut closest: HashMap<IpAddr, TTL> = HashMap::new();
+        let received = self
+            .responses
+            .iter()
+            .flat_map(|trace_flow| trace_flow.responses.iter())
+            .filter_map(|response| match response {
+                TraceResponse::Received(resp) => Some(resp),
+                _ => None,
+            });
+        for resp in received {
+            let ttl = closest.entry(resp.destination).or_insert(resp.ttl);
+            *ttl = resp.ttl.min(*ttl);
+        }
+
+        let mut interfaces = closest
+            .into_iter()
+            .map(|(ip, ttl)| (ttl, ip))
+            .collect::<Vec<_>>();
+        interfaces.sort();
+        interfaces
+    }
+
+    /// Probe the interfaces which replied to find the ones belonging to the same router
+    pub async fn resolve_aliases(
+        &mut self,
+        traceroute: &Traceroute,
+        resolver: &mut AliasResolver,
+    ) -> Result<(), TracerouteError> {
+        let interfaces = self.interfaces();
+        let report = resolver.resolve(traceroute, &interfaces, self.options).await?;
+        self.aliases = Some(report);
+        Ok(())
+    }
+
+    /// Options the traces were run with
+    pub fn options(&self) -> &TraceOptions {
+        &self.options
+    }
+
+    /// Responses of every trace processed so far
+    pub fn flows(&self) -> &Vec<TraceFlow> {
+        &self.responses
+    }
+
+    /// Graph of every node seen
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// Run a round of every trace at the same time and add the results
     pub async fn process(&mut self, mut traces: Vec<Trace>) -> Result<(), TracerouteError> {
+        let rounds = next_rounds(&mut traces).await;
+
+        // Keep the rounds which completed even when others failed
+        let mut result = Ok(());
+        for (trace, round) in traces.iter().zip(rounds) {
+            let responses = match round {
+                Some(Ok(responses)) => responses,
+                Some(Err(err)) => {
+                    result = result.and(Err(err));
+                    continue;
+                }
+                None => continue,
+            };
+
+            self.add_flow(trace.source(), trace.destination(), &responses);
+        }
+
+        result
+    }
+
+    /// Add the responses of a round of a flow
+    pub fn add_flow(&mut self, source: IpAddr, destination: IpAddr, responses: &[TraceResponse]) {
+        self.add_responses(source, destination, responses, true);
+    }
+
+    /// Add the responses of a flow which only probed from a hop already in the graph onwards
+    ///
+    /// The first hop is not connected to the source as the path leading up to it is known from
+    /// other flows.
+    pub fn join_flow(&mut self, source: IpAddr, destination: IpAddr, responses: &[TraceResponse]) {
+        self.add_responses(source, destination, responses, false);
+    }
+
+    fn add_responses(
+        &mut self,
+        source: IpAddr,
+        destination: IpAddr,
+        responses: &[TraceResponse],
+        from_source: bool,
+    ) {
         let track_flows = !self.options.dot;
 
-        for trace in &mut traces {
-            let responses = match StreamExt::next(trace).await {
-                Some(result) => result,
-                None => continue,
-            }?;
+        self.record_flow(source, destination, responses, !from_source);
 
-            let iter = responses.iter();
+        let iter = responses.iter();
 
-            // Copy iter for peaking values
-            let mut peek_iter = iter.clone();
+        // Copy iter for peaking values
+        let mut peek_iter = iter.clone();
 
-            // Get first response to create some common assets
-            let peek_first = loop {
-                if let Some(resp) = peek_iter.next() {
-                    match resp {
-                        TraceResponse::Masked(_ttl) => continue,
-                        TraceResponse::TimedOut(_sent) => continue,
-                        TraceResponse::Received(resp) => break Some(resp.clone()),
-                    }
+        // Get first response to create some common assets
+        let peek_first = loop {
+            if let Some(resp) = peek_iter.next() {
+                match resp {
+                    TraceResponse::Masked(_ttl) => continue,
+                    TraceResponse::TimedOut(_sent) => continue,
+                    TraceResponse::Received(resp) => break Some(resp.clone()),
                 }
+            }
 
-                break None;
-            };
+            break None;
+        };
 
-            let peek_resp = match peek_first {
-                Some(resp) => resp,
-                None => return Ok(()),
-            };
+        // Nothing replied so there is nothing to place in the graph
+        let peek_resp = match peek_first {
+            Some(resp) => resp,
+            None => return,
+        };
 
-            let source = peek_resp.sent.source;
-            let flowhash = peek_resp.sent.flowhash;
+        let source = peek_resp.sent.source;
+        let flowhash = peek_resp.sent.flowhash;
 
-            let source_node = Node::Hop(source);
-            let flow_node = Node::Flow(flowhash);
+        let source_node = Node::Hop(source);
+        let flow_node = Node::Flow(flowhash);
 
-            if track_flows {
-                self.flows.push(flowhash);
+        if track_flows {
+            self.flows.push(flowhash);
 
-                self.graph.add_node(flow_node);
-                self.graph.add_edge(flow_node, source_node, Edge::TTL(0));
-            }
+            self.graph.add_node(flow_node);
+            self.graph.add_edge(flow_node, source_node, Edge::TTL(0));
+        }
 
-            let mut prev_node = source_node;
+        // Joined flows start their lineage at the first hop
+        let mut prev_node = from_source.then_some(source_node);
 
-            for response in iter {
-                prev_node = match response {
-                    TraceResponse::Masked(ttl) => {
-                        let ttl = ttl.clone();
-                        let new_node = Node::Masked(ttl);
+        for response in iter {
+            let new_node = match response {
+                TraceResponse::Masked(ttl) => {
+                    let ttl = ttl.clone();
+                    let new_node = Node::Masked(ttl);
 
-                        if track_flows {
-                            // connect node to flow
-                            self.graph.add_edge(flow_node, new_node, Edge::TTL(ttl));
-                        }
+                    if track_flows {
+                        // connect node to flow
+                        self.graph.add_edge(flow_node, new_node, Edge::TTL(ttl));
+                    }
 
-                        // connect prev node to create lineage
+                    // connect prev node to create lineage
+                    if let Some(prev_node) = prev_node {
                         self.graph.add_edge(prev_node, new_node, Edge::Connected);
-                        new_node
-                    },
-                    TraceResponse::TimedOut(sent) => {
-                        let ttl = sent.ttl.clone();
-                        let new_node = Node::Hidden(ttl);
-
-                        if track_flows {
-                            // connect node to flow
-                            self.graph.add_edge(flow_node, new_node, Edge::TTL(ttl));
-                        }
-                        // connect prev node to create lineage
+                    }
+                    new_node
+                },
+                TraceResponse::TimedOut(sent) => {
+                    let ttl = sent.ttl.clone();
+                    let new_node = Node::hidden(source, flowhash, responses, ttl);
+
+                    if track_flows {
+                        // connect node to flow
+                        self.graph.add_edge(flow_node, new_node, Edge::TTL(ttl));
+                    }
+                    // connect prev node to create lineage
+                    if let Some(prev_node) = prev_node {
                         self.graph.add_edge(prev_node, new_node, Edge::Connected);
-                        new_node
                     }
-                    TraceResponse::Received(resp) => self.handle_received(resp, prev_node, flow_node),
-                };
-            }
+                    new_node
+                }
+                TraceResponse::Received(resp) => self.handle_received(resp, prev_node, flow_node),
+            };
+            prev_node = Some(new_node);
         }
+    }
 
-        Ok(())
+    // Keep the raw responses around for exporters
+    fn record_flow(
+        &mut self,
+        source: IpAddr,
+        destination: IpAddr,
+        responses: &[TraceResponse],
+        joined: bool,
+    ) {
+        let flowhash = responses.iter().find_map(|response| match response {
+            TraceResponse::Received(resp) => Some(resp.sent.flowhash),
+            TraceResponse::TimedOut(sent) => Some(sent.flowhash),
+            TraceResponse::Masked(_ttl) => None,
+        });
+
+        // Nothing was sent so there is no flow to speak of
+        let flowhash = match flowhash {
+            Some(flowhash) => flowhash,
+            None => return,
+        };
+
+        self.responses.push(TraceFlow {
+            flowhash,
+            source,
+            destination,
+            responses: responses.to_vec(),
+            joined,
+        });
     }
 
-    fn handle_received(&mut self, resp: &ProbeResponse, prev_node: Node, flow_node: Node) -> Node {
+    fn handle_received(
+        &mut self,
+        resp: &ProbeResponse,
+        prev_node: Option<Node>,
+        flow_node: Node,
+    ) -> Node {
         let track_flows = !self.options.dot;
 
         let ttl = resp.ttl;
@@ -138,16 +322,15 @@ impl TraceData {
         let new_node = self.graph.add_node(Node::Hop(destination));
 
         if track_flows {
-            // Maybe not a good idea. Can merge exact same ping times. Even if unlikely.
-            self.graph.add_edge(flow_node, new_node, Edge::RTT(ping));
-
             // connect node to flow
             self.graph.add_edge(flow_node, new_node, Edge::TTL(ttl));
         }
 
         // connect prev node to create lineage
-        if prev_node != new_node {
-          self.graph.add_edge(prev_node, new_node, Edge::Connected);
+        if let Some(prev_node) = prev_node
+            && prev_node != new_node
+        {
+            self.graph.add_edge(prev_node, new_node, Edge::Connected);
         }
 
         // Pass new node back to become the new prev_node
@@ -191,12 +374,42 @@ impl fmt::Display for TraceData {
                 self.graph.edge_count()
             );
 
-            return write!(f, "{}", Dot::new(&self.graph))
+            return write!(f, "{}", self.to_dot(&ExportOptions::default()))
+        }
+
+        // Otherwise list each flow, headed by its target when there are several
+        for (index, flow) in self.flows.iter().enumerate() {
+            if self.flows.len() > 1 {
+                if index > 0 {
+                    writeln!(f)?;
+                }
+                let destination = self
+                    .responses
+                    .iter()
+                    .find(|trace_flow| trace_flow.flowhash == *flow)
+                    .map(|trace_flow| trace_flow.destination);
+                if let Some(destination) = destination {
+                    writeln!(f, "{}", destination)?;
+                }
+            }
+            self.fmt_flow(f, *flow)?;
         }
 
-        // Otherwise print the first flow
-        let flow = self.flows[0];
+        if let Some(aliases) = &self.aliases {
+            write!(f, "{}", aliases)?;
+        }
+
+        for anomaly in self.anomalies() {
+            writeln!(f, "Warning: {}", anomaly)?;
+        }
 
+        Ok(())
+    }
+}
+
+impl TraceData {
+    // Typical traceroute listing of a single flow
+    fn fmt_flow(&self, f: &mut fmt::Formatter, flow: Flowhash) -> fmt::Result {
         let edges = self.graph.edges_directed(Node::Flow(flow), Outgoing);
         let mut edges = edges.collect::<Vec<_>>();
 
@@ -213,7 +426,7 @@ impl fmt::Display for TraceData {
         loop {
             let pop = edges.pop_if(|(_,node,_)| {
                 match node {
-                    Node::Hidden(_) => true,
+                    Node::Hidden(..) => true,
                     _ => false,
                 }
             });
@@ -236,13 +449,116 @@ impl fmt::Display for TraceData {
                     .join(" ");
 
             if let Edge::TTL(ttl) = edge {
-                match writeln!(f, "{:>2}. {:<15} {}", ttl, replier, formatted_durations) {
+                let notes = self.hop_notes(flow, *ttl);
+                match writeln!(f, "{:>2}. {:<15} {}{}", ttl, replier, formatted_durations, notes) {
                     Ok(_) => continue,
                     Err(err) => return Err(err),
                 }
             }
         }
 
+        if let Some(trace_flow) = self.latest_flow(flow) {
+            for middlebox in trace_flow.middleboxes() {
+                writeln!(f, "{}", middlebox)?;
+            }
+        }
+
         Ok(())
     }
+
+    // Latest round of a flow
+    fn latest_flow(&self, flow: Flowhash) -> Option<&TraceFlow> {
+        self.responses
+            .iter()
+            .rev()
+            .find(|trace_flow| trace_flow.flowhash == flow)
+    }
+
+    // Note the headers the probe at a distance arrived with changed and a return path of
+    // another length
+    fn hop_notes(&self, flow: Flowhash, ttl: TTL) -> String {
+        let response = self
+            .latest_flow(flow)
+            .into_iter()
+            .flat_map(|trace_flow| trace_flow.responses.iter())
+            .find_map(|response| match response {
+                TraceResponse::Received(resp) if resp.ttl == ttl => Some(resp),
+                _ => None,
+            });
+
+        let resp = match response {
+            Some(resp) => resp,
+            None => return String::new(),
+        };
+
+        let mut notes = resp
+            .modifications
+            .iter()
+            .map(HeaderChange::to_string)
+            .collect::<Vec<String>>();
+        if let (Some(_asymmetry), Some(return_hops)) = (resp.asymmetry(), resp.return_hops()) {
+            notes.push(format!("returned over {} hops", return_hops));
+        }
+
+        match notes.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", notes.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::fake;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    // Hops of a flow as (ttl, last octet of the replying address). Timeouts have no address
+    fn round(flowhash: Flowhash, hops: &[(TTL, Option<u8>)]) -> Vec<TraceResponse> {
+        hops.iter()
+            .map(|(ttl, address)| {
+                let mut sent = fake::sent(*ttl);
+                sent.flowhash = flowhash;
+                match address {
+                    Some(last) => TraceResponse::Received(ProbeResponse {
+                        sent,
+                        ..fake::received(*ttl, ip(*last), Duration::from_millis(1))
+                    }),
+                    None => TraceResponse::TimedOut(sent),
+                }
+            })
+            .collect()
+    }
+
+    fn hidden_nodes(data: &TraceData) -> usize {
+        data.graph()
+            .nodes()
+            .filter(|node| matches!(node, Node::Hidden(..)))
+            .count()
+    }
+
+    #[test]
+    fn flows_share_hidden_hops_between_the_same_neighbours() {
+        let mut data = TraceData::new(TraceOptions::default());
+        let path = [(1, Some(1)), (2, None), (3, Some(3))];
+        data.add_flow(fake::SOURCE, ip(200), &round(1, &path));
+        data.add_flow(fake::SOURCE, ip(200), &round(2, &path));
+        assert_eq!(hidden_nodes(&data), 1);
+
+        let diverging = [(1, Some(1)), (2, None), (3, Some(13))];
+        data.add_flow(fake::SOURCE, ip(200), &round(3, &diverging));
+        assert_eq!(hidden_nodes(&data), 2);
+    }
+
+    #[test]
+    fn flows_keep_their_own_trailing_hidden_hops() {
+        let mut data = TraceData::new(TraceOptions::default());
+        data.add_flow(fake::SOURCE, ip(200), &round(1, &[(1, Some(1)), (2, None)]));
+        data.add_flow(fake::SOURCE, ip(200), &round(2, &[(1, Some(1)), (2, None)]));
+        assert_eq!(hidden_nodes(&data), 2);
+    }
 }
@@ -0,0 +1,127 @@
+use async_std::{
+    pin::Pin,
+    stream::Stream,
+    task::{Context, Poll},
+};
+
+use super::{Trace, TraceResponse};
+use crate::TracerouteError;
+use crate::prelude::Flowhash;
+use crate::probe::{ProbeResponse, ProbeSent};
+
+/// What the network threads tell a trace about its probes
+#[derive(Debug)]
+pub enum Activity {
+    /// The probe was handed to the network
+    Sent(ProbeSent),
+    /// The probe got its outcome
+    Response(TraceResponse),
+    /// The engine dropped the probes of the trace still in flight
+    Cancelled,
+}
+
+/// Something which happened to a trace, as it happens
+///
+/// Every event carries the round it belongs to, counting from 1, and the flowhash of the trace.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "event", rename_all = "snake_case"))]
+pub enum HopEvent {
+    /// A probe was handed to the network
+    ProbeSent {
+        round: usize,
+        flowhash: Flowhash,
+        sent: ProbeSent,
+    },
+    /// A hop along the way answered a probe
+    HopReplied {
+        round: usize,
+        flowhash: Flowhash,
+        response: ProbeResponse,
+    },
+    /// A probe got no answer in time and another is sent in its place
+    HopRetried {
+        round: usize,
+        flowhash: Flowhash,
+        sent: ProbeSent,
+        /// Attempt the probe sent in its place is, counting from 1
+        attempt: u8,
+    },
+    /// A probe got no answer in time and there are no attempts left
+    HopTimedOut {
+        round: usize,
+        flowhash: Flowhash,
+        sent: ProbeSent,
+    },
+    /// The destination itself answered a probe
+    DestinationReached {
+        round: usize,
+        flowhash: Flowhash,
+        response: ProbeResponse,
+    },
+    /// Every probe of the round has been accounted for
+    RoundComplete {
+        round: usize,
+        flowhash: Flowhash,
+        /// Outcome of every distance probed, ordered by distance
+        responses: Vec<TraceResponse>,
+    },
+}
+
+impl HopEvent {
+    pub fn round(&self) -> usize {
+        match self {
+            Self::ProbeSent { round, .. }
+            | Self::HopReplied { round, .. }
+            | Self::HopRetried { round, .. }
+            | Self::HopTimedOut { round, .. }
+            | Self::DestinationReached { round, .. }
+            | Self::RoundComplete { round, .. } => *round,
+        }
+    }
+
+    pub fn flowhash(&self) -> Flowhash {
+        match self {
+            Self::ProbeSent { flowhash, .. }
+            | Self::HopReplied { flowhash, .. }
+            | Self::HopRetried { flowhash, .. }
+            | Self::HopTimedOut { flowhash, .. }
+            | Self::DestinationReached { flowhash, .. }
+            | Self::RoundComplete { flowhash, .. } => *flowhash,
+        }
+    }
+}
+
+/// Stream of the [`HopEvent`]s of a trace, see [`Trace::events`]
+///
+/// Rounds start one after the other for as long as the stream is polled, it ends once the trace
+/// is cancelled and the round in flight completed.
+#[derive(Debug)]
+pub struct HopEvents<'trace> {
+    pub(super) trace: &'trace mut Trace,
+}
+
+impl HopEvents<'_> {
+    /// Cancel the trace, see [`Trace::cancel`]
+    pub fn cancel(&mut self) {
+        self.trace.cancel();
+    }
+}
+
+impl Stream for HopEvents<'_> {
+    type Item = Result<HopEvent, TracerouteError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let trace = &mut self.get_mut().trace;
+        let result = match trace.next_event() {
+            Some(result) => result,
+            None if trace.is_finished() => return Poll::Ready(None),
+            None => {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        };
+        cx.waker().wake_by_ref();
+        Poll::Ready(Some(result))
+    }
+}
@@ -0,0 +1,30 @@
+use std::net::IpAddr;
+
+use crate::middlebox::Middlebox;
+use crate::prelude::Flowhash;
+use crate::trace::TraceResponse;
+
+/// All responses of the latest round of a single flow
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceFlow {
+    /// Flowhash shared by every probe of the flow
+    pub flowhash: Flowhash,
+    /// IP the probes were sent from
+    pub source: IpAddr,
+    /// IP the probes were sent to
+    pub destination: IpAddr,
+    /// Responses ordered by TTL
+    pub responses: Vec<TraceResponse>,
+    /// Probing started part way along the path at a hop other flows already reached, so the
+    /// first response isn't connected to the source
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub joined: bool,
+}
+
+impl TraceFlow {
+    /// Boxes along the flow which changed the probes
+    pub fn middleboxes(&self) -> Vec<Middlebox> {
+        Middlebox::infer(&self.responses)
+    }
+}
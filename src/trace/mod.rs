@@ -1,17 +1,24 @@
+mod event;
+mod flow;
 mod options;
 mod request;
 mod response;
+mod rtt;
 mod sent;
 mod trace;
 mod data;
 
 use crate::TracerouteError;
 
-pub type TraceResult = Result<TraceResponse, TracerouteError>;
+pub type TraceResult = Result<Activity, TracerouteError>;
 
-pub use options::TraceOptions;
+pub use event::Activity;
+pub use event::{HopEvent, HopEvents};
+pub use flow::TraceFlow;
+pub use options::{DEFAULT_PROBE_SIZE, MIN_BROKEN_NAT_SIZE, MIN_PROBE_SIZE, TraceOptions};
 pub use request::TraceRequest;
 pub use response::TraceResponse;
-pub use sent::TraceSent;
-pub use trace::Trace;
+pub use rtt::RttEstimate;
+pub use sent::{SentActivity, TraceSent};
+pub use trace::{Trace, next_rounds};
 pub use data::TraceData;
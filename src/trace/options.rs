@@ -1,7 +1,21 @@
-use crate::protocol::Protocol;
+use crate::packet::Payload;
+use crate::protocol::{PortStrategy, Protocol};
+
+/// Probe length the traces have always used. A 20 byte IP header, 8 byte UDP header and 24
+/// byte payload
+pub const DEFAULT_PROBE_SIZE: u16 = 52;
+
+/// Smallest probe which fits the IP and UDP headers
+pub const MIN_PROBE_SIZE: u16 = 28;
+
+/// Smallest probe with the two payload bytes [`broken_nat`](TraceOptions::broken_nat) rewrites
+/// to make the checksum unique to each probe
+pub const MIN_BROKEN_NAT_SIZE: u16 = MIN_PROBE_SIZE + 2;
 
 /// Contains configuration parameters
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct TraceOptions {
     /// The minimum TTL to probe
     /// 0 is invalid as it would drop before leaving the originating machine
@@ -10,14 +24,39 @@ pub struct TraceOptions {
     pub max_ttl: u8,
     /// The inter-packet delay in milliseconds
     pub delay: u16,
-    /// The probe response timeout in milliseconds
+    /// The probe response timeout in milliseconds. With adaptive timeouts it is only waited on
+    /// until some hop answers
     pub timeout: u16,
+    /// Wait on each hop as long as its earlier replies suggest instead of the fixed timeout. Hops
+    /// which haven't replied go by the closest hop which has
+    pub adaptive_timeout: bool,
+    /// Probes sent to a hop before it is reported as timed out. Each retry waits twice as long
+    /// as the one before
+    pub attempts: u8,
+    /// How long the whole trace may take in milliseconds, counted from its first round. Once
+    /// passed the round in flight is cancelled and no new rounds start
+    pub deadline: Option<u32>,
     /// TTLs to skip probing. ex: skip closest known hosts
     pub mask: [bool; 32],
     /// Protocol to use for tracing
     pub protocol: Protocol,
     /// Output in dot format
     pub dot: bool,
+    /// Total length in bytes of each probe including the IP header
+    pub size: u16,
+    /// Set the Don't Fragment flag so routers answer oversized probes with Fragmentation Needed
+    pub dont_fragment: bool,
+    /// Pattern repeated over the probe payload
+    pub payload: Payload,
+    /// Differentiated services codepoint marking the probes. 0 - 63
+    pub dscp: u8,
+    /// Explicit congestion notification codepoint marking the probes. 0 - 3
+    pub ecn: u8,
+    /// A NAT on the path rewrites the IP id without restoring it in the quoted probe. Replies are
+    /// matched by the UDP checksum instead, which is fixed up to equal the IP id
+    pub broken_nat: bool,
+    /// How the ports of UDP probes change between probes and flows
+    pub ports: PortStrategy,
 }
 
 impl TraceOptions {
@@ -50,6 +89,11 @@ impl TraceOptions {
             .collect()
     }
 
+    /// Type of service byte the probes carry
+    pub fn tos(&self) -> u8 {
+        (self.dscp << 2) | (self.ecn & 0b11)
+    }
+
     pub fn mask(&mut self, ttl: u8) {
         self.mask[ttl as usize] = true;
     }
@@ -62,9 +106,19 @@ impl Default for TraceOptions {
             max_ttl: 32,
             delay: 10,
             timeout: 300,
+            adaptive_timeout: false,
+            attempts: 1,
+            deadline: None,
             mask: [false; 32],
             protocol: Protocol::default(),
             dot: false,
+            size: DEFAULT_PROBE_SIZE,
+            dont_fragment: false,
+            payload: Payload::default(),
+            dscp: 0,
+            ecn: 0,
+            broken_nat: false,
+            ports: PortStrategy::Fixed,
         }
     }
 }
@@ -1,21 +1,29 @@
+use crate::prelude::TraceId;
 use crate::probe::ProbeBundle;
 use crate::trace::TraceResult;
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv6::Ipv6Packet;
 use std::sync::mpsc::Sender;
-use std::time::Duration;
 
 /// Artifact created by [`Trace`](crate::trace::Trace) with the packets it wants to have sent and a channel to receive
 /// the [`TraceResult`]'s
 pub enum TraceRequest<'trace> {
     V4 {
+        trace_id: TraceId,
         bundles: Vec<ProbeBundle<Ipv4Packet<'trace>>>,
-        timeout: Duration,
+        /// Match replies by checksum when a NAT rewrote the quoted IP id
+        broken_nat: bool,
         activity_sender: Sender<TraceResult>,
     },
     V6 {
+        trace_id: TraceId,
         bundles: Vec<ProbeBundle<Ipv6Packet<'trace>>>,
-        timeout: Duration,
+        /// Match replies by checksum when a NAT rewrote the quoted IP id
+        broken_nat: bool,
         activity_sender: Sender<TraceResult>,
     },
+    /// Drop the probes of a trace still waiting to be sent or answered
+    Cancel { trace_id: TraceId },
+    /// Cancel every trace and refuse new ones
+    Shutdown,
 }
@@ -4,6 +4,8 @@ use crate::probe::{ProbeResponse, ProbeSent};
 
 /// The outcome of any probe we handled
 #[derive(Clone,Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TraceResponse {
     /// The sent probe responded
     Received(ProbeResponse),
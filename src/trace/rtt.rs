@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+// Shortest a hop is waited on however quickly it answered before
+const MIN_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Longest a hop is waited on, backoff included
+const MAX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Round trip time of a hop smoothed over its replies, as TCP estimates its retransmission
+/// timeout (RFC 6298)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RttEstimate {
+    /// Smoothed round trip time
+    pub srtt: Duration,
+    /// How much the round trip time varies
+    pub rttvar: Duration,
+}
+
+impl RttEstimate {
+    /// Estimate from the first reply of a hop
+    pub fn new(rtt: Duration) -> Self {
+        Self {
+            srtt: rtt,
+            rttvar: rtt / 2,
+        }
+    }
+
+    /// Take another reply into account
+    pub fn update(&mut self, rtt: Duration) {
+        // RTTVAR = 3/4 * RTTVAR + 1/4 * |SRTT - R'|
+        let deviation = self.srtt.abs_diff(rtt);
+        self.rttvar = (self.rttvar * 3 + deviation) / 4;
+        // SRTT = 7/8 * SRTT + 1/8 * R'
+        self.srtt = (self.srtt * 7 + rtt) / 8;
+    }
+
+    /// How long to wait on the hop before giving up on a probe, between 50ms and 10s
+    pub fn timeout(&self) -> Duration {
+        (self.srtt + self.rttvar * 4).clamp(MIN_TIMEOUT, MAX_TIMEOUT)
+    }
+}
+
+/// Timeout of the `attempt`th probe to a hop, doubling the first timeout on each retry
+pub fn backoff(timeout: Duration, attempt: u8) -> Duration {
+    let doubled = timeout.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    // Timeouts set longer than the cap aren't cut short
+    doubled.min(MAX_TIMEOUT.max(timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn first_reply_sets_the_estimate() {
+        let estimate = RttEstimate::new(ms(100));
+        assert_eq!(estimate.srtt, ms(100));
+        assert_eq!(estimate.rttvar, ms(50));
+        assert_eq!(estimate.timeout(), ms(300));
+    }
+
+    #[test]
+    fn update_smooths_towards_replies() {
+        let mut estimate = RttEstimate::new(ms(100));
+        estimate.update(ms(180));
+        // 3/4 * 50 + 1/4 * 80
+        assert_eq!(estimate.rttvar, ms(57) + Duration::from_micros(500));
+        // 7/8 * 100 + 1/8 * 180
+        assert_eq!(estimate.srtt, ms(110));
+
+        for _ in 0..100 {
+            estimate.update(ms(20));
+        }
+        assert!(estimate.srtt.abs_diff(ms(20)) < ms(1));
+        assert!(estimate.rttvar < ms(1));
+    }
+
+    #[test]
+    fn timeout_is_clamped() {
+        assert_eq!(RttEstimate::new(ms(1)).timeout(), MIN_TIMEOUT);
+        assert_eq!(RttEstimate::new(ms(5_000)).timeout(), MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn backoff_doubles_each_attempt() {
+        assert_eq!(backoff(ms(300), 0), ms(300));
+        assert_eq!(backoff(ms(300), 1), ms(300));
+        assert_eq!(backoff(ms(300), 2), ms(600));
+        assert_eq!(backoff(ms(300), 4), ms(2_400));
+    }
+
+    #[test]
+    fn backoff_stops_at_the_cap() {
+        assert_eq!(backoff(ms(300), 8), MAX_TIMEOUT);
+        assert_eq!(backoff(ms(300), u8::MAX), MAX_TIMEOUT);
+        // Longer timeouts are waited in full but not doubled
+        assert_eq!(backoff(ms(30_000), 1), ms(30_000));
+        assert_eq!(backoff(ms(30_000), 3), ms(30_000));
+    }
+}
@@ -2,10 +2,23 @@ use std::sync::mpsc::Sender;
 use std::time::Duration;
 
 use super::TraceResult;
+use crate::prelude::TraceId;
 use crate::probe::ProbeSent;
 
 pub struct TraceSent {
+    pub trace_id: TraceId,
     pub probes: Vec<ProbeSent>,
     pub timeout: Duration,
+    pub broken_nat: bool,
     pub activity_sender: Sender<TraceResult>,
 }
+
+/// What the sender passes on to the receiver
+pub enum SentActivity {
+    /// Probes to wait for replies to
+    Sent(TraceSent),
+    /// Forget the probes of a trace
+    Cancel(TraceId),
+    /// Forget every probe
+    Shutdown,
+}
@@ -1,9 +1,13 @@
-use super::{TraceOptions, TraceRequest, TraceResponse, TraceResult};
+use super::rtt::backoff;
+use super::{
+    Activity, HopEvent, HopEvents, RttEstimate, TraceOptions, TraceRequest, TraceResponse,
+    TraceResult,
+};
 
 use crate::TracerouteError;
+use crate::prelude::{TTL, TraceId};
 use crate::packet::{PacketBuilder, PacketBuilderTrait};
-use crate::prelude::*;
-use crate::probe::ProbeBundle;
+use crate::probe::{ProbeBundle, ReplyKind};
 
 use async_std::{
     pin::Pin,
@@ -14,16 +18,25 @@ use log::*;
 use pnet::packet::ipv4::Ipv4Packet;
 
 use std::collections::hash_map::DefaultHasher;
+use std::future::poll_fn;
 use std::hash::{Hash, Hasher};
 use std::iter::Iterator;
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+// Tells the network threads which probes belong to which trace
+static NEXT_TRACE_ID: AtomicU64 = AtomicU64::new(0);
 
 /// Perform trace from a source to destination
 /// Acts as an iterator or stream by providing whole trace each time `next()` is called.
+/// [`events()`](Trace::events) follows the same rounds probe by probe.
+///
+/// Dropping a trace [cancels](Trace::cancel) the round in flight.
 #[derive(Debug)]
 pub struct Trace {
+    id: TraceId,
     source: IpAddr,
     destination: IpAddr,
     options: TraceOptions,
@@ -36,6 +49,18 @@ pub struct Trace {
     // packets were with which round of sending. Is this an issue?
     round: usize,
     completed: usize,
+    // No new rounds are started once cancelled
+    cancelled: bool,
+    // When the first round started, the deadline counts from here
+    started: Option<Instant>,
+    // Round trip times of each distance learned over the rounds
+    rtts: Vec<Option<RttEstimate>>,
+    // Probes sent to each distance this round
+    attempts: Vec<u8>,
+    // Kept while timed out probes may be retried, the round completes once it is dropped
+    retry_sender: Option<Sender<TraceResult>>,
+    // Distances of the round still waiting on their final outcome
+    outstanding: usize,
 }
 
 impl Trace {
@@ -56,6 +81,12 @@ impl Trace {
         // thus this receiver is really just filling space for on new
         let (_sender, activity_receiver) = channel();
 
+        // Ports picked once for the whole flow
+        let options = TraceOptions {
+            protocol: options.ports.flow_protocol(options.protocol),
+            ..options
+        };
+
         // convert ttl to usize
         let max_ttl: usize = options.max_ttl.into();
 
@@ -63,6 +94,7 @@ impl Trace {
         let queue = vec![None; max_ttl];
 
         Ok(Self {
+            id: NEXT_TRACE_ID.fetch_add(1, Ordering::Relaxed),
             source,
             destination,
             options,
@@ -72,6 +104,12 @@ impl Trace {
             queue,
             round: 0,
             completed: 0,
+            cancelled: false,
+            started: None,
+            rtts: vec![None; max_ttl],
+            attempts: vec![0; max_ttl],
+            retry_sender: None,
+            outstanding: 0,
         })
     }
 
@@ -86,38 +124,189 @@ impl Trace {
             self.insert_response(TraceResponse::Masked(ttl));
         }
 
-        let Self {
-            packet_sender,
-            options,
-            ..
-        } = self;
-
         // Get a list of all distances we are trying to probe
-        let range = options.get_ttl_range();
-        let TraceOptions { protocol, .. } = options;
-
+        let range = self.options.get_ttl_range();
         // Build packets and place them into probe bundles
+        self.attempts.fill(0);
         let bundles: Vec<ProbeBundle<Ipv4Packet<'static>>> = range
             .iter()
-            .map(|ttl| PacketBuilder::build(*protocol, source, destination, *ttl))
+            .map(|ttl| self.ipv4_bundle(source, destination, *ttl))
             .collect::<Result<_, TracerouteError>>()?;
 
         // Record how many probes we sent before we loose bundles
         let probes_sent = bundles.len();
+        if self.options.attempts > 1 && probes_sent > 0 {
+            self.retry_sender = Some(activity_sender.clone());
+            self.outstanding = probes_sent;
+        }
         // Create a package for the packet sender
         let request = TraceRequest::V4 {
+            trace_id: self.id,
             bundles,
-            timeout: Duration::from_millis(options.timeout.into()),
+            broken_nat: self.options.broken_nat,
             activity_sender,
         };
-        packet_sender.send(request)?;
+        self.packet_sender.send(request)?;
         Ok(probes_sent)
     }
 
+    // Build the next attempt at probing `ttl`
+    fn ipv4_bundle(
+        &mut self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        ttl: TTL,
+    ) -> Result<ProbeBundle<Ipv4Packet<'static>>, TracerouteError> {
+        let attempt = match self.attempts.get_mut(usize::from(ttl) - 1) {
+            Some(attempts) => {
+                *attempts += 1;
+                *attempts
+            }
+            None => 1,
+        };
+        let mut bundle = PacketBuilder::build(&self.options, source, destination, ttl)?;
+        bundle.timeout = backoff(self.hop_timeout(ttl), attempt);
+        Ok(bundle)
+    }
+
+    // Probe a distance again after it timed out, returning the attempt sent
+    fn retry(&mut self, ttl: TTL) -> Result<u8, TracerouteError> {
+        let activity_sender = match self.retry_sender {
+            Some(ref sender) => sender.clone(),
+            None => return Err(TracerouteError::ChannelUnexpectedlyClosed),
+        };
+        let (source, destination) = match (self.source, self.destination) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => (source, destination),
+            (IpAddr::V6(_source), IpAddr::V6(_destination)) => Err(TracerouteError::NoIpv6)?,
+            _ => Err(TracerouteError::IpProtocolMismatch)?,
+        };
+        let bundle = self.ipv4_bundle(source, destination, ttl)?;
+        debug!("Retrying {} at ttl {} after {:?}", destination, ttl, bundle.timeout);
+        let request = TraceRequest::V4 {
+            trace_id: self.id,
+            bundles: vec![bundle],
+            broken_nat: self.options.broken_nat,
+            activity_sender,
+        };
+        self.packet_sender.send(request)?;
+        self.probes_sent += 1;
+        Ok(self.attempts[usize::from(ttl) - 1])
+    }
+
+    // A distance got its final outcome, the round completes once all of them did
+    fn settle(&mut self) {
+        self.outstanding = self.outstanding.saturating_sub(1);
+        if self.outstanding == 0 {
+            self.retry_sender = None;
+        }
+    }
+
+    // Probes have been sent again this round
+    fn retried(&self) -> bool {
+        self.attempts.iter().any(|attempts| *attempts > 1)
+    }
+
+    /// How long a first probe to `ttl` waits for a reply
+    ///
+    /// With [adaptive timeouts](TraceOptions::adaptive_timeout) this follows the replies of the
+    /// hop so far. Hops yet to reply go by the closest hop which did, so retries within a single
+    /// round already adapt. Until any hop replies the fixed timeout is used.
+    pub fn hop_timeout(&self, ttl: TTL) -> Duration {
+        let estimate = match self.options.adaptive_timeout {
+            true => self.closest_rtt(ttl),
+            false => None,
+        };
+        match estimate {
+            Some(estimate) => estimate.timeout(),
+            None => Duration::from_millis(self.options.timeout.into()),
+        }
+    }
+
+    /// Round trip time of the hop at `ttl` smoothed over its replies so far
+    pub fn hop_rtt(&self, ttl: TTL) -> Option<RttEstimate> {
+        let index = usize::from(ttl).checked_sub(1)?;
+        self.rtts.get(index).copied().flatten()
+    }
+
+    // Estimate of the hop at `ttl`, or the closest one to it. On a tie the further hop is taken
+    // for its longer round trip
+    fn closest_rtt(&self, ttl: TTL) -> Option<RttEstimate> {
+        let index = usize::from(ttl).checked_sub(1)?;
+        (0..self.rtts.len()).find_map(|distance| {
+            let further = self.rtts.get(index + distance).copied().flatten();
+            let closer = index
+                .checked_sub(distance)
+                .and_then(|closer| self.rtts.get(closer).copied().flatten());
+            further.or(closer)
+        })
+    }
+
+    // Learn from the round trip time of a reply
+    fn observe_rtt(&mut self, ttl: TTL, rtt: Duration) {
+        let index = usize::from(ttl) - 1;
+        if let Some(estimate) = self.rtts.get_mut(index) {
+            match estimate {
+                Some(estimate) => estimate.update(rtt),
+                None => *estimate = Some(RttEstimate::new(rtt)),
+            }
+        }
+    }
+
+    /// IP the probes are sent from
+    pub fn source(&self) -> IpAddr {
+        self.source
+    }
+
+    /// IP the probes are sent to
+    pub fn destination(&self) -> IpAddr {
+        self.destination
+    }
+
     pub fn probes_sent(&self) -> usize {
         self.probes_sent
     }
 
+    /// Stop tracing
+    ///
+    /// Probes of the round in flight which haven't been sent are dropped, and those waiting on a
+    /// reply are forgotten by the engine. The round still completes with the responses seen so
+    /// far, after that the trace ends.
+    pub fn cancel(&mut self) {
+        if self.cancelled {
+            return;
+        }
+        self.cancelled = true;
+        // No more retries, the round completes with what is in flight
+        self.retry_sender = None;
+        if self.round == self.completed {
+            return;
+        }
+        debug!("Cancelling trace to {}", self.destination);
+        // Without the engine the round completes anyway as the channel disconnects
+        let _ = self.packet_sender.send(TraceRequest::Cancel { trace_id: self.id });
+    }
+
+    /// The trace was cancelled or passed its deadline
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    // Nothing more will come from the trace
+    pub(super) fn is_finished(&self) -> bool {
+        self.cancelled && self.round == self.completed
+    }
+
+    // Cancel the trace once it has run longer than its deadline
+    fn check_deadline(&mut self) {
+        let (Some(deadline), Some(started)) = (self.options.deadline, self.started) else {
+            return;
+        };
+        if !self.cancelled && started.elapsed() >= Duration::from_millis(deadline.into()) {
+            info!("Trace to {} reached its deadline", self.destination);
+            self.cancel();
+        }
+    }
+
     // place response into queue
     fn insert_response(&mut self, response: TraceResponse) {
         let ttl = match response {
@@ -152,61 +341,24 @@ impl Trace {
         hops
     }
 
-    pub fn flowhash(&self) -> u16 {
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish() as u16
-    }
-
-    /// Calculate the flowhash of a potential new trace
+    /// Stream every probe sent and every outcome as it arrives instead of whole rounds
     ///
-    /// This is to check if a flow is already in a `HashMap` or `HashSet` or similar
-    pub fn pre_build_flowhash(source: &IpAddr, destination: &IpAddr, protocol: &Protocol) -> u16 {
-        let mut state = DefaultHasher::new();
-        // dscp
-        0u8.hash(&mut state);
-        // ecn
-        0u8.hash(&mut state);
-
-        // Port data may not be taken into account for flows depending on the network device vendor
-        // and device configuration
-        source.hash(&mut state);
-        destination.hash(&mut state);
-        protocol.hash(&mut state);
-
-        state.finish() as u16
+    /// Shares its rounds with the [`Stream`] and [`Iterator`] of the trace, whatever one of them
+    /// has taken the other won't see.
+    pub fn events(&mut self) -> HopEvents<'_> {
+        HopEvents { trace: self }
     }
-}
 
-impl Hash for Trace {
-    #[inline]
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        // dscp
-        0u8.hash(state);
-        // ecn
-        0u8.hash(state);
-
-        // Port data may not be taken into account for flows depending on the network device vendor
-        // and device configuration
-        self.source.hash(state);
-        self.destination.hash(state);
-        self.options.protocol.hash(state);
-    }
-}
-
-impl PartialEq for Trace {
-    fn eq(&self, other: &Self) -> bool {
-        self.source == other.source
-            && self.destination == other.destination
-            && self.options == other.options
-    }
-}
-impl Eq for Trace {}
-
-impl Iterator for Trace {
-    type Item = Result<Vec<TraceResponse>, TracerouteError>;
+    /// Next event of the round, starting a new round once the last one completed
+    ///
+    /// None when nothing happened yet, including right after a round was started, and once the
+    /// trace was cancelled and its last round completed.
+    pub fn next_event(&mut self) -> Option<Result<HopEvent, TracerouteError>> {
+        self.check_deadline();
+        if self.is_finished() {
+            return None;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
         let Self {
             round,
             completed,
@@ -218,6 +370,7 @@ impl Iterator for Trace {
         // start new round
         if round == completed {
             *round += 1;
+            self.started.get_or_insert_with(Instant::now);
 
             let (activity_sender, activity_receiver) = channel();
             self.activity_receiver = activity_receiver;
@@ -241,6 +394,9 @@ impl Iterator for Trace {
             }
         }
 
+        let round = self.round;
+        let flowhash = self.flowhash();
+
         // handle all activity in the channel
         loop {
             let trace_result = match self.activity_receiver.try_recv() {
@@ -251,26 +407,174 @@ impl Iterator for Trace {
                     }
                     TryRecvError::Disconnected => {
                         self.completed += 1;
-                        return Some(Ok(self.collect_results()));
+                        let responses = self.collect_results();
+                        return Some(Ok(HopEvent::RoundComplete {
+                            round,
+                            flowhash,
+                            responses,
+                        }));
                     }
                 },
             };
-            let response = match trace_result {
-                Ok(response) => response,
-                Err(err) => return Some(Err(err)),
+            let activity = match trace_result {
+                Ok(activity) => activity,
+                // A retry turned away by an engine shut down after the round started
+                Err(TracerouteError::Cancelled) if self.retried() => {
+                    self.cancelled = true;
+                    self.retry_sender = None;
+                    continue;
+                }
+                Err(err) => {
+                    // Probes which failed to send never get an outcome to wait for
+                    self.retry_sender = None;
+                    return Some(Err(err));
+                }
             };
 
-            match response {
-                TraceResponse::Received(ref _probe) => {
-                    self.insert_response(response);
+            let event = match activity {
+                Activity::Sent(sent) => HopEvent::ProbeSent {
+                    round,
+                    flowhash,
+                    sent,
+                },
+                Activity::Response(TraceResponse::Received(response)) => {
+                    self.settle();
+                    self.observe_rtt(response.ttl, response.ping);
+                    self.insert_response(TraceResponse::Received(response.clone()));
+                    match self.reached(&response.kind, &response.destination) {
+                        true => HopEvent::DestinationReached {
+                            round,
+                            flowhash,
+                            response,
+                        },
+                        false => HopEvent::HopReplied {
+                            round,
+                            flowhash,
+                            response,
+                        },
+                    }
                 }
-                TraceResponse::TimedOut(ref _probe_sent) => {
-                    self.insert_response(response);
+                Activity::Response(TraceResponse::TimedOut(sent))
+                    if self.retry_sender.is_some()
+                        && self.attempts[usize::from(sent.ttl) - 1] < self.options.attempts =>
+                {
+                    match self.retry(sent.ttl) {
+                        Ok(attempt) => HopEvent::HopRetried {
+                            round,
+                            flowhash,
+                            sent,
+                            attempt,
+                        },
+                        Err(err) => {
+                            self.retry_sender = None;
+                            return Some(Err(err));
+                        }
+                    }
                 }
-                TraceResponse::Masked(_ttl) => {
-                  error!("Not how masked are usually received");
+                Activity::Response(TraceResponse::TimedOut(sent)) => {
+                    self.settle();
+                    self.insert_response(TraceResponse::TimedOut(sent.clone()));
+                    HopEvent::HopTimedOut {
+                        round,
+                        flowhash,
+                        sent,
+                    }
+                }
+                Activity::Response(TraceResponse::Masked(_ttl)) => {
+                    error!("Not how masked are usually received");
+                    continue;
+                }
+                Activity::Cancelled => {
+                    // Whatever the engine still held of the round won't come back
+                    self.cancelled = true;
+                    self.retry_sender = None;
+                    continue;
                 }
             };
+            return Some(Ok(event));
+        }
+    }
+
+    // The reply came from the destination rather than a hop on the way
+    fn reached(&self, kind: &ReplyKind, replier: &IpAddr) -> bool {
+        *replier == self.destination || *kind == ReplyKind::EchoReply
+    }
+
+    pub fn flowhash(&self) -> u16 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish() as u16
+    }
+
+    /// Calculate the flowhash of a potential new trace
+    ///
+    /// This is to check if a flow is already in a `HashMap` or `HashSet` or similar. Source ports
+    /// picked at random by [`PortStrategy::RandomSource`](crate::PortStrategy) aren't known yet so
+    /// those traces hash differently once built.
+    pub fn pre_build_flowhash(
+        source: &IpAddr,
+        destination: &IpAddr,
+        options: &TraceOptions,
+    ) -> u16 {
+        let TraceOptions {
+            dscp,
+            ecn,
+            protocol,
+            ..
+        } = options;
+        let mut state = DefaultHasher::new();
+        dscp.hash(&mut state);
+        ecn.hash(&mut state);
+
+        // Port data may not be taken into account for flows depending on the network device vendor
+        // and device configuration
+        source.hash(&mut state);
+        destination.hash(&mut state);
+        protocol.hash(&mut state);
+
+        state.finish() as u16
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl Hash for Trace {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.options.dscp.hash(state);
+        self.options.ecn.hash(state);
+
+        // Port data may not be taken into account for flows depending on the network device vendor
+        // and device configuration
+        self.source.hash(state);
+        self.destination.hash(state);
+        self.options.protocol.hash(state);
+    }
+}
+
+impl PartialEq for Trace {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+            && self.destination == other.destination
+            && self.options == other.options
+    }
+}
+impl Eq for Trace {}
+
+impl Iterator for Trace {
+    type Item = Result<Vec<TraceResponse>, TracerouteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_event()? {
+                Ok(HopEvent::RoundComplete { responses, .. }) => return Some(Ok(responses)),
+                Ok(_event) => continue,
+                Err(err) => return Some(Err(err)),
+            }
         }
     }
 }
@@ -279,8 +583,10 @@ impl Stream for Trace {
     type Item = Result<Vec<TraceResponse>, TracerouteError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let result = match self.get_mut().next() {
+        let trace = self.get_mut();
+        let result = match trace.next() {
             Some(result) => result,
+            None if trace.is_finished() => return Poll::Ready(None),
             None => {
                 cx.waker().wake_by_ref();
                 return Poll::Pending;
@@ -295,3 +601,32 @@ impl Stream for Trace {
         }
     }
 }
+
+/// Wait for the next round of every trace while letting them all probe at the same time
+///
+/// Results are in the same order as `traces`.
+pub async fn next_rounds(
+    traces: &mut [Trace],
+) -> Vec<Option<Result<Vec<TraceResponse>, TracerouteError>>> {
+    let mut rounds: Vec<Option<Option<_>>> = traces.iter().map(|_trace| None).collect();
+
+    poll_fn(|cx| {
+        for (trace, round) in traces.iter_mut().zip(rounds.iter_mut()) {
+            if round.is_some() {
+                continue;
+            }
+            if let Poll::Ready(result) = Pin::new(trace).poll_next(cx) {
+                *round = Some(result);
+            }
+        }
+
+        if rounds.iter().all(Option::is_some) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    rounds.into_iter().map(Option::flatten).collect()
+}
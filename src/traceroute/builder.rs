@@ -0,0 +1,161 @@
+use super::{Traceroute, TracerouteError};
+use crate::sockets::{Backend, Binding, CaptureHook, Direction, RateLimit, SocketOptions, Sockets};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Routers quote at most this much of a probe, anything smaller cuts replies short
+const MIN_BUFFER_SIZE: usize = 576;
+
+/// Configure and start a [`Traceroute`] engine
+///
+/// Settings are checked together when building so a bad combination fails before any socket is
+/// opened.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use traceroute::{RateLimit, TracerouteBuilder};
+///
+/// let traceroute = TracerouteBuilder::new()
+///     .rate_limit(RateLimit {
+///         packets_per_second: 500,
+///         per_destination: Some(50),
+///     })
+///     .interface("eth0")
+///     .receive_timeout(Duration::from_millis(1))
+///     .capture(|direction, packet| println!("{:?} {} bytes", direction, packet.len()))
+///     .build()?;
+/// # Ok::<(), traceroute::TracerouteError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TracerouteBuilder {
+    options: SocketOptions,
+}
+
+impl TracerouteBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Packets per second budgets shared by every trace
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.options.rate_limit = rate_limit;
+        self
+    }
+
+    /// Send a packet at most every `delay`
+    pub fn packet_delay(self, delay: Duration) -> Self {
+        self.rate_limit(RateLimit::from_delay(delay))
+    }
+
+    /// Interface and source address probes are bound to
+    pub fn binding(mut self, binding: Binding) -> Self {
+        self.options.binding = binding;
+        self
+    }
+
+    /// Only send and receive through the interface named `name`
+    pub fn interface(mut self, name: impl Into<String>) -> Self {
+        self.options.binding.interface = Some(name.into());
+        self
+    }
+
+    /// Only send from `source` instead of the address the routing table picks
+    pub fn source(mut self, source: IpAddr) -> Self {
+        self.options.binding.source = Some(source);
+        self
+    }
+
+    /// How probes are put on the wire
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.options.backend = backend;
+        self
+    }
+
+    /// Bytes buffered for each packet received
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+
+    /// How long the receive thread waits on the network before checking on new probes
+    pub fn receive_timeout(mut self, timeout: Duration) -> Self {
+        self.options.receive_timeout = timeout;
+        self
+    }
+
+    /// How long a reply arriving before its probe was handed over is kept around
+    pub fn unmatched_timeout(mut self, timeout: Duration) -> Self {
+        self.options.unmatched_timeout = timeout;
+        self
+    }
+
+    /// Open a receive socket for another protocol, only ICMP is listened for by default
+    ///
+    /// Hops reply over ICMP. Listen for TCP to match the destination answering TCP probes,
+    /// packets of other protocols are counted as unmatched and handed to the capture hooks.
+    pub fn listen(mut self, protocol: IpNextHeaderProtocol) -> Self {
+        self.options.listen.push(protocol);
+        self
+    }
+
+    /// Hand every packet sent and received to `hook`
+    pub fn capture<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        let hook: CaptureHook = Arc::new(hook);
+        self.options.captures.push(hook);
+        self
+    }
+
+    /// Check the settings work together
+    pub fn validate(&self) -> Result<(), TracerouteError> {
+        let options = &self.options;
+        let invalid = |reason| Err(TracerouteError::InvalidEngine(reason));
+
+        if options.buffer_size < MIN_BUFFER_SIZE {
+            return invalid("buffer size must hold a 576 byte ICMP error");
+        }
+        if options.receive_timeout.is_zero() {
+            return invalid("a zero receive timeout blocks the receive thread forever");
+        }
+        if options.unmatched_timeout < options.receive_timeout {
+            return invalid("unmatched packets must be kept longer than the receive timeout");
+        }
+        if !options.listen.contains(&IpNextHeaderProtocols::Icmp) {
+            return invalid("replies to probes arrive over ICMP which must be listened for");
+        }
+        let mut listen = options.listen.clone();
+        listen.sort_by_key(|protocol| protocol.0);
+        listen.dedup();
+        if listen.len() != options.listen.len() {
+            return invalid("a protocol is listened for more than once");
+        }
+        if options
+            .binding
+            .source
+            .is_some_and(|source| source.is_ipv6())
+        {
+            return Err(TracerouteError::NoIpv6);
+        }
+        if options.binding.interface.is_some() && !cfg!(target_os = "linux") {
+            return invalid("binding to an interface is only supported on Linux");
+        }
+        Ok(())
+    }
+
+    /// Open the sockets and start the network threads
+    pub fn build(mut self) -> Result<Traceroute, TracerouteError> {
+        self.validate()?;
+
+        // Probes go out of the ICMP socket
+        self.options
+            .listen
+            .sort_by_key(|protocol| *protocol != IpNextHeaderProtocols::Icmp);
+        let sockets = Sockets::new(self.options)?;
+
+        Ok(Traceroute::from_sockets(sockets))
+    }
+}
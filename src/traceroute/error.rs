@@ -1,8 +1,10 @@
+use crate::export::Format;
 use crate::protocol::Protocol;
 use pnet::packet::icmp::IcmpType;
 use std::error::Error;
 use std::fmt;
 use std::io::{self, ErrorKind};
+use std::net::IpAddr;
 use std::sync::mpsc;
 
 /// Wrapper for all errors that can occur in this library
@@ -23,12 +25,35 @@ pub enum TracerouteError {
     /// An invalid trace was created which used an Ipv4 and Ipv6 address for the source and
     /// destination
     IpProtocolMismatch,
+    /// Output format isn't available with the enabled crate features
+    UnsupportedFormat(Format),
+    /// Failed to serialize results into json
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+    /// The history database returned an error
+    #[cfg(feature = "history")]
+    History(rusqlite::Error),
+    /// The history database contains data we don't understand
+    CorruptHistory(&'static str),
+    /// No network interface goes by this name
+    UnknownInterface(String),
+    /// The source address doesn't belong to the interfaces traces may use
+    UnusableSource(IpAddr),
+    /// A [`TracerouteBuilder`](crate::TracerouteBuilder) was given settings which don't work
+    /// together
+    InvalidEngine(&'static str),
+    /// The engine was shut down before the trace could run
+    Cancelled,
 }
 
 impl Error for TracerouteError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             Self::Io(err) => Some(err),
+            #[cfg(feature = "serde")]
+            Self::Json(err) => Some(err),
+            #[cfg(feature = "history")]
+            Self::History(err) => Some(err),
             _ => None,
         }
     }
@@ -56,6 +81,20 @@ impl fmt::Display for TracerouteError {
             }
             Self::ChannelUnexpectedlyClosed => write!(f, "channel unexptectedly closed"),
             Self::IpProtocolMismatch => write!(f, "attempted to use ipv4 with ipv6"),
+            Self::UnsupportedFormat(format) => {
+                write!(f, "{} output requires a crate feature which isn't enabled", format)
+            }
+            #[cfg(feature = "serde")]
+            Self::Json(ref err) => write!(f, "failed to serialize json: {}", err),
+            #[cfg(feature = "history")]
+            Self::History(ref err) => write!(f, "history database error: {}", err),
+            Self::CorruptHistory(ref err) => write!(f, "history database is corrupt: {}", err),
+            Self::UnknownInterface(ref name) => write!(f, "no interface named {}", name),
+            Self::UnusableSource(source) => {
+                write!(f, "{} isn't an IPv4 address of a usable interface", source)
+            }
+            Self::InvalidEngine(reason) => write!(f, "invalid traceroute engine: {}", reason),
+            Self::Cancelled => write!(f, "traceroute engine was shut down"),
         }
     }
 }
@@ -66,6 +105,13 @@ impl From<io::Error> for TracerouteError {
     }
 }
 
+#[cfg(feature = "history")]
+impl From<rusqlite::Error> for TracerouteError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::History(err)
+    }
+}
+
 impl<T> From<mpsc::SendError<T>> for TracerouteError {
     fn from(_err: mpsc::SendError<T>) -> Self {
         Self::ChannelUnexpectedlyClosed
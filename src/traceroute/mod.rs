@@ -1,5 +1,7 @@
+mod builder;
 mod error;
 mod traceroute;
 
 pub use self::traceroute::Traceroute;
+pub use builder::TracerouteBuilder;
 pub use error::TracerouteError;
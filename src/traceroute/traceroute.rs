@@ -1,8 +1,10 @@
-use crate::sockets::{SocketJoinResult, Sockets};
+use super::TracerouteBuilder;
+use crate::sockets::{EngineStats, RateLimit, Route, ShutdownHandle, SocketJoinResult, Sockets};
 use crate::trace::{Trace, TraceOptions};
 use crate::traceroute::TracerouteError;
 use log::*;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Interface to init the network and create [`Trace`s](Trace)
@@ -13,7 +15,6 @@ use std::time::Duration;
 /// it from within the application logic and not here.
 
 // Provides management interface for traceroute
-// TODO bind/unbind interface?
 pub struct Traceroute {
     sockets: Sockets,
     //probes: HashMap<u16, Probe>,
@@ -21,12 +22,24 @@ pub struct Traceroute {
 }
 
 impl Traceroute {
-    /// Create a new traceroute engine
+    /// Create a new traceroute engine sending a packet at most every `packet_delay` milliseconds
     pub fn new(packet_delay: u16) -> Result<Self, TracerouteError> {
         let packet_delay = Duration::from_millis(packet_delay as u64);
-        let sockets = Sockets::new(packet_delay)?;
+        Self::builder().packet_delay(packet_delay).build()
+    }
+
+    /// Create a new traceroute engine sharing a packets per second budget between all traces
+    pub fn with_rate_limit(rate_limit: RateLimit) -> Result<Self, TracerouteError> {
+        Self::builder().rate_limit(rate_limit).build()
+    }
+
+    /// Configure every setting of a new traceroute engine
+    pub fn builder() -> TracerouteBuilder {
+        TracerouteBuilder::new()
+    }
 
-        Ok(Self { sockets })
+    pub(super) fn from_sockets(sockets: Sockets) -> Self {
+        Self { sockets }
     }
 
     /// Get available system addresses which can be used as the source for a trace
@@ -34,6 +47,34 @@ impl Traceroute {
         self.sockets.addresses()
     }
 
+    /// Route the kernel takes to `destination`
+    pub fn route(&self, destination: IpAddr) -> Result<Route, TracerouteError> {
+        Ok(self.sockets.bound().route(destination)?)
+    }
+
+    /// Source address the routing table picks to reach `destination`
+    ///
+    /// Falls back to the first of the [`addresses`](Traceroute::addresses) when the route leaves
+    /// from somewhere traces can't use.
+    pub fn source_for(&self, destination: IpAddr) -> IpAddr {
+        self.sockets.bound().source_for(destination)
+    }
+
+    /// Counters of the packets handled by the network threads
+    pub fn stats(&self) -> Arc<EngineStats> {
+        self.sockets.stats()
+    }
+
+    /// Cancel every trace and refuse new ones, the network threads keep running until closed
+    pub fn shutdown(&self) {
+        self.sockets.shutdown_handle().shutdown();
+    }
+
+    /// Handle to [`shutdown`](Traceroute::shutdown) the engine from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.sockets.shutdown_handle()
+    }
+
     /// Close network connections
     ///
     /// This must be run before drop to capture any panics that came from the socket threads.
@@ -1,48 +1,23 @@
 use crate::TracerouteError;
-use crate::prelude::{Checksum, TcpId};
+use crate::packet::{TCP_DESTINATION_PORT, TCP_SOURCE_PORT};
+use crate::prelude::Checksum;
+use crate::probe::{IcmpReply, ReplyKind};
 
 use pnet::datalink::{MacAddr, NetworkInterface};
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 
 use pnet::packet::Packet;
 use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
 use pnet::packet::ip::IpNextHeaderProtocols;
 use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
 use pnet::packet::udp::UdpPacket;
-use std::io;
-
-pub fn get_default_source_ip() -> Result<Ipv4Addr, TracerouteError> {
-    let default_interface = get_available_interfaces()
-        .first()
-        .ok_or_else(|| {
-            // io error may not be right for here maybe just a new traceroute error
-            TracerouteError::Io(io::Error::new(
-                io::ErrorKind::Other,
-                "No interfaces available",
-            ))
-        })?
-        .clone();
-
-    let source_ip = default_interface
-        .ips
-        .iter()
-        .find(|i| i.is_ipv4())
-        .ok_or_else(|| {
-            TracerouteError::Io(io::Error::new(
-                io::ErrorKind::Other,
-                "Couldn't get interface IPv4 address",
-            ))
-        })?
-        .ip();
-
-    match source_ip {
-        IpAddr::V4(ip) => Ok(ip),
-        _ => unreachable!("ipv6 addresses have already been filtered out"),
-    }
-}
 
-/// Returns the list of interfaces that are up, not loopback, not point-to-point,
-/// and have an IPv4 address associated with them.
+// Destination unreachable code sent when DF is set on a packet too large for the next hop
+const FRAGMENTATION_NEEDED: u8 = 4;
+
+/// Returns the list of interfaces that are up, not loopback and have an IPv4 address associated
+/// with them. Point-to-point links such as VPN tunnels are kept even though they have no MAC.
 pub fn get_available_interfaces() -> Vec<NetworkInterface> {
     let all_interfaces = pnet::datalink::interfaces();
 
@@ -62,8 +37,7 @@ pub fn get_available_interfaces() -> Vec<NetworkInterface> {
                 e.is_lower_up()
                     && !e.is_loopback()
                     && e.ips.iter().any(|ip| ip.is_ipv4())
-                    && e.mac.is_some()
-                    && e.mac.unwrap() != MacAddr::zero()
+                    && (e.is_point_to_point() || e.mac.is_some_and(|mac| mac != MacAddr::zero()))
             })
             .collect()
     }
@@ -71,49 +45,88 @@ pub fn get_available_interfaces() -> Vec<NetworkInterface> {
 
 /// Unpack the incoming payload from an ICMP packet
 /// This payload should be the payload we sent to the destination via the echo request
-fn unpack_icmp_payload(payload: &[u8]) -> Result<(u16, u16), TracerouteError> {
-    let packet = Ipv4Packet::new(&payload[4..]).ok_or(TracerouteError::MalformedPacket)?;
+fn unpack_icmp_payload(
+    payload: &[u8],
+) -> Result<(u16, Option<Checksum>, Vec<u8>), TracerouteError> {
+    let quoted = payload.get(4..).ok_or(TracerouteError::MalformedPacket)?;
+    let packet = Ipv4Packet::new(quoted).ok_or(TracerouteError::MalformedPacket)?;
     let id = packet.get_identification();
 
     let checksum = match packet.get_next_level_protocol() {
         IpNextHeaderProtocols::Udp => UdpPacket::new(packet.payload())
             .ok_or(TracerouteError::MalformedPacket)?
-            .get_checksum(),
+            .get_checksum()
+            .into(),
         IpNextHeaderProtocols::Icmp => IcmpPacket::new(packet.payload())
             .ok_or(TracerouteError::MalformedPacket)?
-            .get_checksum(),
+            .get_checksum()
+            .into(),
+        // Only the first 8 bytes are sure to be quoted which leaves the checksum out
+        IpNextHeaderProtocols::Tcp => packet
+            .payload()
+            .get(16..18)
+            .map(|checksum| u16::from_be_bytes([checksum[0], checksum[1]])),
         _ => {
             return Err(TracerouteError::UnmatchedPacket(
-                "incoming icmp payload is not a UDP or ICMP packet",
+                "incoming icmp payload is not a UDP, TCP or ICMP packet",
             ));
         }
     };
-    Ok((id, checksum))
+    Ok((id, checksum, quoted.to_vec()))
 }
 
+// Kind of reply with the id, checksum and headers of the probe it quotes
+type Quote = (ReplyKind, u16, Option<Checksum>, Vec<u8>);
+
 /// Process incoming ICMP packet and handle unexpected results
-fn handle_icmp_packet(packet: &[u8]) -> Result<(u16, u16), TracerouteError> {
+fn handle_icmp_packet(packet: &[u8]) -> Result<Quote, TracerouteError> {
     let icmp_packet = IcmpPacket::new(packet).ok_or(TracerouteError::MalformedPacket)?;
 
     let payload = icmp_packet.payload();
 
-    match icmp_packet.get_icmp_type() {
-        IcmpTypes::TimeExceeded | IcmpTypes::EchoReply | IcmpTypes::DestinationUnreachable => {
-            unpack_icmp_payload(payload)
-        }
-        icmp_type => Err(TracerouteError::ICMPTypeUnexpected(icmp_type)),
-    }
+    let kind = match icmp_packet.get_icmp_type() {
+        IcmpTypes::TimeExceeded => ReplyKind::TimeExceeded,
+        IcmpTypes::EchoReply => ReplyKind::EchoReply,
+        IcmpTypes::DestinationUnreachable => match icmp_packet.get_icmp_code().0 {
+            FRAGMENTATION_NEEDED => {
+                // RFC 1191 places the next hop MTU in the second half of the unused field.
+                // Routers predating it leave it zeroed
+                let mtu = payload
+                    .get(2..4)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                    .filter(|mtu| *mtu != 0);
+                ReplyKind::FragmentationNeeded { mtu }
+            }
+            code => ReplyKind::Unreachable { code },
+        },
+        icmp_type => return Err(TracerouteError::ICMPTypeUnexpected(icmp_type)),
+    };
+
+    let (id, checksum, quoted) = unpack_icmp_payload(payload)?;
+    Ok((kind, id, checksum, quoted))
 }
 
 /// Processes incoming IPv4 packet and passes it on to transport layer packet handler.
-pub fn handle_ipv4_packet(
-    header: Ipv4Packet,
-) -> Result<(IpAddr, TcpId, Checksum), TracerouteError> {
+pub fn handle_ipv4_packet(header: Ipv4Packet) -> Result<IcmpReply, TracerouteError> {
     let source = IpAddr::V4(header.get_source());
     let payload = header.payload();
 
-    let (id, checksum) = match header.get_next_level_protocol() {
+    let (kind, id, checksum, quoted) = match header.get_next_level_protocol() {
         IpNextHeaderProtocols::Icmp => handle_icmp_packet(payload)?,
+        IpNextHeaderProtocols::Tcp => {
+            let (kind, acknowledges) = handle_tcp_packet(payload)?;
+            // Matched by the sequence number acknowledged, nothing of the probe is quoted
+            return Ok(IcmpReply {
+                source,
+                ip_id: header.get_identification(),
+                ttl: header.get_ttl(),
+                id: 0,
+                checksum: None,
+                acknowledges: Some(acknowledges),
+                quoted: Vec::new(),
+                kind,
+            });
+        }
         // Any packets hitting here are actually for another application
         _ => {
             return Err(TracerouteError::UnmatchedPacket(
@@ -121,5 +134,39 @@ pub fn handle_ipv4_packet(
             ));
         }
     };
-    Ok((source, id, checksum))
+    Ok(IcmpReply {
+        source,
+        ip_id: header.get_identification(),
+        ttl: header.get_ttl(),
+        id,
+        checksum,
+        acknowledges: None,
+        quoted,
+        kind,
+    })
+}
+
+/// Answer of the destination to a TCP probe and the sequence number of the probe
+fn handle_tcp_packet(packet: &[u8]) -> Result<(ReplyKind, u32), TracerouteError> {
+    let tcp_packet = TcpPacket::new(packet).ok_or(TracerouteError::MalformedPacket)?;
+
+    // Connections of other applications come in on the same socket
+    if tcp_packet.get_source() != TCP_DESTINATION_PORT
+        || tcp_packet.get_destination() != TCP_SOURCE_PORT
+    {
+        return Err(TracerouteError::UnmatchedPacket("tcp packet isn't for a probe"));
+    }
+
+    let flags = tcp_packet.get_flags();
+    let kind = if flags & TcpFlags::RST != 0 {
+        ReplyKind::Reset
+    } else if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+        ReplyKind::SynAck
+    } else {
+        return Err(TracerouteError::UnmatchedPacket("tcp packet doesn't answer a syn"));
+    };
+
+    // Both acknowledge the sequence number of the SYN plus one
+    let sequence = tcp_packet.get_acknowledgement().wrapping_sub(1);
+    Ok((kind, sequence))
 }
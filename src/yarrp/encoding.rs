@@ -0,0 +1,94 @@
+use crate::prelude::TTL;
+
+/// Highest TTL which fits in the IP id
+pub const MAX_TTL: TTL = 0x1f;
+
+// Milliseconds wrap around after 26 bits, about 18 hours
+const TIMESTAMP_MASK: u32 = 0x03ff_ffff;
+
+// Checksums never come out as 0xffff so the top bit is left clear
+const CHECKSUM_MASK: u16 = 0x7fff;
+
+/// What a stateless probe carries in its headers
+///
+/// The TTL and the upper 11 bits of the send time go in the IP id, the lower 15 bits of the
+/// send time in the UDP checksum. The ports stay the same for every probe so each target is
+/// probed over a single flow. The target is the destination of the quoted IP header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbeStamp {
+    pub ttl: TTL,
+    /// Milliseconds since the start of the run
+    pub timestamp: u32,
+}
+
+impl ProbeStamp {
+    pub fn new(ttl: TTL, timestamp: u32) -> Self {
+        Self {
+            ttl: ttl & MAX_TTL,
+            timestamp: timestamp & TIMESTAMP_MASK,
+        }
+    }
+
+    /// IP id and UDP checksum to send with
+    pub fn encode(&self) -> (u16, u16) {
+        let ip_id = ((self.ttl as u16) << 11) | ((self.timestamp >> 15) as u16 & 0x07ff);
+        let checksum = self.timestamp as u16 & CHECKSUM_MASK;
+        (ip_id, checksum)
+    }
+
+    /// Recover the stamp from the quoted IP id and UDP checksum
+    pub fn decode(ip_id: u16, checksum: u16) -> Self {
+        let ttl = (ip_id >> 11) as TTL;
+        let timestamp = (((ip_id & 0x07ff) as u32) << 15) | (checksum & CHECKSUM_MASK) as u32;
+        Self { ttl, timestamp }
+    }
+
+    /// Milliseconds between sending and `now`, taking wrap around into account
+    pub fn elapsed(&self, now: u32) -> u32 {
+        (now & TIMESTAMP_MASK).wrapping_sub(self.timestamp) & TIMESTAMP_MASK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_survive_the_headers() {
+        for ttl in 0..=MAX_TTL {
+            for timestamp in [0, 1, 0x7fff, 0x8000, 1_234_567, TIMESTAMP_MASK] {
+                let stamp = ProbeStamp::new(ttl, timestamp);
+                let (ip_id, checksum) = stamp.encode();
+                assert_eq!(ProbeStamp::decode(ip_id, checksum), stamp);
+                assert_ne!(checksum, 0xffff);
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_cut_to_fit() {
+        let stamp = ProbeStamp::new(MAX_TTL + 1, TIMESTAMP_MASK + 2);
+        assert_eq!(stamp.ttl, 0);
+        assert_eq!(stamp.timestamp, 1);
+    }
+
+    #[test]
+    fn checksum_top_bit_is_ignored() {
+        let stamp = ProbeStamp::new(3, 0x1234);
+        let (ip_id, checksum) = stamp.encode();
+        assert_eq!(ProbeStamp::decode(ip_id, checksum | 0x8000), stamp);
+    }
+
+    #[test]
+    fn elapsed_wraps_around() {
+        let stamp = ProbeStamp::new(1, 1_000);
+        assert_eq!(stamp.elapsed(1_250), 250);
+        assert_eq!(stamp.elapsed(1_000), 0);
+
+        // Sent just before the milliseconds wrapped
+        let stamp = ProbeStamp::new(1, TIMESTAMP_MASK - 5);
+        assert_eq!(stamp.elapsed(10), 16);
+        // Run times past 26 bits are wrapped the same way
+        assert_eq!(stamp.elapsed(TIMESTAMP_MASK + 11), 16);
+    }
+}
@@ -0,0 +1,9 @@
+mod encoding;
+mod permutation;
+mod record;
+mod yarrp;
+
+pub use encoding::{MAX_TTL, ProbeStamp};
+pub use permutation::Permutation;
+pub use record::YarrpRecord;
+pub use yarrp::{Yarrp, YarrpOptions, YarrpSummary};
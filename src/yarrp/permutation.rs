@@ -0,0 +1,92 @@
+use rand::Rng;
+
+/// Random order over `0..len` without keeping the order in memory
+///
+/// Walks a full period linear congruential generator over the next power of two and skips
+/// values out of range.
+#[derive(Clone, Debug)]
+pub struct Permutation {
+    len: u64,
+    mask: u64,
+    multiplier: u64,
+    increment: u64,
+    state: u64,
+    remaining: u64,
+}
+
+impl Permutation {
+    pub fn new(len: u64) -> Self {
+        let modulus = len.max(1).next_power_of_two();
+        let mut rng = rand::rng();
+
+        // Hull-Dobell: an odd increment and a multiplier one more than a multiple of 4 visit
+        // every value of a power of two modulus once per period
+        let multiplier = (rng.random::<u64>() << 2) | 1;
+        let increment = rng.random::<u64>() | 1;
+        let state = rng.random::<u64>() & (modulus - 1);
+
+        Self {
+            len,
+            mask: modulus - 1,
+            multiplier,
+            increment,
+            state,
+            remaining: len,
+        }
+    }
+}
+
+impl Iterator for Permutation {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            self.state = self
+                .state
+                .wrapping_mul(self.multiplier)
+                .wrapping_add(self.increment)
+                & self.mask;
+            if self.state < self.len {
+                self.remaining -= 1;
+                return Some(self.state);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_index_comes_up_once() {
+        for len in [1, 2, 3, 17, 64, 65, 1000] {
+            let mut seen = Permutation::new(len).collect::<Vec<_>>();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>(), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn nothing_to_permute() {
+        assert_eq!(Permutation::new(0).next(), None);
+    }
+
+    #[test]
+    fn size_is_known_up_front() {
+        let mut permutation = Permutation::new(5);
+        assert_eq!(permutation.size_hint(), (5, Some(5)));
+        let _ = permutation.next();
+        assert_eq!(permutation.size_hint(), (4, Some(4)));
+        assert_eq!(permutation.by_ref().count(), 4);
+        assert_eq!(permutation.size_hint(), (0, Some(0)));
+        assert_eq!(permutation.next(), None);
+    }
+}
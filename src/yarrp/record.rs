@@ -0,0 +1,33 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use crate::prelude::TTL;
+
+/// A reply to a stateless probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct YarrpRecord {
+    pub target: Ipv4Addr,
+    pub ttl: TTL,
+    /// Interface which replied
+    pub hop: IpAddr,
+    /// Millisecond resolution, it is all the headers have room for
+    pub rtt: Duration,
+    /// The reply came from the target itself
+    pub reached: bool,
+}
+
+impl fmt::Display for YarrpRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<15} {:>2} {:<15} {:.2?}",
+            self.target, self.ttl, self.hop, self.rtt
+        )?;
+        if self.reached {
+            write!(f, " reached")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+use pnet::packet::Packet;
+use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::udp::UdpPacket;
+use pnet::transport::TransportChannelType::Layer3;
+use pnet::transport::{TransportSender, ipv4_packet_iter, transport_channel};
+
+use super::{MAX_TTL, Permutation, ProbeStamp, YarrpRecord};
+use crate::TracerouteError;
+use crate::packet::build_udp_probe;
+use crate::prelude::TTL;
+use crate::protocol::UdpParams;
+use crate::sockets::{Binding, ShutdownHandle, TokenBucket};
+
+// How long to wait for new packets before checking if the run is over
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Configuration of a [`Yarrp`] run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct YarrpOptions {
+    /// The minimum TTL to probe
+    pub min_ttl: TTL,
+    /// The maximum TTL to probe. At most [`MAX_TTL`]
+    pub max_ttl: TTL,
+    /// Probes sent per second. 0 is unlimited
+    pub packets_per_second: u32,
+    /// UDP ports of every probe, so each target is probed over a single flow
+    pub ports: UdpParams,
+    /// How long to keep listening after the last probe was sent
+    pub linger: Duration,
+}
+
+impl Default for YarrpOptions {
+    fn default() -> Self {
+        Self {
+            min_ttl: 1,
+            max_ttl: 16,
+            packets_per_second: 1000,
+            ports: UdpParams::default(),
+            linger: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Totals of a [`Yarrp`] run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YarrpSummary {
+    pub probes_sent: u64,
+    pub replies: u64,
+    /// ICMP packets which weren't replies to our probes
+    pub ignored: u64,
+}
+
+/// Stateless, randomized probing for mapping many targets quickly
+///
+/// Every (target, TTL) pair is probed once in a random order. Nothing is remembered about the
+/// probes sent, replies are matched purely from the headers quoted back to us. See
+/// [`ProbeStamp`] for what goes where.
+///
+/// Opens its own sockets so it can run next to a [`Traceroute`](crate::Traceroute).
+pub struct Yarrp {
+    source: Ipv4Addr,
+    options: YarrpOptions,
+    binding: Binding,
+    shut_down: Arc<AtomicBool>,
+}
+
+impl Yarrp {
+    pub fn new(source: Ipv4Addr, options: YarrpOptions) -> Self {
+        Self {
+            source,
+            options,
+            binding: Binding::default(),
+            shut_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Only send and receive through the interface or source of `binding`
+    pub fn set_binding(&mut self, binding: Binding) {
+        self.binding = binding;
+    }
+
+    /// Stop a run early from anywhere, such as a signal handler
+    ///
+    /// The run stops sending, skips waiting for late replies and returns the totals so far.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::flag(self.shut_down.clone())
+    }
+
+    /// Probe every target, handing each reply to `on_record` as it arrives
+    pub fn run<F: FnMut(YarrpRecord)>(
+        &self,
+        targets: &[Ipv4Addr],
+        mut on_record: F,
+    ) -> Result<YarrpSummary, TracerouteError> {
+        let YarrpOptions {
+            min_ttl, max_ttl, ..
+        } = self.options;
+        if min_ttl == 0 || max_ttl < min_ttl || max_ttl > MAX_TTL {
+            let message = format!("TTLs must be between 1 and {} with min before max", MAX_TTL);
+            let err = io::Error::new(io::ErrorKind::InvalidInput, message);
+            return Err(TracerouteError::Io(err));
+        }
+
+        let protocol = Layer3(IpNextHeaderProtocols::Icmp);
+        let (mut tx, mut rx) = transport_channel(4096, protocol)?;
+        self.binding.resolve()?.apply(&tx)?;
+        let start = Instant::now();
+        // A shutdown stops the run like the end of it would
+        let done = AtomicBool::new(self.shut_down.load(Ordering::SeqCst));
+        let mut summary = YarrpSummary::default();
+
+        let received = thread::scope(|scope| {
+            let sender = thread::Builder::new()
+                .name("yarrp-send".to_string())
+                .spawn_scoped(scope, || {
+                    let result = self.send(&mut tx, targets, start, &done);
+                    // Late replies are still on their way
+                    if !done.load(Ordering::SeqCst) {
+                        self.linger(&done);
+                    }
+                    done.store(true, Ordering::SeqCst);
+                    result
+                })?;
+
+            let received = self.receive(&mut rx, start, &done, &mut summary, &mut on_record);
+            // Stop sending if receiving failed
+            done.store(true, Ordering::SeqCst);
+
+            match sender.join() {
+                Ok(result) => summary.probes_sent = result?,
+                Err(err) => panic::resume_unwind(err),
+            }
+            received
+        });
+        received?;
+
+        info!(
+            "Yarrp sent {} probes and matched {} replies in {:.3?}{}",
+            summary.probes_sent,
+            summary.replies,
+            start.elapsed(),
+            if self.shut_down.load(Ordering::SeqCst) {
+                ", stopped early"
+            } else {
+                ""
+            }
+        );
+        Ok(summary)
+    }
+
+    // Wait out the linger unless the run is stopped first
+    fn linger(&self, done: &AtomicBool) {
+        let until = Instant::now() + self.options.linger;
+        while !done.load(Ordering::SeqCst) && !self.shut_down.load(Ordering::SeqCst) {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(RECEIVE_TIMEOUT));
+        }
+    }
+
+    // Walk the (target, TTL) permutation within the rate limit
+    fn send(
+        &self,
+        tx: &mut TransportSender,
+        targets: &[Ipv4Addr],
+        start: Instant,
+        done: &AtomicBool,
+    ) -> Result<u64, TracerouteError> {
+        let YarrpOptions {
+            min_ttl,
+            max_ttl,
+            packets_per_second,
+            ports,
+            ..
+        } = self.options;
+        let ttls = (max_ttl - min_ttl + 1) as u64;
+        let mut budget = TokenBucket::new(packets_per_second);
+        let mut sent = 0;
+
+        for index in Permutation::new(targets.len() as u64 * ttls) {
+            if done.load(Ordering::Relaxed) || self.shut_down.load(Ordering::Relaxed) {
+                break;
+            }
+            let target = targets[(index / ttls) as usize];
+            let ttl = min_ttl + (index % ttls) as TTL;
+
+            let wait = budget.wait(Instant::now());
+            if !wait.is_zero() {
+                thread::sleep(wait);
+            }
+            budget.take();
+
+            let stamp = ProbeStamp::new(ttl, start.elapsed().as_millis() as u32);
+            let (ip_id, checksum) = stamp.encode();
+            let packet = build_udp_probe(self.source, target, ttl, ip_id, ports, checksum)?;
+            let _ = tx.send_to(packet, IpAddr::V4(target))?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    fn receive<F: FnMut(YarrpRecord)>(
+        &self,
+        rx: &mut pnet::transport::TransportReceiver,
+        start: Instant,
+        done: &AtomicBool,
+        summary: &mut YarrpSummary,
+        on_record: &mut F,
+    ) -> Result<(), TracerouteError> {
+        let mut packet_iter = ipv4_packet_iter(rx);
+
+        while !done.load(Ordering::SeqCst) && !self.shut_down.load(Ordering::SeqCst) {
+            let packet = match packet_iter.next_with_timeout(RECEIVE_TIMEOUT)? {
+                Some((packet, _address)) => packet,
+                None => continue,
+            };
+            match self.decode(&packet, start) {
+                Some(record) => {
+                    summary.replies += 1;
+                    on_record(record);
+                }
+                None => summary.ignored += 1,
+            }
+        }
+        Ok(())
+    }
+
+    // Rebuild the record from the probe quoted in an ICMP error
+    fn decode(&self, packet: &Ipv4Packet, start: Instant) -> Option<YarrpRecord> {
+        if packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+            return None;
+        }
+        let icmp = IcmpPacket::new(packet.payload())?;
+        match icmp.get_icmp_type() {
+            IcmpTypes::TimeExceeded | IcmpTypes::DestinationUnreachable => (),
+            _ => return None,
+        }
+
+        // Skip the unused 4 bytes of the ICMP header
+        let quoted = Ipv4Packet::new(icmp.payload().get(4..)?)?;
+        if quoted.get_source() != self.source
+            || quoted.get_next_level_protocol() != IpNextHeaderProtocols::Udp
+        {
+            return None;
+        }
+        let udp = UdpPacket::new(quoted.payload())?;
+        if udp.get_source() != self.options.ports.source_port
+            || udp.get_destination() != self.options.ports.destination_port
+        {
+            return None;
+        }
+
+        let stamp = ProbeStamp::decode(quoted.get_identification(), udp.get_checksum());
+        let now = start.elapsed().as_millis() as u32;
+        let target = quoted.get_destination();
+        let hop = packet.get_source();
+
+        Some(YarrpRecord {
+            target,
+            ttl: stamp.ttl,
+            hop: IpAddr::V4(hop),
+            rtt: Duration::from_millis(stamp.elapsed(now).into()),
+            reached: hop == target,
+        })
+    }
+}